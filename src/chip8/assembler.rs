@@ -0,0 +1,713 @@
+use super::{
+    cpu,
+    opcode,
+    opcode::OpCode,
+};
+
+use std::{
+    collections::HashMap,
+    error,
+    fmt,
+};
+
+// Source format, one statement per line, ';' starts a comment:
+//   label:                 Names the address of the next statement
+//   NAME = expression      Constant, only refers to what is defined above it
+//   LD V3, 0x12            Instruction with the same mnemonics as the disassembler
+//   db 0x12, 34, 0b101     Bytes
+//   dw 0x1234, label       Big endian words
+//   sprite ##..##..        One sprite row of 8 or 16 pixels, '#' is lit
+// Expressions are numbers, labels and constants joined with '+' and '-'.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AssemblerError
+{
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AssemblerError
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl error::Error for AssemblerError {}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum TokenKind
+{
+    Identifier(String),
+    Number(i64),
+    Pixels(String),
+    Comma,
+    Colon,
+    OpenBracket,
+    CloseBracket,
+    Plus,
+    Minus,
+    Equals,
+}
+
+#[derive(Clone, Debug)]
+struct Token
+{
+    kind: TokenKind,
+    column: usize,
+}
+
+struct Location
+{
+    line: usize,
+    column: usize,
+}
+
+impl Location
+{
+    fn error(&self, message: String) -> AssemblerError
+    {
+        AssemblerError { line: self.line, column: self.column, message }
+    }
+}
+
+fn parse_number(text: &str) -> Option<i64>
+{
+    let lowercase = text.to_ascii_lowercase();
+
+    if let Some(digits) = lowercase.strip_prefix("0x") {
+        i64::from_str_radix(digits, 16).ok()
+    } else if let Some(digits) = lowercase.strip_prefix("0b") {
+        i64::from_str_radix(digits, 2).ok()
+    } else {
+        lowercase.parse::<i64>().ok()
+    }
+}
+
+fn tokenize(text: &str, line: usize) -> Result<Vec<Token>, AssemblerError>
+{
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = Vec::new();
+    let mut index = 0;
+
+    while index < chars.len() {
+        let c = chars[index];
+        let column = index + 1;
+
+        let word_end = |is_word_char: &dyn Fn(char) -> bool| {
+            (index..chars.len()).find(|&end| !is_word_char(chars[end])).unwrap_or(chars.len())
+        };
+
+        let (kind, end) = match c {
+            ';' => break,
+            _ if c.is_whitespace() => {
+                index += 1;
+                continue;
+            },
+            ',' => (TokenKind::Comma, index + 1),
+            ':' => (TokenKind::Colon, index + 1),
+            '[' => (TokenKind::OpenBracket, index + 1),
+            ']' => (TokenKind::CloseBracket, index + 1),
+            '+' => (TokenKind::Plus, index + 1),
+            '-' => (TokenKind::Minus, index + 1),
+            '=' => (TokenKind::Equals, index + 1),
+            '#' | '.' => {
+                let end = word_end(&|c| c == '#' || c == '.');
+                (TokenKind::Pixels(chars[index..end].iter().collect()), end)
+            },
+            _ if c.is_ascii_digit() => {
+                let end = word_end(&|c| c.is_ascii_alphanumeric());
+                let word: String = chars[index..end].iter().collect();
+                let number = parse_number(&word)
+                    .ok_or_else(|| Location { line, column }.error(format!("invalid number '{}'", word)))?;
+                (TokenKind::Number(number), end)
+            },
+            _ if c.is_ascii_alphabetic() || c == '_' => {
+                let end = word_end(&|c| c.is_ascii_alphanumeric() || c == '_');
+                (TokenKind::Identifier(chars[index..end].iter().collect()), end)
+            },
+            _ => return Err(Location { line, column }.error(format!("unexpected character '{}'", c))),
+        };
+
+        tokens.push(Token { kind, column });
+        index = end;
+    }
+
+    Ok(tokens)
+}
+
+// Sum of signed terms, each term is a number or a symbol
+struct Expression
+{
+    terms: Vec<(bool, Token)>, // Negated, term
+    column: usize,
+}
+
+enum Operand
+{
+    Register(u8),
+    Keyword(String), // I, DT, ST, K, F, HF, B, R
+    IndirectI, // [I]
+    Long(Expression),
+    Value(Expression),
+}
+
+const KEYWORDS: [&str; 8] = ["I", "DT", "ST", "K", "F", "HF", "B", "R"];
+
+fn parse_register(name: &str) -> Option<u8>
+{
+    let mut chars = name.chars();
+
+    match (chars.next(), chars.next(), chars.next()) {
+        (Some('V'), Some(digit), None) | (Some('v'), Some(digit), None) => digit.to_digit(16).map(|register| register as u8),
+        _ => None,
+    }
+}
+
+fn is_reserved_name(name: &str) -> bool
+{
+    parse_register(name).is_some() || KEYWORDS.contains(&name.to_ascii_uppercase().as_str()) || name.eq_ignore_ascii_case("long")
+}
+
+fn parse_expression(tokens: &[Token], line: usize, column: usize) -> Result<Expression, AssemblerError>
+{
+    let mut terms = Vec::new();
+    let mut is_negated = false;
+    let mut expects_term = true;
+
+    for token in tokens {
+        match (&token.kind, expects_term) {
+            (TokenKind::Number(_), true) | (TokenKind::Identifier(_), true) => {
+                terms.push((is_negated, token.clone()));
+                expects_term = false;
+            },
+            (TokenKind::Minus, true) if terms.is_empty() => is_negated = !is_negated,
+            (TokenKind::Plus, false) => {
+                is_negated = false;
+                expects_term = true;
+            },
+            (TokenKind::Minus, false) => {
+                is_negated = true;
+                expects_term = true;
+            },
+            _ => return Err(Location { line, column: token.column }.error("unexpected token in expression".to_string())),
+        }
+    }
+
+    if expects_term {
+        return Err(Location { line, column }.error("expected a value".to_string()));
+    }
+
+    Ok(Expression { terms, column })
+}
+
+fn parse_operand(tokens: &[Token], line: usize, column: usize) -> Result<Operand, AssemblerError>
+{
+    match tokens {
+        [] => Err(Location { line, column }.error("missing operand".to_string())),
+        [Token { kind: TokenKind::OpenBracket, .. }, Token { kind: TokenKind::Identifier(name), .. }, Token { kind: TokenKind::CloseBracket, .. }]
+            if name.eq_ignore_ascii_case("I") => Ok(Operand::IndirectI),
+        [Token { kind: TokenKind::Identifier(name), .. }] if parse_register(name).is_some() => Ok(Operand::Register(parse_register(name).unwrap())),
+        [Token { kind: TokenKind::Identifier(name), .. }] if KEYWORDS.contains(&name.to_ascii_uppercase().as_str()) => Ok(Operand::Keyword(name.to_ascii_uppercase())),
+        [Token { kind: TokenKind::Identifier(name), column }, rest @ ..] if name.eq_ignore_ascii_case("long") =>
+            Ok(Operand::Long(parse_expression(rest, line, rest.first().map_or(column + name.len(), |token| token.column))?)),
+        _ => Ok(Operand::Value(parse_expression(tokens, line, column)?)),
+    }
+}
+
+enum StatementKind
+{
+    Instruction { mnemonic: String, operands: Vec<(Operand, usize)> },
+    Bytes(Vec<Expression>),
+    Words(Vec<Expression>),
+    Sprite(Vec<u8>),
+}
+
+struct Statement
+{
+    kind: StatementKind,
+    address: usize,
+    line: usize,
+    column: usize,
+}
+
+impl Statement
+{
+    fn size(&self) -> usize
+    {
+        match &self.kind {
+            StatementKind::Instruction { mnemonic, operands } => {
+                let is_long = mnemonic == "LD" && operands.iter().any(|(operand, _)| matches!(operand, Operand::Long(_)));
+                if is_long { 4 } else { 2 }
+            },
+            StatementKind::Bytes(values) => values.len(),
+            StatementKind::Words(values) => values.len() * 2,
+            StatementKind::Sprite(bytes) => bytes.len(),
+        }
+    }
+}
+
+// Splits on commas, keeping the column of each group
+fn split_operands(tokens: &[Token], column: usize) -> Vec<(&[Token], usize)>
+{
+    if tokens.is_empty() {
+        return Vec::new();
+    }
+
+    let mut groups = Vec::new();
+    let mut group_begin = 0;
+    let mut group_column = tokens[0].column;
+
+    for (index, token) in tokens.iter().enumerate() {
+        if token.kind == TokenKind::Comma {
+            groups.push((&tokens[group_begin..index], group_column));
+            group_begin = index + 1;
+            group_column = tokens.get(index + 1).map_or(token.column + 1, |next| next.column);
+        }
+    }
+
+    groups.push((&tokens[group_begin..], if group_begin < tokens.len() { group_column } else { column }));
+
+    groups
+}
+
+fn parse_sprite_row(pixels: &str, location: Location) -> Result<Vec<u8>, AssemblerError>
+{
+    if pixels.len() != 8 && pixels.len() != 16 {
+        return Err(location.error(format!("sprite rows are 8 or 16 pixels wide, not {}", pixels.len())));
+    }
+
+    // Leftmost pixel in the highest bit, as Dxyn reads them
+    Ok(pixels.as_bytes().chunks(8)
+        .map(|chunk| chunk.iter().fold(0, |byte, &pixel| byte << 1 | (pixel == b'#') as u8))
+        .collect())
+}
+
+struct Assembler
+{
+    symbols: HashMap<String, i64>,
+    statements: Vec<Statement>,
+    address: usize,
+}
+
+impl Assembler
+{
+    fn evaluate(&self, expression: &Expression, line: usize) -> Result<i64, AssemblerError>
+    {
+        let mut value: i64 = 0;
+
+        for (is_negated, token) in expression.terms.iter() {
+            let term = match &token.kind {
+                TokenKind::Number(number) => *number,
+                TokenKind::Identifier(name) => *self.symbols.get(name)
+                    .ok_or_else(|| Location { line, column: token.column }.error(format!("undefined symbol '{}'", name)))?,
+                _ => unreachable!(),
+            };
+
+            value = if *is_negated { value.checked_sub(term) } else { value.checked_add(term) }
+                .ok_or_else(|| Location { line, column: token.column }.error("expression overflows".to_string()))?;
+        }
+
+        Ok(value)
+    }
+
+    fn define_symbol(&mut self, name: &str, value: i64, location: Location) -> Result<(), AssemblerError>
+    {
+        if is_reserved_name(name) {
+            return Err(location.error(format!("'{}' is a reserved name", name)));
+        }
+
+        if self.symbols.insert(name.to_string(), value).is_some() {
+            return Err(location.error(format!("'{}' is already defined", name)));
+        }
+
+        Ok(())
+    }
+
+    // First pass, collects the statements and the address of every label
+    fn parse_line(&mut self, text: &str, line: usize) -> Result<(), AssemblerError>
+    {
+        let tokens = tokenize(text, line)?;
+        let mut tokens = &tokens[..];
+
+        // Constant definition
+        if let [Token { kind: TokenKind::Identifier(name), column }, Token { kind: TokenKind::Equals, column: equals_column }, rest @ ..] = tokens {
+            let expression = parse_expression(rest, line, equals_column + 1)?;
+            let value = self.evaluate(&expression, line)?;
+
+            return self.define_symbol(name, value, Location { line, column: *column });
+        }
+
+        // Label definition, may be followed by a statement
+        if let [Token { kind: TokenKind::Identifier(name), column }, Token { kind: TokenKind::Colon, .. }, rest @ ..] = tokens {
+            self.define_symbol(name, self.address as i64, Location { line, column: *column })?;
+            tokens = rest;
+        }
+
+        let (name, column, rest) = match tokens {
+            [] => return Ok(()),
+            [Token { kind: TokenKind::Identifier(name), column }, rest @ ..] => (name.to_ascii_uppercase(), *column, rest),
+            [token, ..] => return Err(Location { line, column: token.column }.error("expected an instruction or directive".to_string())),
+        };
+
+        let end_column = column + name.len();
+
+        let kind = match name.as_str() {
+            "DB" | "DW" => {
+                let values = split_operands(rest, end_column + 1).into_iter()
+                    .map(|(group, group_column)| parse_expression(group, line, group_column))
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                if values.is_empty() {
+                    return Err(Location { line, column: end_column + 1 }.error("expected a value".to_string()));
+                }
+
+                if name == "DB" { StatementKind::Bytes(values) } else { StatementKind::Words(values) }
+            },
+            "SPRITE" => match rest {
+                [Token { kind: TokenKind::Pixels(pixels), column }] => StatementKind::Sprite(parse_sprite_row(pixels, Location { line, column: *column })?),
+                _ => return Err(Location { line, column: end_column + 1 }.error("expected a sprite row such as '##..##..'".to_string())),
+            },
+            _ => {
+                let operands = split_operands(rest, end_column + 1).into_iter()
+                    .map(|(group, group_column)| parse_operand(group, line, group_column).map(|operand| (operand, group_column)))
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                StatementKind::Instruction { mnemonic: name, operands }
+            },
+        };
+
+        let statement = Statement { kind, address: self.address, line, column };
+
+        self.address += statement.size();
+        self.statements.push(statement);
+
+        Ok(())
+    }
+
+    fn evaluate_in_range(&self, expression: &Expression, line: usize, min: i64, max: i64, what: &str) -> Result<i64, AssemblerError>
+    {
+        let value = self.evaluate(expression, line)?;
+        let location = Location { line, column: expression.column };
+
+        if value < min {
+            return Err(location.error(format!("{} {} out of range, {} at least", what, value, min)));
+        } else if value > max {
+            return Err(location.error(format!("{} 0x{:X} out of range, 0x{:X} at most", what, value, max)));
+        }
+
+        Ok(value)
+    }
+
+    fn encode_instruction(&self, statement: &Statement, mnemonic: &str, operands: &[(Operand, usize)]) -> Result<Vec<u8>, AssemblerError>
+    {
+        let line = statement.line;
+        let address = |expression: &Expression| self.evaluate_in_range(expression, line, 0, 0x0FFF, "address").map(|value| value as u16);
+        let byte = |expression: &Expression| self.evaluate_in_range(expression, line, 0, 0xFF, "byte").map(|value| value as u8);
+        let nibble = |expression: &Expression| self.evaluate_in_range(expression, line, 0, 0xF, "nibble").map(|value| value as u8);
+
+        let kinds: Vec<&Operand> = operands.iter().map(|(operand, _)| operand).collect();
+        let is_keyword = |operand: &Operand, name: &str| matches!(operand, Operand::Keyword(keyword) if keyword == name);
+
+        let opcode = match (mnemonic, &kinds[..]) {
+            ("CLS", []) => OpCode::CLS,
+            ("RET", []) => OpCode::RET,
+            ("SCR", []) => OpCode::SCR,
+            ("SCL", []) => OpCode::SCL,
+            ("EXIT", []) => OpCode::EXIT,
+            ("LOW", []) => OpCode::LOW,
+            ("HIGH", []) => OpCode::HIGH,
+            ("AUDIO", []) => OpCode::AUDIO,
+            ("SYS", [Operand::Value(addr)]) => OpCode::SYS { addr: address(addr)? },
+            ("JP", [Operand::Value(addr)]) => OpCode::JP { addr: address(addr)? },
+            ("JP", [Operand::Register(0), Operand::Value(addr)]) => OpCode::JP2 { addr: address(addr)? },
+            ("CALL", [Operand::Value(addr)]) => OpCode::CALL { addr: address(addr)? },
+            ("SE", [Operand::Register(reg), Operand::Value(value)]) => OpCode::SE { reg: *reg, value: byte(value)? },
+            ("SE", [Operand::Register(reg_x), Operand::Register(reg_y)]) => OpCode::SE2 { reg_x: *reg_x, reg_y: *reg_y },
+            ("SNE", [Operand::Register(reg), Operand::Value(value)]) => OpCode::SNE { reg: *reg, value: byte(value)? },
+            ("SNE", [Operand::Register(reg_x), Operand::Register(reg_y)]) => OpCode::SNE2 { reg_x: *reg_x, reg_y: *reg_y },
+            ("LD", [Operand::Register(reg), Operand::Value(value)]) => OpCode::LD { reg: *reg, value: byte(value)? },
+            ("LD", [Operand::Register(reg_x), Operand::Register(reg_y)]) => OpCode::LD2 { reg_x: *reg_x, reg_y: *reg_y },
+            ("LD", [i, Operand::Value(addr)]) if is_keyword(i, "I") => OpCode::LDI { addr: address(addr)? },
+            ("LD", [i, Operand::Long(_)]) if is_keyword(i, "I") => OpCode::LDIL,
+            ("LD", [Operand::Register(reg), dt]) if is_keyword(dt, "DT") => OpCode::LDT { reg: *reg },
+            ("LD", [Operand::Register(reg), k]) if is_keyword(k, "K") => OpCode::LDK { reg: *reg },
+            ("LD", [Operand::Register(reg), r]) if is_keyword(r, "R") => OpCode::LDVR { reg: *reg },
+            ("LD", [Operand::Register(reg), Operand::IndirectI]) => OpCode::LDM { reg: *reg },
+            ("LD", [Operand::IndirectI, Operand::Register(reg)]) => OpCode::LDAI { reg: *reg },
+            ("LD", [Operand::Keyword(keyword), Operand::Register(reg)]) => match keyword.as_str() {
+                "DT" => OpCode::LDDT { reg: *reg },
+                "ST" => OpCode::LDST { reg: *reg },
+                "F" => OpCode::LDF { reg: *reg },
+                "HF" => OpCode::LDHF { reg: *reg },
+                "B" => OpCode::LDB { reg: *reg },
+                "R" => OpCode::LDR { reg: *reg },
+                _ => return Err(Location { line, column: statement.column }.error("invalid operands for 'LD'".to_string())),
+            },
+            ("ADD", [Operand::Register(reg), Operand::Value(value)]) => OpCode::ADD { reg: *reg, value: byte(value)? },
+            ("ADD", [Operand::Register(reg_x), Operand::Register(reg_y)]) => OpCode::ADD2 { reg_x: *reg_x, reg_y: *reg_y },
+            ("ADD", [i, Operand::Register(reg)]) if is_keyword(i, "I") => OpCode::ADDI { reg: *reg },
+            ("OR", [Operand::Register(reg_x), Operand::Register(reg_y)]) => OpCode::OR { reg_x: *reg_x, reg_y: *reg_y },
+            ("AND", [Operand::Register(reg_x), Operand::Register(reg_y)]) => OpCode::AND { reg_x: *reg_x, reg_y: *reg_y },
+            ("XOR", [Operand::Register(reg_x), Operand::Register(reg_y)]) => OpCode::XOR { reg_x: *reg_x, reg_y: *reg_y },
+            ("SUB", [Operand::Register(reg_x), Operand::Register(reg_y)]) => OpCode::SUB { reg_x: *reg_x, reg_y: *reg_y },
+            ("SUBN", [Operand::Register(reg_x), Operand::Register(reg_y)]) => OpCode::SUBN { reg_x: *reg_x, reg_y: *reg_y },
+            // Vy only matters with the shift quirk, it defaults to Vx
+            ("SHR", [Operand::Register(reg_x)]) => OpCode::SHR { reg_x: *reg_x, reg_y: *reg_x },
+            ("SHR", [Operand::Register(reg_x), Operand::Register(reg_y)]) => OpCode::SHR { reg_x: *reg_x, reg_y: *reg_y },
+            ("SHL", [Operand::Register(reg_x)]) => OpCode::SHL { reg_x: *reg_x, reg_y: *reg_x },
+            ("SHL", [Operand::Register(reg_x), Operand::Register(reg_y)]) => OpCode::SHL { reg_x: *reg_x, reg_y: *reg_y },
+            ("RND", [Operand::Register(reg), Operand::Value(value)]) => OpCode::RND { reg: *reg, value: byte(value)? },
+            ("DRW", [Operand::Register(reg_x), Operand::Register(reg_y), Operand::Value(size)]) =>
+                OpCode::DRW { reg_x: *reg_x, reg_y: *reg_y, size: nibble(size)? },
+            ("SKP", [Operand::Register(reg)]) => OpCode::SKP { reg: *reg },
+            ("SKNP", [Operand::Register(reg)]) => OpCode::SKNP { reg: *reg },
+            ("SCD", [Operand::Value(size)]) => OpCode::SCD { size: nibble(size)? },
+            ("SCU", [Operand::Value(size)]) => OpCode::SCU { size: nibble(size)? },
+            ("SAVE", [Operand::Register(reg_x), Operand::Register(reg_y)]) => OpCode::SAVE { reg_x: *reg_x, reg_y: *reg_y },
+            ("LOAD", [Operand::Register(reg_x), Operand::Register(reg_y)]) => OpCode::LOAD { reg_x: *reg_x, reg_y: *reg_y },
+            ("PLANE", [Operand::Value(mask)]) => OpCode::PLANE { mask: self.evaluate_in_range(mask, line, 0, 0x3, "plane mask")? as u8 },
+            ("PITCH", [Operand::Register(reg)]) => OpCode::PITCH { reg: *reg },
+            _ if MNEMONICS.contains(&mnemonic) =>
+                return Err(Location { line, column: statement.column }.error(format!("invalid operands for '{}'", mnemonic))),
+            _ => return Err(Location { line, column: statement.column }.error(format!("unknown instruction '{}'", mnemonic))),
+        };
+
+        let mut bytes = opcode::encode_instruction(&opcode).to_be_bytes().to_vec();
+
+        if let (OpCode::LDIL, [_, (Operand::Long(addr), _)]) = (opcode, operands) {
+            let addr = self.evaluate_in_range(addr, line, 0, 0xFFFF, "address")? as u16;
+            bytes.extend_from_slice(&addr.to_be_bytes());
+        }
+
+        Ok(bytes)
+    }
+
+    // Second pass, every symbol is known by now
+    fn encode(&self) -> Result<Vec<u8>, AssemblerError>
+    {
+        let mut program = Vec::new();
+
+        for statement in self.statements.iter() {
+            let line = statement.line;
+
+            match &statement.kind {
+                StatementKind::Instruction { mnemonic, operands } => {
+                    program.extend(self.encode_instruction(statement, mnemonic, operands)?);
+                },
+                StatementKind::Bytes(values) => {
+                    for value in values {
+                        // Negative bytes are accepted as two's complement
+                        let value = self.evaluate_in_range(value, line, -0x80, 0xFF, "byte")?;
+                        program.push(value as u8);
+                    }
+                },
+                StatementKind::Words(values) => {
+                    for value in values {
+                        let value = self.evaluate_in_range(value, line, 0, 0xFFFF, "word")? as u16;
+                        program.extend_from_slice(&value.to_be_bytes());
+                    }
+                },
+                StatementKind::Sprite(bytes) => program.extend_from_slice(bytes),
+            }
+
+            debug_assert_eq!(program.len(), statement.address - cpu::MIN_PROGRAM_ADDRESS + statement.size());
+        }
+
+        Ok(program)
+    }
+}
+
+const MNEMONICS: [&str; 32] = [
+    "CLS", "RET", "SCR", "SCL", "EXIT", "LOW", "HIGH", "AUDIO", "SYS", "JP", "CALL", "SE", "SNE", "LD", "ADD", "OR",
+    "AND", "XOR", "SUB", "SUBN", "SHR", "SHL", "RND", "DRW", "SKP", "SKNP", "SCD", "SCU", "SAVE", "LOAD", "PLANE", "PITCH",
+];
+
+// Produces a program to be loaded at the usual address, see load_program.
+pub fn assemble(source: &str) -> Result<Vec<u8>, AssemblerError>
+{
+    let mut assembler = Assembler {
+        symbols: HashMap::new(),
+        statements: Vec::new(),
+        address: cpu::MIN_PROGRAM_ADDRESS,
+    };
+
+    for (line_index, text) in source.lines().enumerate() {
+        assembler.parse_line(text, line_index + 1)?;
+    }
+
+    assembler.encode()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::{
+        config::Platform,
+        disassembler,
+        display,
+        execution,
+    };
+
+    #[test]
+    fn assembler() {
+        let program = assemble("
+            ; Draws the sprite below
+            X = 8
+            start:
+                LD V0, X
+                ld v1, X - 4        ; Case does not matter
+                LD I, sprite
+                DRW V0, V1, sprite_end - sprite
+            loop: JP loop
+            sprite:
+                sprite #..##..#
+                sprite .######.
+            sprite_end:
+                db 1, 0x02, 0b11, -1
+                dw 0x1234, start
+        ").unwrap();
+
+        assert_eq!(program, [
+            0x60, 0x08,
+            0x61, 0x04,
+            0xA2, 0x0A,
+            0xD0, 0x12,
+            0x12, 0x08,
+            0x99, 0x7E,
+            0x01, 0x02, 0x03, 0xFF,
+            0x12, 0x34, 0x02, 0x00,
+        ]);
+
+        // SUBCASE: every instruction form
+        {
+            let program = assemble("
+                CLS
+                RET
+                SYS 0x123
+                JP V0, 0x300
+                CALL 0x400
+                SE V1, 0x10
+                SE V1, V2
+                SNE V1, 0x10
+                SNE V1, V2
+                LD V1, V2
+                LD VA, DT
+                LD VA, K
+                LD DT, VA
+                LD ST, VA
+                LD F, VA
+                LD B, VA
+                LD [I], VA
+                LD VA, [I]
+                LD HF, VA
+                LD R, VA
+                LD VA, R
+                ADD V1, 0x10
+                ADD V1, V2
+                ADD I, V3
+                OR V1, V2
+                AND V1, V2
+                XOR V1, V2
+                SUB V1, V2
+                SUBN V1, V2
+                SHR V1
+                SHL V1, V2
+                RND V4, 0x0F
+                SKP V5
+                SKNP V5
+                SCD 4
+                SCR
+                SCL
+                EXIT
+                LOW
+                HIGH
+                SCU 2
+                SAVE V1, V4
+                LOAD V1, V4
+                LD I, long 0x1234
+                PLANE 3
+                AUDIO
+                PITCH V6
+            ").unwrap();
+
+            let words: Vec<u16> = program.chunks(2).map(|word| u16::from(word[0]) << 8 | u16::from(word[1])).collect();
+
+            assert_eq!(words, [
+                0x00E0, 0x00EE, 0x0123, 0xB300, 0x2400, 0x3110, 0x5120, 0x4110, 0x9120, 0x8120,
+                0xFA07, 0xFA0A, 0xFA15, 0xFA18, 0xFA29, 0xFA33, 0xFA55, 0xFA65, 0xFA30, 0xFA75,
+                0xFA85, 0x7110, 0x8124, 0xF31E, 0x8121, 0x8122, 0x8123, 0x8125, 0x8127, 0x8116,
+                0x812E, 0xC40F, 0xE59E, 0xE5A1, 0x00C4, 0x00FB, 0x00FC, 0x00FD, 0x00FE, 0x00FF,
+                0x00D2, 0x5142, 0x5143, 0xF000, 0x1234, 0xF301, 0xF002, 0xF63A,
+            ]);
+        }
+
+        // SUBCASE: errors
+        {
+            let error = |source: &str| assemble(source).unwrap_err();
+
+            assert_eq!(error("  FOO V1"), AssemblerError { line: 1, column: 3, message: "unknown instruction 'FOO'".to_string() });
+            assert_eq!(error("CLS\n  LD V1, V2, V3"), AssemblerError { line: 2, column: 3, message: "invalid operands for 'LD'".to_string() });
+            assert_eq!(error("LD V1, 0x100"), AssemblerError { line: 1, column: 8, message: "byte 0x100 out of range, 0xFF at most".to_string() });
+            assert_eq!(error("JP nowhere"), AssemblerError { line: 1, column: 4, message: "undefined symbol 'nowhere'".to_string() });
+            assert_eq!(error("a:\na:"), AssemblerError { line: 2, column: 1, message: "'a' is already defined".to_string() });
+            assert_eq!(error("V1: CLS"), AssemblerError { line: 1, column: 1, message: "'V1' is a reserved name".to_string() });
+            assert_eq!(error("sprite ##"), AssemblerError { line: 1, column: 8, message: "sprite rows are 8 or 16 pixels wide, not 2".to_string() });
+            assert_eq!(error("LD V1, 0xZZ"), AssemblerError { line: 1, column: 8, message: "invalid number '0xZZ'".to_string() });
+            assert_eq!(error("LD V1, @"), AssemblerError { line: 1, column: 8, message: "unexpected character '@'".to_string() });
+            assert_eq!(error("LD V0, 0x7FFFFFFFFFFFFFFF + 1"), AssemblerError { line: 1, column: 29, message: "expression overflows".to_string() });
+            assert_eq!(error("LD V0, 0 - 0x7FFFFFFFFFFFFFFF - 2"), AssemblerError { line: 1, column: 33, message: "expression overflows".to_string() });
+            assert_eq!(error("DRW V1, V2, 16").to_string(), "1:13: nibble 0x10 out of range, 0xF at most");
+        }
+
+        // SUBCASE: round trip through the disassembler
+        {
+            let program = assemble("
+                LD I, long sprite
+                CALL draw
+                halt: JP halt
+                draw:
+                    SE V0, 0x01
+                    LD I, long sprite
+                    DRW V0, V1, 0
+                    RET
+                sprite:
+                    sprite ################
+                    db 0xAA, 0xBB, 0xCC
+            ").unwrap();
+
+            let lines = disassembler::disassemble(&program, Platform::XoChip);
+
+            assert_eq!(assemble(&disassembler::format_source(&lines)).unwrap(), program);
+        }
+
+        // SUBCASE: round trip with targets inside an instruction
+        {
+            let program = assemble("
+                LD I, patch + 1
+                patch: LD V0, 0x05
+                LD I, long operand + 2
+                operand: LD I, long 0x1234
+                halt: JP halt
+            ").unwrap();
+
+            let lines = disassembler::disassemble(&program, Platform::XoChip);
+
+            assert_eq!(assemble(&disassembler::format_source(&lines)).unwrap(), program);
+        }
+
+        // SUBCASE: snippets run as is
+        {
+            let mut state = cpu::create_chip8_state();
+
+            execution::load_program(&mut state, assemble("LD V0, 0x0A\nLD F, V0\nDRW V1, V1, 5").unwrap()).unwrap();
+
+            for _ in 0..3 {
+                execution::execute_step(&mut state, cpu::INSTRUCTION_EXECUTION_PERIOD_MS).unwrap();
+            }
+
+            assert!(display::read_screen_pixel(&state, 0, 0));
+        }
+    }
+}
@@ -0,0 +1,154 @@
+use super::cpu::CPUState;
+
+use std::f32::consts::PI;
+
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Waveform
+{
+    #[default]
+    Square,
+    Triangle,
+    Sawtooth,
+    Sine,
+}
+
+pub const WAVEFORM_NAMES: [&str; 4] = ["square", "triangle", "sawtooth", "sine"];
+
+impl Waveform
+{
+    pub fn from_name(name: &str) -> Option<Waveform>
+    {
+        match name {
+            "square" => Some(Waveform::Square),
+            "triangle" => Some(Waveform::Triangle),
+            "sawtooth" => Some(Waveform::Sawtooth),
+            "sine" => Some(Waveform::Sine),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AudioConfig
+{
+    pub frequency: f32, // Hz
+    pub volume: f32, // 0.0 to 1.0
+    pub waveform: Waveform,
+}
+
+impl Default for AudioConfig
+{
+    fn default() -> Self
+    {
+        AudioConfig {
+            frequency: 440.0,
+            volume: 0.25,
+            waveform: Waveform::Square,
+        }
+    }
+}
+
+// The buzzer sounds for as long as the sound timer is non-zero.
+pub fn is_sound_playing(state: &CPUState) -> bool
+{
+    state.sound_timer > 0
+}
+
+// Produces mono PCM samples in the [-volume, volume] range, independently of any audio backend.
+pub struct ToneGenerator
+{
+    config: AudioConfig,
+    sample_rate: u32,
+    phase: f32, // In periods, from 0.0 to 1.0
+}
+
+impl ToneGenerator
+{
+    pub fn new(config: AudioConfig, sample_rate: u32) -> ToneGenerator
+    {
+        assert!(sample_rate > 0);
+
+        ToneGenerator {
+            config,
+            sample_rate,
+            phase: 0.0,
+        }
+    }
+
+    // Silence is written while not playing, and the tone restarts from the beginning of its period.
+    pub fn generate(&mut self, is_playing: bool, samples: &mut [f32])
+    {
+        if !is_playing {
+            self.phase = 0.0;
+
+            for sample in samples.iter_mut() {
+                *sample = 0.0;
+            }
+
+            return;
+        }
+
+        let phase_increment = self.config.frequency / self.sample_rate as f32;
+
+        for sample in samples.iter_mut() {
+            let amplitude = match self.config.waveform {
+                Waveform::Square => if self.phase < 0.5 { 1.0 } else { -1.0 },
+                Waveform::Triangle => 1.0 - 4.0 * (self.phase - 0.5).abs(),
+                Waveform::Sawtooth => 2.0 * self.phase - 1.0,
+                Waveform::Sine => (2.0 * PI * self.phase).sin(),
+            };
+
+            *sample = amplitude * self.config.volume;
+
+            self.phase = (self.phase + phase_increment).fract();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::{
+        cpu,
+        execution,
+    };
+
+    #[test]
+    fn sound_timer() {
+        let mut state = cpu::create_chip8_state();
+
+        state.sound_timer = 2;
+
+        assert!(is_sound_playing(&state));
+
+        execution::execute_step(&mut state, cpu::DELAY_TIMER_PERIOD_MS).unwrap();
+
+        assert_eq!(state.sound_timer, 1);
+
+        execution::execute_step(&mut state, cpu::DELAY_TIMER_PERIOD_MS * 4).unwrap();
+
+        assert_eq!(state.sound_timer, 0);
+        assert!(!is_sound_playing(&state));
+    }
+
+    #[test]
+    fn tone_generator() {
+        let config = AudioConfig {
+            frequency: 1000.0,
+            volume: 0.5,
+            waveform: Waveform::Square,
+        };
+        let mut generator = ToneGenerator::new(config, 8000);
+        let mut samples = [1.0; 16];
+
+        generator.generate(false, &mut samples);
+
+        assert!(samples.iter().all(|&sample| sample == 0.0));
+
+        // 8 samples per period, half of them high
+        generator.generate(true, &mut samples);
+
+        assert_eq!(samples[0..8], [0.5, 0.5, 0.5, 0.5, -0.5, -0.5, -0.5, -0.5]);
+        assert_eq!(samples[0..8], samples[8..16]);
+    }
+}
@@ -1,3 +1,8 @@
+use super::{
+    audio::AudioConfig,
+    rewind::RewindConfig,
+};
+
 #[derive(Default)]
 pub struct Color
 {
@@ -6,11 +11,28 @@ pub struct Color
     pub b: f32,
 }
 
+// XO-CHIP draws with two bitplanes, each pixel picks one of four colours.
 #[derive(Default)]
 pub struct Palette
 {
-    pub primary: Color,
-    pub secondary: Color,
+    pub primary: Color, // First plane
+    pub secondary: Color, // Background
+    pub tertiary: Color, // Second plane
+    pub quaternary: Color, // Both planes
+}
+
+impl Palette
+{
+    // Bit N of the color index is set when the pixel is lit on plane N.
+    pub fn color(&self, color_index: u8) -> &Color
+    {
+        match color_index & 0x3 {
+            0 => &self.secondary,
+            1 => &self.primary,
+            2 => &self.tertiary,
+            _ => &self.quaternary,
+        }
+    }
 }
 
 #[derive(Default)]
@@ -19,4 +41,220 @@ pub struct EmuConfig
     pub debug_mode: bool,
     pub palette: Palette,
     pub screen_scale: u32,
+    pub screenshot_scale: u32, // Screenshots grow every pixel to a square this wide, 0 counts as 1
+    pub audio: AudioConfig,
+    pub rewind: RewindConfig,
+    pub platform: Platform,
+    pub quirks: Quirks,
+}
+
+// Instruction set and machine flavour being emulated.
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Platform
+{
+    #[default]
+    Chip8,
+    SuperChip, // SUPER-CHIP 1.1
+    XoChip,
+}
+
+pub const PLATFORM_NAMES: [&str; 3] = ["chip8", "schip", "xochip"];
+
+impl Platform
+{
+    pub fn from_name(name: &str) -> Option<Platform>
+    {
+        match name {
+            "chip8" => Some(Platform::Chip8),
+            "schip" => Some(Platform::SuperChip),
+            "xochip" => Some(Platform::XoChip),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str
+    {
+        match self {
+            Platform::Chip8 => "chip8",
+            Platform::SuperChip => "schip",
+            Platform::XoChip => "xochip",
+        }
+    }
+}
+
+// How far Fx55/Fx65 move I once the registers are transferred.
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IndexIncrement
+{
+    #[default]
+    Unchanged,
+    ByX, // CHIP-48 and SUPER-CHIP 1.0 off-by-one
+    ByXPlusOne, // COSMAC VIP
+}
+
+// Instruction timing, not tied to the profiles since it changes how fast every game runs.
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TimingMode
+{
+    #[default]
+    Fixed, // Every instruction takes INSTRUCTION_EXECUTION_PERIOD_MS
+    CosmacVip, // Every instruction takes its COSMAC VIP machine cycles, see timing.rs
+}
+
+pub const TIMING_MODE_NAMES: [&str; 2] = ["fixed", "vip"];
+
+impl TimingMode
+{
+    pub fn from_name(name: &str) -> Option<TimingMode>
+    {
+        match name {
+            "fixed" => Some(TimingMode::Fixed),
+            "vip" => Some(TimingMode::CosmacVip),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str
+    {
+        match self {
+            TimingMode::Fixed => "fixed",
+            TimingMode::CosmacVip => "vip",
+        }
+    }
+}
+
+// Source of the random numbers returned by Cxkk.
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RandomMode
+{
+    #[default]
+    Modern, // SplitMix64
+    Lfsr, // 8-bit maximal-length LFSR, not modelled on any particular interpreter
+}
+
+pub const RANDOM_MODE_NAMES: [&str; 2] = ["modern", "lfsr"];
+
+impl RandomMode
+{
+    pub fn from_name(name: &str) -> Option<RandomMode>
+    {
+        match name {
+            "modern" => Some(RandomMode::Modern),
+            "lfsr" => Some(RandomMode::Lfsr),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str
+    {
+        match self {
+            RandomMode::Modern => "modern",
+            RandomMode::Lfsr => "lfsr",
+        }
+    }
+}
+
+// Behaviours that differ between CHIP-8 interpreters.
+// The default leaves every quirk disabled, which is how this emulator always behaved.
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Quirks
+{
+    pub shift_uses_vy: bool, // 8xy6/8xyE shift Vy and store the result in Vx
+    pub load_store_increment: IndexIncrement, // Fx55/Fx65 move I past the registers
+    pub jump_uses_vx: bool, // Bxnn jumps to xnn + Vx instead of xnn + V0
+    pub logic_resets_vf: bool, // 8xy1/8xy2/8xy3 set VF to 0
+    pub clip_sprites: bool, // Dxyn clips sprites at the screen edges instead of wrapping them
+    pub display_wait: bool, // Dxyn stops execution until the next 60 Hz frame
+    pub random_mode: RandomMode, // Cxkk random number generator
+    pub timing: TimingMode, // Time taken by each instruction
+    pub instructions_per_frame: Option<u32>, // Runs whole 60 Hz frames of this many instructions instead of one every INSTRUCTION_EXECUTION_PERIOD_MS
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QuirksProfile
+{
+    CosmacVip,
+    Chip48,
+    SuperChip,
+    XoChip,
+}
+
+pub const QUIRKS_PROFILE_NAMES: [&str; 4] = ["vip", "chip48", "schip", "xochip"];
+
+impl QuirksProfile
+{
+    pub fn from_name(name: &str) -> Option<QuirksProfile>
+    {
+        match name {
+            "vip" => Some(QuirksProfile::CosmacVip),
+            "chip48" => Some(QuirksProfile::Chip48),
+            "schip" => Some(QuirksProfile::SuperChip),
+            "xochip" => Some(QuirksProfile::XoChip),
+            _ => None,
+        }
+    }
+}
+
+impl Quirks
+{
+    // Quirks used when no profile is explicitly selected.
+    pub fn for_platform(platform: Platform) -> Quirks
+    {
+        match platform {
+            Platform::Chip8 => Quirks::default(),
+            Platform::SuperChip => Quirks::from_profile(QuirksProfile::SuperChip),
+            Platform::XoChip => Quirks::from_profile(QuirksProfile::XoChip),
+        }
+    }
+
+    pub fn from_profile(profile: QuirksProfile) -> Quirks
+    {
+        match profile {
+            QuirksProfile::CosmacVip => Quirks {
+                shift_uses_vy: true,
+                load_store_increment: IndexIncrement::ByXPlusOne,
+                jump_uses_vx: false,
+                logic_resets_vf: true,
+                clip_sprites: true,
+                display_wait: true,
+                // The VIP interpreter's own Cxkk routine isn't emulated yet
+                random_mode: RandomMode::Modern,
+                timing: TimingMode::Fixed,
+                instructions_per_frame: None,
+            },
+            QuirksProfile::Chip48 => Quirks {
+                shift_uses_vy: false,
+                load_store_increment: IndexIncrement::ByX,
+                jump_uses_vx: true,
+                logic_resets_vf: false,
+                clip_sprites: true,
+                display_wait: false,
+                random_mode: RandomMode::Modern,
+                timing: TimingMode::Fixed,
+                instructions_per_frame: None,
+            },
+            QuirksProfile::SuperChip => Quirks {
+                shift_uses_vy: false,
+                load_store_increment: IndexIncrement::Unchanged,
+                jump_uses_vx: true,
+                logic_resets_vf: false,
+                clip_sprites: true,
+                display_wait: false,
+                random_mode: RandomMode::Modern,
+                timing: TimingMode::Fixed,
+                instructions_per_frame: None,
+            },
+            QuirksProfile::XoChip => Quirks {
+                shift_uses_vy: true,
+                load_store_increment: IndexIncrement::ByXPlusOne,
+                jump_uses_vx: false,
+                logic_resets_vf: false,
+                clip_sprites: false,
+                display_wait: false,
+                random_mode: RandomMode::Modern,
+                timing: TimingMode::Fixed,
+                instructions_per_frame: None,
+            },
+        }
+    }
 }
@@ -1,11 +1,32 @@
+use super::{
+    config::{
+        Platform,
+        Quirks,
+    },
+    trace,
+};
+
 pub const V_REGISTER_COUNT: usize = 16;
 pub const STACK_SIZE: usize = 16;
 pub const MEMORY_SIZE_IN_BYTES: usize = 0x1000;
+pub const XO_CHIP_MEMORY_SIZE_IN_BYTES: usize = 0x10000;
 
 // Display
 pub const SCREEN_WIDTH: usize = 64;
 pub const SCREEN_HEIGHT: usize = 32;
 pub const SCREEN_LINE_SIZE_IN_BYTES: usize = SCREEN_WIDTH / 8;
+pub const HIRES_SCREEN_WIDTH: usize = 128;
+pub const HIRES_SCREEN_HEIGHT: usize = 64;
+pub const SCREEN_PLANE_COUNT: usize = 2;
+
+// SUPER-CHIP persistent storage (HP-48 RPL user flags)
+// NOTE: SUPER-CHIP only exposes the first 8 flags, XO-CHIP all of them.
+pub const RPL_FLAG_COUNT: usize = 16;
+pub const SUPER_CHIP_RPL_FLAG_COUNT: usize = 8;
+
+// XO-CHIP audio
+pub const AUDIO_PATTERN_SIZE_IN_BYTES: usize = 16;
+pub const DEFAULT_AUDIO_PITCH: u8 = 64;
 
 // Memory
 pub const MIN_PROGRAM_ADDRESS: usize = 0x0200;
@@ -20,6 +41,7 @@ pub const INSTRUCTION_EXECUTION_PERIOD_MS: u32 = 1000 / INSTRUCTION_EXECUTION_FR
 // Fonts
 const FONT_TABLE_GLYPH_COUNT: usize = 16;
 const GLYPH_SIZE_IN_BYTES: usize = 5;
+const BIG_GLYPH_SIZE_IN_BYTES: usize = 10;
 
 #[allow(dead_code)]
 pub enum VRegisterName
@@ -45,6 +67,8 @@ pub struct CPUState
     // Implementation detail
     pub delay_timer_accumulator: u32,
     pub execution_timer_accumulator: u32,
+    pub cycle_budget: i32, // COSMAC VIP timing only, negative once an instruction ran past the end of the frame
+    pub frame_time_accumulator: u64, // Frame scheduler only, in 1/60 ns
 
     pub memory: Vec<u8>,
 
@@ -52,9 +76,28 @@ pub struct CPUState
 
     pub key_state_prev: u16,
     pub is_waiting_for_key: bool,
+    pub is_waiting_for_display: bool,
+    pub is_halted: bool,
+    pub is_pc_written: bool, // Set by the instruction being executed when it moved PC itself
+
+    pub platform: Platform,
+    pub quirks: Quirks,
+    pub rom_hash: u64, // Identifies the loaded program in save states
+    pub rng_state: u64,
+    pub frame_count: u32, // 60 Hz frames since power on, for the schedulers, tracing and headless runs
+    pub instruction_count: u32, // Instructions fetched since power on, a faulting one and every retry of Fx0A included
+    pub tracer: Option<Box<trace::Tracer>>,
 
     pub font_table_offsets: [u16; FONT_TABLE_GLYPH_COUNT],
-    pub screen: Vec<Vec<u8>>,
+    pub big_font_table_offsets: [u16; FONT_TABLE_GLYPH_COUNT],
+    pub rpl_flags: [u8; RPL_FLAG_COUNT],
+
+    pub audio_pattern: [u8; AUDIO_PATTERN_SIZE_IN_BYTES],
+    pub audio_pitch: u8,
+
+    pub is_hires: bool,
+    pub selected_planes: u8, // Bit N selects plane N
+    pub screen: [Vec<Vec<u8>>; SCREEN_PLANE_COUNT],
 }
 
 const FONT_TABLE_OFFSET_IN_BYTES: usize = 0x0000;
@@ -78,6 +121,28 @@ const FONT_TABLE: [u8; GLYPH_SIZE_IN_BYTES * FONT_TABLE_GLYPH_COUNT] =
     0xF0, 0x80, 0xF0, 0x80, 0x80
 ];
 
+// Stored right after the small font
+const BIG_FONT_TABLE_OFFSET_IN_BYTES: usize = FONT_TABLE_OFFSET_IN_BYTES + GLYPH_SIZE_IN_BYTES * FONT_TABLE_GLYPH_COUNT;
+const BIG_FONT_TABLE: [u8; BIG_GLYPH_SIZE_IN_BYTES * FONT_TABLE_GLYPH_COUNT] =
+[
+    0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, // 0
+    0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF, // 1
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // etc...
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF,
+    0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03,
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF,
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF,
+    0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18,
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF,
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF,
+    0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3,
+    0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC,
+    0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C,
+    0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC,
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF,
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0
+];
+
 fn load_font_table(state: &mut CPUState)
 {
     let table_offset = FONT_TABLE_OFFSET_IN_BYTES;
@@ -96,20 +161,62 @@ fn load_font_table(state: &mut CPUState)
     }
 }
 
+fn load_big_font_table(state: &mut CPUState)
+{
+    let table_offset = BIG_FONT_TABLE_OFFSET_IN_BYTES;
+    let table_size = FONT_TABLE_GLYPH_COUNT * BIG_GLYPH_SIZE_IN_BYTES;
+
+    // Make sure we don't spill in program addressable space.
+    assert!((table_offset + table_size - 1) < MIN_PROGRAM_ADDRESS);
+
+    let font_range_begin = BIG_FONT_TABLE_OFFSET_IN_BYTES;
+    let font_range_end = BIG_FONT_TABLE_OFFSET_IN_BYTES + table_size;
+    state.memory[font_range_begin..font_range_end].clone_from_slice(&BIG_FONT_TABLE[..]);
+
+    for table_index in 0..FONT_TABLE_GLYPH_COUNT {
+        state.big_font_table_offsets[table_index] = (table_offset + BIG_GLYPH_SIZE_IN_BYTES * table_index) as u16;
+    }
+}
+
+pub fn memory_size_in_bytes(platform: Platform) -> usize
+{
+    match platform {
+        Platform::Chip8 | Platform::SuperChip => MEMORY_SIZE_IN_BYTES,
+        Platform::XoChip => XO_CHIP_MEMORY_SIZE_IN_BYTES,
+    }
+}
+
 pub fn create_chip8_state() -> CPUState
+{
+    create_chip8_state_for_platform(Platform::Chip8)
+}
+
+pub fn create_chip8_state_for_platform(platform: Platform) -> CPUState
 {
     let mut state: CPUState = Default::default();
 
+    state.platform = platform;
+
     // Set PC to first address
     state.pc = MIN_PROGRAM_ADDRESS as u16;
 
     // Clear memory
-    state.memory = vec![0; MEMORY_SIZE_IN_BYTES];
+    state.memory = vec![0; memory_size_in_bytes(platform)];
 
     // Clear screen
-    state.screen = vec![vec![0; SCREEN_LINE_SIZE_IN_BYTES]; SCREEN_HEIGHT];
+    state.selected_planes = 0x1;
+    state.screen = [
+        vec![vec![0; SCREEN_LINE_SIZE_IN_BYTES]; SCREEN_HEIGHT],
+        vec![vec![0; SCREEN_LINE_SIZE_IN_BYTES]; SCREEN_HEIGHT],
+    ];
+
+    state.audio_pitch = DEFAULT_AUDIO_PITCH;
+
+    // Hosts needing reproducible runs reseed it
+    state.rng_state = rand::random();
 
     load_font_table(&mut state);
+    load_big_font_table(&mut state);
 
     state
 }
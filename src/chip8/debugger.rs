@@ -0,0 +1,493 @@
+use super::{
+    cpu::CPUState,
+    disassembler,
+    execution,
+    fault::ExecutionError,
+    opcode,
+    opcode::OpCode,
+};
+
+use std::{
+    collections::BTreeSet,
+    io::{self, BufRead, Write},
+};
+
+const HELP_TEXT: &str = "\
+Numbers are hexadecimal, an empty line repeats the last command.
+  b, break <addr>          set a breakpoint
+  d, delete <addr>         clear a breakpoint
+  bl, breakpoints          list breakpoints
+  s, step                  execute one instruction
+  n, next                  same as step, but runs over subroutine calls
+  f, finish                run until the current subroutine returns
+  c, continue              resume execution
+  r, regs                  show registers, stack and timers
+  l, list [addr] [count]   disassemble around PC or the given address
+  x <addr> [count]         dump memory
+  w <addr> <byte>...       write memory
+  h, help                  show this help
+  q, quit                  quit the emulator
+";
+
+// Instructions listed before and after PC by default
+const LIST_CONTEXT_SIZE: usize = 5;
+const DUMP_BYTES_PER_LINE: usize = 16;
+
+// Condition that pauses a running machine, besides breakpoints
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum RunMode
+{
+    Continue,
+    StepOver { return_address: u16, sp: u8 },
+    Finish { sp: u8 },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DebuggerAction
+{
+    Resume,
+    Quit,
+}
+
+pub struct Debugger
+{
+    breakpoints: BTreeSet<u16>,
+    run_mode: RunMode,
+    is_paused: bool,
+    last_command: String,
+}
+
+impl Default for Debugger
+{
+    fn default() -> Self
+    {
+        Self::new()
+    }
+}
+
+fn parse_hex(text: &str) -> Option<u32>
+{
+    let digits = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")).unwrap_or(text);
+
+    u32::from_str_radix(digits, 16).ok()
+}
+
+fn parse_address(state: &CPUState, text: Option<&str>) -> Result<u16, String>
+{
+    let text = text.ok_or_else(|| "missing address".to_string())?;
+
+    parse_hex(text)
+        .filter(|&address| (address as usize) < state.memory.len())
+        .map(|address| address as u16)
+        .ok_or_else(|| format!("invalid address '{}'", text))
+}
+
+impl Debugger
+{
+    pub fn new() -> Debugger
+    {
+        Debugger {
+            breakpoints: BTreeSet::new(),
+            run_mode: RunMode::Continue,
+            is_paused: false,
+            last_command: String::new(),
+        }
+    }
+
+    pub fn is_paused(&self) -> bool
+    {
+        self.is_paused
+    }
+
+    // Break into the debugger before the next instruction.
+    pub fn pause(&mut self)
+    {
+        self.is_paused = true;
+        self.run_mode = RunMode::Continue;
+    }
+
+    pub fn set_breakpoint(&mut self, address: u16)
+    {
+        self.breakpoints.insert(address);
+    }
+
+    pub fn clear_breakpoint(&mut self, address: u16) -> bool
+    {
+        self.breakpoints.remove(&address)
+    }
+
+    // Replaces execution::execute_step while the debugger is attached.
+    // Pauses right before an instruction on a breakpoint, or once a step over or finish is done.
+    pub fn execute_step(&mut self, state: &mut CPUState, delta_time_ms: u32) -> Result<(), ExecutionError>
+    {
+        if self.is_paused {
+            return Ok(());
+        }
+
+        let breakpoints = &self.breakpoints;
+        let run_mode = self.run_mode;
+
+        let has_stopped = execution::execute_step_until(state, delta_time_ms, &mut |state| {
+            let is_done = match run_mode {
+                RunMode::Continue => false,
+                RunMode::StepOver { return_address, sp } => state.pc == return_address && state.sp == sp,
+                RunMode::Finish { sp } => state.sp < sp,
+            };
+
+            is_done || breakpoints.contains(&state.pc)
+        })?;
+
+        if has_stopped {
+            self.pause();
+        }
+
+        Ok(())
+    }
+
+    // Resume until the next breakpoint.
+    pub fn continue_execution(&mut self, state: &mut CPUState) -> Result<(), ExecutionError>
+    {
+        self.resume(state, RunMode::Continue)
+    }
+
+    // Leaves the breakpoint PC sits on behind, so that resuming doesn't stop right away.
+    fn resume(&mut self, state: &mut CPUState, run_mode: RunMode) -> Result<(), ExecutionError>
+    {
+        if self.breakpoints.contains(&state.pc) && !state.is_halted && !state.is_waiting_for_display {
+            execution::execute_next_instruction(state)?;
+        }
+
+        self.run_mode = run_mode;
+        self.is_paused = false;
+
+        Ok(())
+    }
+
+    pub fn write_location(&self, state: &CPUState, out: &mut dyn Write) -> io::Result<()>
+    {
+        let reason = if self.breakpoints.contains(&state.pc) { "Breakpoint at" } else { "Paused at" };
+
+        writeln!(out, "{} 0x{:03X}", reason, state.pc)?;
+        self.write_listing(state, state.pc as usize, 0, 1, out)
+    }
+
+    fn write_registers(&self, state: &CPUState, out: &mut dyn Write) -> io::Result<()>
+    {
+        writeln!(out, "PC 0x{:03X}  I 0x{:03X}  SP {}  DT {}  ST {}", state.pc, state.i, state.sp, state.delay_timer, state.sound_timer)?;
+
+        for registers in state.v_registers.chunks(8).enumerate() {
+            let (row, values) = registers;
+            let line: Vec<String> = values.iter().enumerate()
+                .map(|(index, value)| format!("V{:X} {:02X}", row * 8 + index, value))
+                .collect();
+
+            writeln!(out, "{}", line.join("  "))?;
+        }
+
+        // Slot 0 is never used, the innermost return address is at sp
+        let stack: Vec<String> = state.stack[1..=state.sp as usize].iter().map(|address| format!("0x{:03X}", address)).collect();
+
+        writeln!(out, "stack: [{}]", stack.join(" "))
+    }
+
+    // CHIP-8 has no way to know where instructions start, the listing assumes they are 2 bytes apart.
+    fn write_listing(&self, state: &CPUState, address: usize, before: usize, count: usize, out: &mut dyn Write) -> io::Result<()>
+    {
+        let mut address = address.saturating_sub(2 * before) & !0x1;
+
+        for _ in 0..count {
+            if address + 1 >= state.memory.len() {
+                break;
+            }
+
+            let (text, size) = disassembler::disassemble_instruction(&state.memory, address)
+                .unwrap_or_else(|| (format!("db 0x{:02X}, 0x{:02X}", state.memory[address], state.memory[address + 1]), 2));
+
+            let pc_marker = if address == state.pc as usize { '>' } else { ' ' };
+            let breakpoint_marker = if self.breakpoints.contains(&(address as u16)) { '*' } else { ' ' };
+
+            writeln!(out, "{}{} 0x{:03X}  {}", pc_marker, breakpoint_marker, address, text)?;
+
+            address += size;
+        }
+
+        Ok(())
+    }
+
+    fn write_memory_dump(&self, state: &CPUState, address: usize, count: usize, out: &mut dyn Write) -> io::Result<()>
+    {
+        let end = (address + count).min(state.memory.len());
+
+        for line_begin in (address..end).step_by(DUMP_BYTES_PER_LINE) {
+            let line_end = (line_begin + DUMP_BYTES_PER_LINE).min(end);
+            let bytes: Vec<String> = state.memory[line_begin..line_end].iter().map(|byte| format!("{:02X}", byte)).collect();
+
+            writeln!(out, "0x{:03X}: {}", line_begin, bytes.join(" "))?;
+        }
+
+        Ok(())
+    }
+
+    // Returns an action when execution should resume or stop altogether.
+    pub fn run_command(&mut self, state: &mut CPUState, command: &str, out: &mut dyn Write) -> io::Result<Option<DebuggerAction>>
+    {
+        let command = if command.trim().is_empty() { self.last_command.clone() } else { command.trim().to_string() };
+        self.last_command = command.clone();
+
+        let mut arguments = command.split_whitespace();
+        let name = arguments.next().unwrap_or("");
+
+        let result: Result<Option<DebuggerAction>, String> = match name {
+            "" => Ok(None),
+            "b" | "break" => parse_address(state, arguments.next()).map(|address| {
+                self.set_breakpoint(address);
+                None
+            }),
+            "d" | "delete" => parse_address(state, arguments.next()).and_then(|address| {
+                if self.clear_breakpoint(address) { Ok(None) } else { Err(format!("no breakpoint at 0x{:03X}", address)) }
+            }),
+            "bl" | "breakpoints" => {
+                for address in self.breakpoints.iter() {
+                    writeln!(out, "0x{:03X}", address)?;
+                }
+                Ok(None)
+            },
+            "s" | "step" | "n" | "next" => {
+                let instruction = state.memory.get(state.pc as usize..state.pc as usize + 2)
+                    .map(|bytes| u16::from(bytes[0]) << 8 | u16::from(bytes[1]));
+                let is_call = matches!(instruction.map(opcode::decode_instruction), Some(Ok(OpCode::CALL{..})));
+
+                if (name == "n" || name == "next") && is_call {
+                    let run_mode = RunMode::StepOver { return_address: state.pc + 2, sp: state.sp };
+
+                    self.resume(state, run_mode).map(|_| Some(DebuggerAction::Resume)).map_err(|error| error.to_string())
+                } else if state.is_halted || state.is_waiting_for_display {
+                    Err("waiting for the next frame, use continue".to_string())
+                } else {
+                    match execution::execute_next_instruction(state) {
+                        Ok(()) => {
+                            self.write_listing(state, state.pc as usize, 0, 1, out)?;
+                            Ok(None)
+                        },
+                        Err(error) => Err(error.to_string()),
+                    }
+                }
+            },
+            "f" | "finish" => {
+                if state.sp == 0 {
+                    Err("not in a subroutine".to_string())
+                } else {
+                    let run_mode = RunMode::Finish { sp: state.sp };
+
+                    self.resume(state, run_mode).map(|_| Some(DebuggerAction::Resume)).map_err(|error| error.to_string())
+                }
+            },
+            "c" | "continue" => self.continue_execution(state).map(|_| Some(DebuggerAction::Resume)).map_err(|error| error.to_string()),
+            "r" | "regs" => {
+                self.write_registers(state, out)?;
+                Ok(None)
+            },
+            "l" | "list" => {
+                let address = arguments.next().map_or(Ok(state.pc), |text| parse_address(state, Some(text)));
+                let count = arguments.next().and_then(parse_hex).map_or(2 * LIST_CONTEXT_SIZE + 1, |count| count as usize);
+
+                match address {
+                    Ok(address) => {
+                        let before = if address == state.pc { LIST_CONTEXT_SIZE } else { 0 };
+                        self.write_listing(state, address as usize, before, count, out)?;
+                        Ok(None)
+                    },
+                    Err(error) => Err(error),
+                }
+            },
+            "x" => {
+                let address = parse_address(state, arguments.next());
+                let count = arguments.next().and_then(parse_hex).map_or(0x40, |count| count as usize);
+
+                match address {
+                    Ok(address) => {
+                        self.write_memory_dump(state, address as usize, count, out)?;
+                        Ok(None)
+                    },
+                    Err(error) => Err(error),
+                }
+            },
+            "w" => parse_address(state, arguments.next()).and_then(|address| {
+                let bytes = arguments
+                    .map(|text| parse_hex(text).filter(|&value| value <= 0xFF).map(|value| value as u8).ok_or_else(|| format!("invalid byte '{}'", text)))
+                    .collect::<Result<Vec<u8>, String>>()?;
+
+                let begin = address as usize;
+                let end = begin + bytes.len();
+
+                if bytes.is_empty() || end > state.memory.len() {
+                    return Err("nothing to write in memory range".to_string());
+                }
+
+                state.memory[begin..end].copy_from_slice(&bytes);
+
+                Ok(None)
+            }),
+            "h" | "help" => {
+                write!(out, "{}", HELP_TEXT)?;
+                Ok(None)
+            },
+            "q" | "quit" => Ok(Some(DebuggerAction::Quit)),
+            _ => Err(format!("unknown command '{}', try 'help'", name)),
+        };
+
+        match result {
+            Ok(action) => Ok(action),
+            Err(message) => {
+                writeln!(out, "error: {}", message)?;
+                Ok(None)
+            },
+        }
+    }
+
+    // Reads commands until execution resumes, end of input quits.
+    pub fn run_repl(&mut self, state: &mut CPUState, input: &mut dyn BufRead, out: &mut dyn Write) -> io::Result<DebuggerAction>
+    {
+        self.write_location(state, out)?;
+
+        loop {
+            write!(out, "(chip8) ")?;
+            out.flush()?;
+
+            let mut line = String::new();
+
+            if input.read_line(&mut line)? == 0 {
+                return Ok(DebuggerAction::Quit);
+            }
+
+            if let Some(action) = self.run_command(state, &line, out)? {
+                return Ok(action);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::{
+        assembler,
+        cpu,
+    };
+
+    fn run(debugger: &mut Debugger, state: &mut CPUState, command: &str) -> (Option<DebuggerAction>, String)
+    {
+        let mut output = Vec::new();
+        let action = debugger.run_command(state, command, &mut output).unwrap();
+
+        (action, String::from_utf8(output).unwrap())
+    }
+
+    #[test]
+    fn debugger() {
+        let mut state = cpu::create_chip8_state();
+
+        execution::load_program(&mut state, assembler::assemble("
+                LD V0, 0x01     ; 0x200
+                CALL sub        ; 0x202
+                LD V2, 0x03     ; 0x204
+            loop:
+                JP loop         ; 0x206
+            sub:
+                LD V1, 0x02     ; 0x208
+                CALL inner      ; 0x20A
+                RET             ; 0x20C
+            inner:
+                ADD V1, 0x01    ; 0x20E
+                RET             ; 0x210
+        ").unwrap()).unwrap();
+
+        let mut debugger = Debugger::new();
+        let frame_ms = cpu::DELAY_TIMER_PERIOD_MS;
+
+        // Breakpoints stop before the instruction
+        run(&mut debugger, &mut state, "b 20A");
+        debugger.execute_step(&mut state, frame_ms).unwrap();
+
+        assert!(debugger.is_paused());
+        assert_eq!(state.pc, 0x20A);
+        assert_eq!(state.v_registers[1], 0x02);
+        assert!(run(&mut debugger, &mut state, "regs").1.ends_with("stack: [0x202]\n"));
+
+        // Step over the nested call
+        assert_eq!(run(&mut debugger, &mut state, "next").0, Some(DebuggerAction::Resume));
+        debugger.execute_step(&mut state, frame_ms).unwrap();
+
+        assert!(debugger.is_paused());
+        assert_eq!(state.pc, 0x20C);
+        assert_eq!(state.v_registers[1], 0x03);
+
+        // Single step into RET
+        let (action, output) = run(&mut debugger, &mut state, "s");
+
+        assert_eq!(action, None);
+        assert_eq!(state.pc, 0x204);
+        assert_eq!(output, ">  0x204  LD V2, 0x03\n");
+
+        // Repeat the last command
+        run(&mut debugger, &mut state, "");
+
+        assert_eq!(state.v_registers[2], 0x03);
+
+        let (_, output) = run(&mut debugger, &mut state, "regs");
+
+        assert!(output.starts_with("PC 0x206  I 0x000  SP 0  DT 0  ST 0\nV0 01  V1 03  V2 03"));
+        assert!(output.ends_with("stack: []\n"));
+
+        // SUBCASE: finish
+        {
+            let mut state = cpu::create_chip8_state();
+            let mut debugger = Debugger::new();
+
+            execution::load_program(&mut state, assembler::assemble("CALL sub\nhalt: JP back\nback: JP halt\nsub: LD V5, 0x55\nADD V5, 1\nRET").unwrap()).unwrap();
+
+            run(&mut debugger, &mut state, "b 208");
+            debugger.execute_step(&mut state, frame_ms).unwrap();
+
+            assert_eq!(run(&mut debugger, &mut state, "finish").0, Some(DebuggerAction::Resume));
+            debugger.execute_step(&mut state, frame_ms).unwrap();
+
+            assert!(debugger.is_paused());
+            assert_eq!(state.pc, 0x202);
+            assert_eq!(state.v_registers[5], 0x56);
+            assert_eq!(run(&mut debugger, &mut state, "finish").1, "error: not in a subroutine\n");
+
+            // The breakpoint is never reached again
+            run(&mut debugger, &mut state, "c");
+            debugger.execute_step(&mut state, frame_ms).unwrap();
+
+            assert!(!debugger.is_paused());
+        }
+
+        // SUBCASE: memory and listing
+        {
+            let (_, output) = run(&mut debugger, &mut state, "w 300 DE AD BE EF");
+
+            assert_eq!(output, "");
+            assert_eq!(state.memory[0x300..0x304], [0xDE, 0xAD, 0xBE, 0xEF]);
+            assert_eq!(run(&mut debugger, &mut state, "x 300 4").1, "0x300: DE AD BE EF\n");
+            assert_eq!(run(&mut debugger, &mut state, "l 208 3").1, "   0x208  LD V1, 0x02\n * 0x20A  CALL 0x20E\n   0x20C  RET\n");
+            assert_eq!(run(&mut debugger, &mut state, "w 300 100").1, "error: invalid byte '100'\n");
+            assert_eq!(run(&mut debugger, &mut state, "x 10000").1, "error: invalid address '10000'\n");
+            assert_eq!(run(&mut debugger, &mut state, "d 20A").1, "");
+            assert_eq!(run(&mut debugger, &mut state, "d 20A").1, "error: no breakpoint at 0x20A\n");
+            assert_eq!(run(&mut debugger, &mut state, "frobnicate").1, "error: unknown command 'frobnicate', try 'help'\n");
+        }
+
+        // SUBCASE: REPL
+        {
+            let mut input = io::Cursor::new("bl\nquit\n");
+            let mut output = Vec::new();
+
+            debugger.set_breakpoint(0x204);
+
+            assert_eq!(debugger.run_repl(&mut state, &mut input, &mut output).unwrap(), DebuggerAction::Quit);
+            assert_eq!(String::from_utf8(output).unwrap(), "Paused at 0x206\n>  0x206  JP 0x206\n(chip8) 0x204\n(chip8) ");
+        }
+    }
+}
@@ -0,0 +1,407 @@
+use super::{
+    config::Platform,
+    cpu,
+    opcode,
+    opcode::OpCode,
+};
+
+// Data bytes are grouped by that many on each 'db' line
+const DATA_BYTES_PER_LINE: usize = 8;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ListingLine
+{
+    pub address: u16,
+    pub bytes: Vec<u8>,
+    pub label: Option<String>, // Set when the line is a jump, call or load target
+    pub text: String,
+}
+
+// Number of bytes taken by the instruction, F000 is followed by its operand.
+fn instruction_size(opcode: &OpCode) -> usize
+{
+    match opcode {
+        OpCode::LDIL => 4,
+        _ => 2,
+    }
+}
+
+fn read_word(memory: &[u8], address: usize) -> Option<u16>
+{
+    let bytes = memory.get(address..address + 2)?;
+
+    Some(u16::from(bytes[0]) << 8 | u16::from(bytes[1]))
+}
+
+// Standard mnemonics, 'format_address' renders the addresses so that labels can replace them.
+pub fn format_opcode(opcode: &OpCode, long_operand: u16, format_address: &dyn Fn(u16) -> String) -> String
+{
+    match *opcode {
+        OpCode::CLS => "CLS".to_string(),
+        OpCode::RET => "RET".to_string(),
+        OpCode::SYS{addr} => format!("SYS {}", format_address(addr)),
+        OpCode::JP{addr} => format!("JP {}", format_address(addr)),
+        OpCode::CALL{addr} => format!("CALL {}", format_address(addr)),
+        OpCode::SE{reg, value} => format!("SE V{:X}, 0x{:02X}", reg, value),
+        OpCode::SNE{reg, value} => format!("SNE V{:X}, 0x{:02X}", reg, value),
+        OpCode::SE2{reg_x, reg_y} => format!("SE V{:X}, V{:X}", reg_x, reg_y),
+        OpCode::LD{reg, value} => format!("LD V{:X}, 0x{:02X}", reg, value),
+        OpCode::ADD{reg, value} => format!("ADD V{:X}, 0x{:02X}", reg, value),
+        OpCode::LD2{reg_x, reg_y} => format!("LD V{:X}, V{:X}", reg_x, reg_y),
+        OpCode::OR{reg_x, reg_y} => format!("OR V{:X}, V{:X}", reg_x, reg_y),
+        OpCode::AND{reg_x, reg_y} => format!("AND V{:X}, V{:X}", reg_x, reg_y),
+        OpCode::XOR{reg_x, reg_y} => format!("XOR V{:X}, V{:X}", reg_x, reg_y),
+        OpCode::ADD2{reg_x, reg_y} => format!("ADD V{:X}, V{:X}", reg_x, reg_y),
+        OpCode::SUB{reg_x, reg_y} => format!("SUB V{:X}, V{:X}", reg_x, reg_y),
+        OpCode::SHR{reg_x, reg_y} => format!("SHR V{:X}, V{:X}", reg_x, reg_y),
+        OpCode::SUBN{reg_x, reg_y} => format!("SUBN V{:X}, V{:X}", reg_x, reg_y),
+        OpCode::SHL{reg_x, reg_y} => format!("SHL V{:X}, V{:X}", reg_x, reg_y),
+        OpCode::SNE2{reg_x, reg_y} => format!("SNE V{:X}, V{:X}", reg_x, reg_y),
+        OpCode::LDI{addr} => format!("LD I, {}", format_address(addr)),
+        OpCode::JP2{addr} => format!("JP V0, {}", format_address(addr)),
+        OpCode::RND{reg, value} => format!("RND V{:X}, 0x{:02X}", reg, value),
+        OpCode::DRW{reg_x, reg_y, size} => format!("DRW V{:X}, V{:X}, {}", reg_x, reg_y, size),
+        OpCode::SKP{reg} => format!("SKP V{:X}", reg),
+        OpCode::SKNP{reg} => format!("SKNP V{:X}", reg),
+        OpCode::LDT{reg} => format!("LD V{:X}, DT", reg),
+        OpCode::LDK{reg} => format!("LD V{:X}, K", reg),
+        OpCode::LDDT{reg} => format!("LD DT, V{:X}", reg),
+        OpCode::LDST{reg} => format!("LD ST, V{:X}", reg),
+        OpCode::ADDI{reg} => format!("ADD I, V{:X}", reg),
+        OpCode::LDF{reg} => format!("LD F, V{:X}", reg),
+        OpCode::LDB{reg} => format!("LD B, V{:X}", reg),
+        OpCode::LDAI{reg} => format!("LD [I], V{:X}", reg),
+        OpCode::LDM{reg} => format!("LD V{:X}, [I]", reg),
+        OpCode::SCD{size} => format!("SCD {}", size),
+        OpCode::SCR => "SCR".to_string(),
+        OpCode::SCL => "SCL".to_string(),
+        OpCode::EXIT => "EXIT".to_string(),
+        OpCode::LOW => "LOW".to_string(),
+        OpCode::HIGH => "HIGH".to_string(),
+        OpCode::LDHF{reg} => format!("LD HF, V{:X}", reg),
+        OpCode::LDR{reg} => format!("LD R, V{:X}", reg),
+        OpCode::LDVR{reg} => format!("LD V{:X}, R", reg),
+        OpCode::SCU{size} => format!("SCU {}", size),
+        OpCode::SAVE{reg_x, reg_y} => format!("SAVE V{:X}, V{:X}", reg_x, reg_y),
+        OpCode::LOAD{reg_x, reg_y} => format!("LOAD V{:X}, V{:X}", reg_x, reg_y),
+        OpCode::LDIL => format!("LD I, long {}", format_address(long_operand)),
+        OpCode::PLANE{mask} => format!("PLANE {}", mask),
+        OpCode::AUDIO => "AUDIO".to_string(),
+        OpCode::PITCH{reg} => format!("PITCH V{:X}", reg),
+    }
+}
+
+pub fn format_raw_address(address: u16) -> String
+{
+    format!("0x{:03X}", address)
+}
+
+// Single instruction at any address of live memory, for the debugger and tracing.
+// Returns the text and the size of the instruction, or None if it does not decode.
+pub fn disassemble_instruction(memory: &[u8], address: usize) -> Option<(String, usize)>
+{
+    let opcode = opcode::decode_instruction(read_word(memory, address)?).ok()?;
+    let long_operand = match opcode {
+        OpCode::LDIL => read_word(memory, address + 2)?,
+        _ => 0,
+    };
+
+    Some((format_opcode(&opcode, long_operand, &format_raw_address), instruction_size(&opcode)))
+}
+
+// Label kinds by priority, an address called as a subroutine is named as such even if also jumped to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum LabelKind
+{
+    Data,
+    Jump,
+    Subroutine,
+}
+
+struct Analysis
+{
+    instruction_starts: Vec<bool>,
+    labels: Vec<Option<LabelKind>>,
+}
+
+// Follows every statically known path from the entry point, whatever is never reached is data.
+// Targets of computed jumps (JP V0, addr) can't be known and end up as data.
+fn analyze_program(program: &[u8], base_address: usize, platform: Platform) -> Analysis
+{
+    let mut analysis = Analysis {
+        instruction_starts: vec![false; program.len()],
+        labels: vec![None; program.len()],
+    };
+
+    let mut visited = vec![false; program.len()];
+    let mut pending_offsets = vec![0];
+
+    let to_offset = |address: u16| (address as usize).checked_sub(base_address).filter(|&offset| offset < program.len());
+
+    let add_label = |labels: &mut Vec<Option<LabelKind>>, address: u16, kind: LabelKind| {
+        if let Some(offset) = to_offset(address) {
+            labels[offset] = labels[offset].max(Some(kind));
+        }
+    };
+
+    while let Some(offset) = pending_offsets.pop() {
+        if offset >= program.len() || visited[offset] {
+            continue;
+        }
+
+        let opcode = match read_word(program, offset).map(opcode::decode_instruction) {
+            Some(Ok(opcode)) if opcode::check_platform_support(&opcode, platform).is_ok() => opcode,
+            _ => continue,
+        };
+
+        let size = instruction_size(&opcode);
+
+        if offset + size > program.len() {
+            continue;
+        }
+
+        visited[offset] = true;
+        analysis.instruction_starts[offset] = true;
+
+        let next_offset = offset + size;
+
+        match opcode {
+            OpCode::JP{addr} => {
+                add_label(&mut analysis.labels, addr, LabelKind::Jump);
+                pending_offsets.extend(to_offset(addr));
+            },
+            OpCode::CALL{addr} => {
+                add_label(&mut analysis.labels, addr, LabelKind::Subroutine);
+                pending_offsets.extend(to_offset(addr));
+                pending_offsets.push(next_offset);
+            },
+            OpCode::RET | OpCode::EXIT => {},
+            OpCode::JP2{addr} => add_label(&mut analysis.labels, addr, LabelKind::Data),
+            OpCode::SE{..} | OpCode::SNE{..} | OpCode::SE2{..} | OpCode::SNE2{..} | OpCode::SKP{..} | OpCode::SKNP{..} => {
+                // XO-CHIP skips over the whole long instruction
+                let skipped_size = match read_word(program, next_offset) {
+                    Some(opcode::LONG_INSTRUCTION_PREFIX) if platform == Platform::XoChip => 4,
+                    _ => 2,
+                };
+
+                pending_offsets.push(next_offset);
+                pending_offsets.push(next_offset + skipped_size);
+            },
+            OpCode::LDI{addr} => {
+                add_label(&mut analysis.labels, addr, LabelKind::Data);
+                pending_offsets.push(next_offset);
+            },
+            OpCode::LDIL => {
+                if let Some(addr) = read_word(program, offset + 2) {
+                    add_label(&mut analysis.labels, addr, LabelKind::Data);
+                }
+                pending_offsets.push(next_offset);
+            },
+            _ => pending_offsets.push(next_offset),
+        }
+    }
+
+    analysis
+}
+
+fn label_name(kind: LabelKind, address: usize) -> String
+{
+    let prefix = match kind {
+        LabelKind::Data => "D",
+        LabelKind::Jump => "L",
+        LabelKind::Subroutine => "S",
+    };
+
+    format!("{}{:03X}", prefix, address)
+}
+
+// Size of the line starting at offset, a whole instruction or a run of data up to the next instruction or label.
+fn line_size(program: &[u8], analysis: &Analysis, offset: usize) -> usize
+{
+    if analysis.instruction_starts[offset] {
+        return instruction_size(&opcode::decode_instruction(read_word(program, offset).unwrap()).unwrap());
+    }
+
+    let mut data_end = offset + 1;
+
+    while data_end < program.len() && data_end - offset < DATA_BYTES_PER_LINE
+        && !analysis.instruction_starts[data_end] && analysis.labels[data_end].is_none() {
+        data_end += 1;
+    }
+
+    data_end - offset
+}
+
+// Programs are expected to be loaded at the usual address.
+pub fn disassemble(program: &[u8], platform: Platform) -> Vec<ListingLine>
+{
+    let base_address = cpu::MIN_PROGRAM_ADDRESS;
+    let analysis = analyze_program(program, base_address, platform);
+
+    // Only lines can declare labels, targets that fall inside an instruction keep their raw address
+    let mut line_starts = vec![false; program.len()];
+    let mut offset = 0;
+
+    while offset < program.len() {
+        line_starts[offset] = true;
+        offset += line_size(program, &analysis, offset);
+    }
+
+    let label_at = |offset: usize| {
+        analysis.labels[offset]
+            .filter(|_| line_starts[offset])
+            .map(|kind| label_name(kind, base_address + offset))
+    };
+
+    let format_address = |address: u16| {
+        (address as usize).checked_sub(base_address)
+            .filter(|&offset| offset < program.len())
+            .and_then(label_at)
+            .unwrap_or_else(|| format_raw_address(address))
+    };
+
+    let mut lines = Vec::new();
+    let mut offset = 0;
+
+    while offset < program.len() {
+        let address = (base_address + offset) as u16;
+        let size = line_size(program, &analysis, offset);
+        let bytes = program[offset..offset + size].to_vec();
+
+        let text = if analysis.instruction_starts[offset] {
+            let opcode = opcode::decode_instruction(read_word(program, offset).unwrap()).unwrap();
+            let long_operand = read_word(program, offset + 2).unwrap_or(0);
+
+            format_opcode(&opcode, long_operand, &format_address)
+        } else {
+            let values: Vec<String> = bytes.iter().map(|byte| format!("0x{:02X}", byte)).collect();
+
+            format!("db {}", values.join(", "))
+        };
+
+        lines.push(ListingLine { address, bytes, label: label_at(offset), text });
+
+        offset += size;
+    }
+
+    lines
+}
+
+// One line per instruction or data chunk, labels on their own line.
+pub fn format_listing(lines: &[ListingLine]) -> String
+{
+    let mut listing = String::new();
+
+    for line in lines {
+        if let Some(label) = &line.label {
+            listing += &format!("{}:\n", label);
+        }
+
+        let bytes: String = line.bytes.iter().take(4).map(|byte| format!("{:02X}", byte)).collect();
+
+        listing += &format!("    {:<8}{:<10}{}\n", format_raw_address(line.address), bytes, line.text);
+    }
+
+    listing
+}
+
+// Same as the listing without addresses and bytes, the assembler reads it back.
+pub fn format_source(lines: &[ListingLine]) -> String
+{
+    let mut source = String::new();
+
+    for line in lines {
+        if let Some(label) = &line.label {
+            source += &format!("{}:\n", label);
+        }
+
+        source += &format!("    {}\n", line.text);
+    }
+
+    source
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn disassembler() {
+        let format = |instruction: u16| {
+            format_opcode(&opcode::decode_instruction(instruction).unwrap(), 0x1234, &format_raw_address)
+        };
+
+        assert_eq!(format(0x6312), "LD V3, 0x12");
+        assert_eq!(format(0xD015), "DRW V0, V1, 5");
+        assert_eq!(format(0x2F00), "CALL 0xF00");
+        assert_eq!(format(0x8AB6), "SHR VA, VB");
+        assert_eq!(format(0xF155), "LD [I], V1");
+        assert_eq!(format(0xF265), "LD V2, [I]");
+        assert_eq!(format(0xB240), "JP V0, 0x240");
+        assert_eq!(format(0x00C4), "SCD 4");
+        assert_eq!(format(0xF000), "LD I, long 0x1234");
+        assert_eq!(format(0xF201), "PLANE 2");
+
+        assert_eq!(disassemble_instruction(&[0x00, 0xE0], 0), Some(("CLS".to_string(), 2)));
+        assert_eq!(disassemble_instruction(&[0xF0, 0x00, 0x12, 0x34], 0), Some(("LD I, long 0x1234".to_string(), 4)));
+        assert_eq!(disassemble_instruction(&[0xFF, 0xFF], 0), None);
+        assert_eq!(disassemble_instruction(&[0x00], 0), None);
+
+        // SUBCASE: code and data separation
+        {
+            let program = [
+                0xA2, 0x0C, // 0x200: LD I, sprite
+                0x22, 0x08, // 0x202: CALL draw
+                0x12, 0x04, // 0x204: JP 0x204
+                0x12, 0x06, // 0x206 (unreachable)
+                0xD0, 0x15, // 0x208: DRW V0, V1, 5
+                0x00, 0xEE, // 0x20A: RET
+                0xF0, 0x90, 0xF0, 0x90, 0x90, // 0x20C: sprite
+            ];
+
+            let lines = disassemble(&program, Platform::Chip8);
+            let texts: Vec<&str> = lines.iter().map(|line| line.text.as_str()).collect();
+
+            assert_eq!(texts, [
+                "LD I, D20C",
+                "CALL S208",
+                "JP L204",
+                "db 0x12, 0x06",
+                "DRW V0, V1, 5",
+                "RET",
+                "db 0xF0, 0x90, 0xF0, 0x90, 0x90",
+            ]);
+
+            assert_eq!(lines[2].label.as_deref(), Some("L204"));
+            assert_eq!(lines[4].label.as_deref(), Some("S208"));
+
+            let listing = format_listing(&lines);
+
+            assert!(listing.starts_with("    0x200   A20C      LD I, D20C\n"));
+            assert!(listing.contains("S208:\n    0x208   D015      DRW V0, V1, 5\n"));
+        }
+
+        // SUBCASE: platform specific instructions are data elsewhere
+        {
+            let program = [0x00, 0xFF, 0x12, 0x00];
+
+            assert_eq!(disassemble(&program, Platform::Chip8)[0].text, "db 0x00, 0xFF, 0x12, 0x00");
+            assert_eq!(disassemble(&program, Platform::SuperChip)[0].text, "HIGH");
+        }
+
+        // SUBCASE: targets inside an instruction aren't labelled
+        {
+            let program = [
+                0xA2, 0x03, // 0x200: LD I, 0x203, the operand of the next instruction
+                0x60, 0x05, // 0x202: LD V0, 5
+                0x12, 0x04, // 0x204: JP 0x204
+            ];
+
+            let lines = disassemble(&program, Platform::Chip8);
+            let texts: Vec<&str> = lines.iter().map(|line| line.text.as_str()).collect();
+
+            assert_eq!(texts, ["LD I, 0x203", "LD V0, 0x05", "JP L204"]);
+            assert!(lines.iter().all(|line| line.label.as_deref() != Some("D203")));
+        }
+    }
+}
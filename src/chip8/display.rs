@@ -1,20 +1,151 @@
+use super::cpu;
 use super::cpu::CPUState;
 
-pub fn read_screen_pixel(state: &CPUState, x: usize, y: usize) -> bool
+pub fn screen_width(state: &CPUState) -> usize
+{
+    if state.is_hires { cpu::HIRES_SCREEN_WIDTH } else { cpu::SCREEN_WIDTH }
+}
+
+pub fn screen_height(state: &CPUState) -> usize
+{
+    if state.is_hires { cpu::HIRES_SCREEN_HEIGHT } else { cpu::SCREEN_HEIGHT }
+}
+
+pub fn read_plane_pixel(state: &CPUState, plane: usize, x: usize, y: usize) -> bool
 {
     let screen_offset_byte = x / 8;
     let screen_offset_bit = x % 8;
 
-    ((state.screen[y][screen_offset_byte] >> screen_offset_bit) & 0x1) != 0
+    ((state.screen[plane][y][screen_offset_byte] >> screen_offset_bit) & 0x1) != 0
 }
 
-pub fn write_screen_pixel(state: &mut CPUState, x: usize, y: usize, value: bool)
+pub fn write_plane_pixel(state: &mut CPUState, plane: usize, x: usize, y: usize, value: bool)
 {
     let screen_offset_byte = x / 8;
     let screen_offset_bit = x % 8;
 
     let mask = (1 << screen_offset_bit) as u8;
-    let screen_byte_value = state.screen[y][screen_offset_byte];
+    let screen_byte_value = state.screen[plane][y][screen_offset_byte];
+
+    state.screen[plane][y][screen_offset_byte] = screen_byte_value & !mask | (value as u8) << screen_offset_bit as u8;
+}
+
+// A pixel is lit if it is set on any plane.
+pub fn read_screen_pixel(state: &CPUState, x: usize, y: usize) -> bool
+{
+    read_screen_pixel_color_index(state, x, y) != 0
+}
+
+// Bit N of the result is set when the pixel is lit on plane N.
+pub fn read_screen_pixel_color_index(state: &CPUState, x: usize, y: usize) -> u8
+{
+    let mut color_index = 0;
+
+    for plane in 0..cpu::SCREEN_PLANE_COUNT {
+        color_index |= (read_plane_pixel(state, plane, x, y) as u8) << plane;
+    }
+
+    color_index
+}
+
+pub fn is_plane_selected(state: &CPUState, plane: usize) -> bool
+{
+    (state.selected_planes & (1 << plane)) != 0
+}
+
+fn create_plane(state: &CPUState) -> Vec<Vec<u8>>
+{
+    vec![vec![0; screen_width(state) / 8]; screen_height(state)]
+}
+
+// Only the selected planes are cleared.
+pub fn clear_screen(state: &mut CPUState)
+{
+    for plane in 0..cpu::SCREEN_PLANE_COUNT {
+        if is_plane_selected(state, plane) {
+            state.screen[plane] = create_plane(state);
+        }
+    }
+}
+
+// NOTE: Switching resolution reallocates and clears all planes.
+pub fn set_hires(state: &mut CPUState, is_hires: bool)
+{
+    state.is_hires = is_hires;
+
+    for plane in 0..cpu::SCREEN_PLANE_COUNT {
+        state.screen[plane] = create_plane(state);
+    }
+}
+
+// Scroll amounts are expressed in pixels of the current resolution, and only affect the selected planes.
+pub fn scroll_down(state: &mut CPUState, line_count: usize)
+{
+    let line_count = line_count.min(screen_height(state));
+    let line_size = screen_width(state) / 8;
+    let height = screen_height(state);
+
+    for plane in 0..cpu::SCREEN_PLANE_COUNT {
+        if is_plane_selected(state, plane) {
+            let plane_lines = &mut state.screen[plane];
+
+            plane_lines.truncate(height - line_count);
+
+            for _ in 0..line_count {
+                plane_lines.insert(0, vec![0; line_size]);
+            }
+        }
+    }
+}
+
+pub fn scroll_up(state: &mut CPUState, line_count: usize)
+{
+    let line_count = line_count.min(screen_height(state));
+    let line_size = screen_width(state) / 8;
+
+    for plane in 0..cpu::SCREEN_PLANE_COUNT {
+        if is_plane_selected(state, plane) {
+            let plane_lines = &mut state.screen[plane];
+
+            plane_lines.drain(0..line_count);
+
+            for _ in 0..line_count {
+                plane_lines.push(vec![0; line_size]);
+            }
+        }
+    }
+}
+
+pub fn scroll_right(state: &mut CPUState, pixel_count: usize)
+{
+    for plane in 0..cpu::SCREEN_PLANE_COUNT {
+        if !is_plane_selected(state, plane) {
+            continue;
+        }
+
+        for y in 0..screen_height(state) {
+            for x in (0..screen_width(state)).rev() {
+                let value = x >= pixel_count && read_plane_pixel(state, plane, x - pixel_count, y);
+                write_plane_pixel(state, plane, x, y, value);
+            }
+        }
+    }
+}
+
+pub fn scroll_left(state: &mut CPUState, pixel_count: usize)
+{
+    let width = screen_width(state);
+
+    for plane in 0..cpu::SCREEN_PLANE_COUNT {
+        if !is_plane_selected(state, plane) {
+            continue;
+        }
 
-    state.screen[y][screen_offset_byte] = screen_byte_value & !mask | (value as u8) << screen_offset_bit as u8;
+        for y in 0..screen_height(state) {
+            for x in 0..width {
+                let value = x + pixel_count < width && read_plane_pixel(state, plane, x + pixel_count, y);
+                write_plane_pixel(state, plane, x, y, value);
+            }
+        }
+    }
 }
@@ -1,23 +1,42 @@
 use super::{
+    config::{Platform, TimingMode},
     cpu,
+    fault::{Chip8Fault, ExecutionError},
+    hash,
     instruction,
     memory,
     opcode,
+    timing,
 };
 
-use std::cmp::max;
+use std::{
+    cmp::max,
+    time::Duration,
+};
+
+// The frame scheduler counts time in 1/60 ns, a frame is then exactly a second's worth of nanoseconds
+const FRAME_TIME_UNITS_PER_FRAME: u64 = 1_000_000_000;
 
-pub fn load_program(state: &mut cpu::CPUState, program: Vec<u8>)
+pub fn load_program(state: &mut cpu::CPUState, program: Vec<u8>) -> Result<(), Chip8Fault>
 {
     let program_size = program.len();
 
-    assert!((program_size & 0x0001) == 0); // Unaligned size
-    assert!(memory::is_valid_memory_range(cpu::MIN_PROGRAM_ADDRESS as u16, program_size, memory::MemoryUsage::Write));
+    // XO-CHIP programs mix code and data freely, so their size may be odd
+    let is_unaligned = (program_size & 0x0001) != 0 && state.platform != Platform::XoChip;
+
+    // Reject empty, unaligned or oversized programs
+    if program_size == 0 || is_unaligned
+        || !memory::is_valid_memory_range(state, cpu::MIN_PROGRAM_ADDRESS as u16, program_size, memory::MemoryUsage::Write) {
+        return Err(Chip8Fault::InvalidProgramSize { size: program_size });
+    }
 
     let range_begin = cpu::MIN_PROGRAM_ADDRESS;
     let range_end = cpu::MIN_PROGRAM_ADDRESS + program_size;
 
     state.memory[range_begin..range_end].clone_from_slice(&program[..]);
+    state.rom_hash = hash::fnv1a_64(&program);
+
+    Ok(())
 }
 
 pub fn load_next_instruction(state: &cpu::CPUState) -> u16
@@ -32,58 +51,330 @@ pub fn load_next_instruction(state: &cpu::CPUState) -> u16
     instruction as u16
 }
 
-pub fn execute_step(state: &mut cpu::CPUState, delta_time_ms: u32)
+// Stops at the first faulting instruction, leaving PC on it.
+pub fn execute_step(state: &mut cpu::CPUState, delta_time_ms: u32) -> Result<(), ExecutionError>
+{
+    execute_step_until(state, delta_time_ms, &mut |_| false).map(|_| ())
+}
+
+// Same as execute_step, but stops right before the first instruction for which 'should_break' is true.
+// Returns true if it stopped that way, the remaining instructions of the step are dropped.
+pub fn execute_step_until(state: &mut cpu::CPUState, delta_time_ms: u32, should_break: &mut dyn FnMut(&cpu::CPUState) -> bool) -> Result<bool, ExecutionError>
+{
+    execute_duration_until(state, Duration::from_millis(u64::from(delta_time_ms)), should_break)
+}
+
+// Same as execute_step_until for hosts with a finer clock, wall-clock or synthetic.
+// Only the frame scheduler keeps the sub-millisecond part, the millisecond one drops it.
+pub fn execute_duration_until(state: &mut cpu::CPUState, elapsed: Duration, should_break: &mut dyn FnMut(&cpu::CPUState) -> bool) -> Result<bool, ExecutionError>
+{
+    match state.quirks.instructions_per_frame {
+        Some(instructions_per_frame) => execute_frames_until(state, elapsed, instructions_per_frame, should_break),
+        None => {
+            let mut instructions_to_execute: u32 = 0;
+
+            update_timers(state, &mut instructions_to_execute, elapsed.as_millis().min(u128::from(u32::MAX)) as u32);
+            execute_instructions_until(state, instructions_to_execute, should_break)
+        },
+    }
+}
+
+// Runs every 60 Hz frame due, each one decrementing the timers once before running its instructions.
+// A break leaves the frames still due for the next call.
+fn execute_frames_until(state: &mut cpu::CPUState, elapsed: Duration, instructions_per_frame: u32, should_break: &mut dyn FnMut(&cpu::CPUState) -> bool) -> Result<bool, ExecutionError>
 {
-    let mut instructions_to_execute: u32 = 0;
+    let elapsed_units = elapsed.as_nanos().saturating_mul(u128::from(cpu::DELAY_TIMER_FREQUENCY));
+    state.frame_time_accumulator = state.frame_time_accumulator.saturating_add(elapsed_units.min(u128::from(u64::MAX)) as u64);
+
+    while state.frame_time_accumulator >= FRAME_TIME_UNITS_PER_FRAME {
+        state.frame_time_accumulator -= FRAME_TIME_UNITS_PER_FRAME;
+
+        start_frames(state, 1);
 
-    update_timers(state, &mut instructions_to_execute, delta_time_ms);
+        // Cycle timed instructions are bounded by the cycle budget instead
+        let instructions_to_execute = match state.quirks.timing {
+            TimingMode::Fixed => instructions_per_frame,
+            TimingMode::CosmacVip => u32::MAX,
+        };
 
+        if execute_instructions_until(state, instructions_to_execute, should_break)? {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+fn execute_instructions_until(state: &mut cpu::CPUState, instructions_to_execute: u32, should_break: &mut dyn FnMut(&cpu::CPUState) -> bool) -> Result<bool, ExecutionError>
+{
     for _ in 0..instructions_to_execute
     {
-        // Simulate logic
-        let next_instruction = load_next_instruction(state);
-        execute_instruction(state, next_instruction);
+        // Nothing runs until the next frame once a sprite waits for the display
+        if state.is_waiting_for_display || state.is_halted {
+            break;
+        }
+
+        // Cycle timed instructions run until the frame is paid for instead
+        if state.quirks.timing == TimingMode::CosmacVip && state.cycle_budget <= 0 {
+            break;
+        }
+
+        if should_break(state) {
+            return Ok(true);
+        }
+
+        execute_next_instruction(state)?;
+    }
+
+    Ok(false)
+}
+
+// Fetch and execute the instruction at PC, regardless of timers.
+pub fn execute_next_instruction(state: &mut cpu::CPUState) -> Result<(), ExecutionError>
+{
+    state.instruction_count = state.instruction_count.wrapping_add(1);
+
+    // PC can run off the end of memory without any jump being involved
+    if let Err(fault) = memory::check_memory_range(state, state.pc, 2, memory::MemoryUsage::Execute) {
+        return Err(record_fault(state, ExecutionError { pc: state.pc, instruction: 0x0000, fault }));
+    }
+
+    // Simulate logic
+    let next_instruction = load_next_instruction(state);
+
+    if state.quirks.timing != TimingMode::CosmacVip {
+        return execute_instruction(state, next_instruction);
+    }
+
+    // Decoded once more for its cost, which depends on the registers before execution
+    let pc = state.pc;
+    let opcode = opcode::decode_instruction(next_instruction).ok();
+    let mut cycles = opcode.as_ref().map_or(0, |opcode| timing::instruction_cycles(state, opcode));
+
+    execute_instruction(state, next_instruction)?;
+
+    if opcode.as_ref().is_some_and(timing::is_skip) && state.pc != pc.wrapping_add(2) {
+        cycles += timing::VIP_SKIP_CYCLES;
+    }
+
+    state.cycle_budget = state.cycle_budget.saturating_sub(cycles);
+
+    Ok(())
+}
+
+// What happens at every 60 Hz tick, whichever scheduler is in use.
+fn start_frames(state: &mut cpu::CPUState, frame_count: u32)
+{
+    if frame_count == 0 {
+        return;
+    }
+
+    // Delay and sound timers share the same 60 Hz clock
+    state.delay_timer = max(0, i64::from(state.delay_timer) - i64::from(frame_count)) as u8;
+    state.sound_timer = max(0, i64::from(state.sound_timer) - i64::from(frame_count)) as u8;
+
+    state.frame_count = state.frame_count.wrapping_add(frame_count);
+
+    // Release any sprite waiting for the new frame
+    state.is_waiting_for_display = false;
+
+    if state.quirks.timing == TimingMode::CosmacVip {
+        timing::start_frames(state, frame_count);
     }
 }
 
 fn update_timers(state: &mut cpu::CPUState, execution_counter: &mut u32, delta_time_ms: u32)
 {
-    // Update delay timer
+    // Update delay and sound timers
     state.delay_timer_accumulator += delta_time_ms;
 
-    let delay_timer_decrement: u32 = state.delay_timer_accumulator / cpu::DELAY_TIMER_PERIOD_MS;
-    state.delay_timer = max(0, i32::from(state.delay_timer) - delay_timer_decrement as i32) as u8; // TODO maybe there's a cast error here
+    let timer_decrement: u32 = state.delay_timer_accumulator / cpu::DELAY_TIMER_PERIOD_MS;
 
     // Remove accumulated ticks
     state.delay_timer_accumulator %= cpu::DELAY_TIMER_PERIOD_MS;
 
+    start_frames(state, timer_decrement);
+
     // Update execution counter
+    if state.quirks.timing == TimingMode::CosmacVip {
+        // Bounded by the cycle budget
+        *execution_counter = u32::MAX;
+        return;
+    }
+
     state.execution_timer_accumulator += delta_time_ms;
 
     *execution_counter = state.execution_timer_accumulator / cpu::INSTRUCTION_EXECUTION_PERIOD_MS;
     state.execution_timer_accumulator %= cpu::INSTRUCTION_EXECUTION_PERIOD_MS;
+}
 
-    // TODO Handle sound
-    if state.sound_timer > 0 {
-        state.sound_timer += 1;
+// Hands the fault to the tracer, if any, on its way out.
+fn record_fault(state: &mut cpu::CPUState, error: ExecutionError) -> ExecutionError
+{
+    if let Some(mut tracer) = state.tracer.take() {
+        tracer.record_fault(state, &error);
+        state.tracer = Some(tracer);
     }
+
+    error
 }
 
-pub fn execute_instruction(state: &mut cpu::CPUState, instruction: u16)
+pub fn execute_instruction(state: &mut cpu::CPUState, instruction: u16) -> Result<(), ExecutionError>
 {
     // Save PC for later
     let pc_save = state.pc;
 
-    let instruction = opcode::decode_instruction(instruction);
+    state.is_pc_written = false;
+
+    let result = opcode::decode_instruction(instruction)
+        .and_then(|opcode| opcode::check_platform_support(&opcode, state.platform).map(|_| opcode))
+        .and_then(|opcode| instruction::execute_instruction_internal(state, opcode).map(|_| opcode))
+        .map_err(|fault| ExecutionError { pc: pc_save, instruction, fault });
 
-    instruction::execute_instruction_internal(state, instruction);
+    let opcode = match result {
+        Ok(opcode) => opcode,
+        Err(error) => return Err(record_fault(state, error)),
+    };
 
     // Increment PC only if it was NOT overriden by an instruction,
     // or if we are waiting for user input.
-    if pc_save == state.pc && !state.is_waiting_for_key {
-        state.pc += 2;
+    // PC wraps at the end of XO-CHIP's 64 KiB, the next fetch then faults in the interpreter area.
+    if !state.is_pc_written && !state.is_waiting_for_key {
+        state.pc = state.pc.wrapping_add(2);
     }
 
     // Save previous key state
     state.key_state_prev = state.key_state;
+
+    // Taken out for the time of the call, the tracer reads the rest of the state
+    if let Some(mut tracer) = state.tracer.take() {
+        tracer.record(state, pc_save, instruction, &opcode);
+        state.tracer = Some(tracer);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::assembler;
+
+    const COUNTER: &str = "
+        loop:
+            ADD V0, 1
+            JP back
+        back:
+            JP loop
+    ";
+
+    fn create_counter_state(instructions_per_frame: Option<u32>) -> cpu::CPUState
+    {
+        let mut state = cpu::create_chip8_state();
+
+        state.quirks.instructions_per_frame = instructions_per_frame;
+        load_program(&mut state, assembler::assemble(COUNTER).unwrap()).unwrap();
+        state.delay_timer = 100;
+
+        state
+    }
+
+    #[test]
+    fn scheduler() {
+        // A second of 1 ms steps, the millisecond scheduler ticks every 16 ms
+        let mut state = create_counter_state(None);
+
+        for _ in 0..1000 {
+            execute_step(&mut state, 1).unwrap();
+        }
+
+        assert_eq!(state.frame_count, 62);
+        assert_eq!(state.delay_timer, 38);
+
+        let mut state = create_counter_state(Some(9));
+
+        for _ in 0..1000 {
+            execute_step(&mut state, 1).unwrap();
+        }
+
+        assert_eq!(state.frame_count, 60);
+        assert_eq!(state.delay_timer, 40);
+        assert_eq!(u32::from(state.v_registers[0]), 60 * 9 / 3);
+
+        // SUBCASE: sub-millisecond time
+        {
+            let mut state = create_counter_state(Some(9));
+
+            execute_duration_until(&mut state, Duration::from_nanos(16_666_666), &mut |_| false).unwrap();
+
+            assert_eq!(state.frame_count, 0);
+
+            execute_duration_until(&mut state, Duration::from_nanos(1), &mut |_| false).unwrap();
+
+            assert_eq!(state.frame_count, 1);
+            assert_eq!(state.delay_timer, 99);
+
+            // Many frames at once, each one runs its own instructions
+            execute_duration_until(&mut state, Duration::from_secs(2), &mut |_| false).unwrap();
+
+            assert_eq!(state.frame_count, 121);
+            assert_eq!(u32::from(state.v_registers[0]), 121 * 3 % 256);
+        }
+
+        // SUBCASE: display wait
+        {
+            let source = "
+                loop:
+                    ADD V1, 1
+                    DRW V0, V0, 1
+                    JP loop
+            ";
+
+            // One draw per frame, whatever the scheduler and the timing
+            for (instructions_per_frame, timing) in [(None, TimingMode::Fixed), (Some(20), TimingMode::Fixed), (Some(20), TimingMode::CosmacVip)] {
+                let mut state = cpu::create_chip8_state();
+
+                state.quirks.display_wait = true;
+                state.quirks.instructions_per_frame = instructions_per_frame;
+                state.quirks.timing = timing;
+                load_program(&mut state, assembler::assemble(source).unwrap()).unwrap();
+
+                for _ in 0..10 {
+                    execute_duration_until(&mut state, Duration::from_nanos(16_666_667), &mut |_| false).unwrap();
+                }
+
+                assert_eq!(state.frame_count, 10);
+                assert_eq!(state.v_registers[1], 10);
+                assert!(state.is_waiting_for_display);
+            }
+
+            // Without it, the whole frame runs
+            let mut state = cpu::create_chip8_state();
+
+            state.quirks.instructions_per_frame = Some(20);
+            load_program(&mut state, assembler::assemble(source).unwrap()).unwrap();
+            execute_duration_until(&mut state, Duration::from_nanos(16_666_667), &mut |_| false).unwrap();
+
+            assert_eq!(state.v_registers[1], 7);
+        }
+
+        // SUBCASE: break
+        {
+            let mut state = create_counter_state(Some(9));
+            let mut instruction_count = 0;
+
+            // Stops in the middle of the first frame, the second one stays due
+            let has_stopped = execute_duration_until(&mut state, Duration::from_millis(34), &mut |_| {
+                instruction_count += 1;
+                instruction_count > 4
+            }).unwrap();
+
+            assert!(has_stopped);
+            assert_eq!(state.frame_count, 1);
+
+            execute_step(&mut state, 0).unwrap();
+
+            assert_eq!(state.frame_count, 2);
+        }
+    }
 }
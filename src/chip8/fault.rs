@@ -0,0 +1,59 @@
+use super::memory::MemoryUsage;
+
+use std::{
+    error,
+    fmt,
+};
+
+// Everything a misbehaving ROM can do to the interpreter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Chip8Fault
+{
+    InvalidOpcode,
+    StackOverflow,
+    StackUnderflow,
+    MemoryViolation { addr: u16, usage: MemoryUsage },
+    UnalignedJump { addr: u16 },
+    IndexOverflow,
+    InvalidKey { key: u8 },
+    InvalidGlyph { glyph: u8 },
+    InvalidProgramSize { size: usize },
+}
+
+// A fault along with the location of the instruction that raised it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExecutionError
+{
+    pub pc: u16,
+    pub instruction: u16,
+    pub fault: Chip8Fault,
+}
+
+impl fmt::Display for Chip8Fault
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        match self {
+            Chip8Fault::InvalidOpcode => write!(f, "invalid opcode"),
+            Chip8Fault::StackOverflow => write!(f, "stack overflow"),
+            Chip8Fault::StackUnderflow => write!(f, "stack underflow"),
+            Chip8Fault::MemoryViolation{addr, usage} => write!(f, "invalid {} access at 0x{:04X}", usage, addr),
+            Chip8Fault::UnalignedJump{addr} => write!(f, "unaligned jump to 0x{:04X}", addr),
+            Chip8Fault::IndexOverflow => write!(f, "I register overflow"),
+            Chip8Fault::InvalidKey{key} => write!(f, "invalid key 0x{:X}", key),
+            Chip8Fault::InvalidGlyph{glyph} => write!(f, "invalid font glyph 0x{:X}", glyph),
+            Chip8Fault::InvalidProgramSize{size} => write!(f, "invalid program size ({} bytes)", size),
+        }
+    }
+}
+
+impl fmt::Display for ExecutionError
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        write!(f, "{} (pc: 0x{:04X}, instruction: 0x{:04X})", self.fault, self.pc, self.instruction)
+    }
+}
+
+impl error::Error for Chip8Fault {}
+impl error::Error for ExecutionError {}
@@ -0,0 +1,515 @@
+use super::{
+    cpu,
+    cpu::CPUState,
+    debugger::Debugger,
+    execution,
+    fault::{Chip8Fault, ExecutionError},
+};
+
+use std::{
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+};
+
+// Register file as seen by GDB, in 'g' packet order:
+// V0-VF (1 byte each), I (2 bytes), PC (2 bytes), SP (1 byte), multi-byte values are little endian.
+pub const GDB_REGISTER_SIZE_IN_BYTES: usize = cpu::V_REGISTER_COUNT + 2 + 2 + 1;
+
+const SIGNAL_INTERRUPT: u8 = 2;
+const SIGNAL_ILLEGAL_INSTRUCTION: u8 = 4;
+const SIGNAL_TRAP: u8 = 5;
+const SIGNAL_SEGMENTATION_FAULT: u8 = 11;
+
+const INTERRUPT_BYTE: u8 = 0x03;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GdbSession
+{
+    Attached,
+    Detached,
+    Killed,
+}
+
+// What the interpreter loop has to do once a packet is handled
+#[derive(Debug, PartialEq, Eq)]
+enum PacketAction
+{
+    Reply(String),
+    Step,
+    Continue,
+    Detach,
+    Kill,
+}
+
+fn checksum(data: &[u8]) -> u8
+{
+    data.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+}
+
+fn encode_packet(data: &str) -> Vec<u8>
+{
+    format!("${}#{:02x}", data, checksum(data.as_bytes())).into_bytes()
+}
+
+fn encode_hex(bytes: &[u8]) -> String
+{
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn decode_hex(text: &str) -> Option<Vec<u8>>
+{
+    if !text.len().is_multiple_of(2) || !text.is_ascii() {
+        return None;
+    }
+
+    (0..text.len()).step_by(2)
+        .map(|offset| u8::from_str_radix(&text[offset..offset + 2], 16).ok())
+        .collect()
+}
+
+// Parses "addr,len", both in hexadecimal.
+fn parse_memory_range(state: &CPUState, text: &str) -> Option<(usize, usize)>
+{
+    let mut fields = text.splitn(2, ',');
+    let address = usize::from_str_radix(fields.next()?, 16).ok()?;
+    let length = usize::from_str_radix(fields.next()?, 16).ok()?;
+
+    if address.checked_add(length)? > state.memory.len() {
+        return None;
+    }
+
+    Some((address, length))
+}
+
+fn read_registers(state: &CPUState) -> Vec<u8>
+{
+    let mut registers = Vec::with_capacity(GDB_REGISTER_SIZE_IN_BYTES);
+
+    registers.extend_from_slice(&state.v_registers);
+    registers.extend_from_slice(&state.i.to_le_bytes());
+    registers.extend_from_slice(&state.pc.to_le_bytes());
+    registers.push(state.sp);
+
+    registers
+}
+
+fn write_registers(state: &mut CPUState, registers: &[u8]) -> bool
+{
+    let v_count = cpu::V_REGISTER_COUNT;
+
+    if registers.len() != GDB_REGISTER_SIZE_IN_BYTES || registers[v_count + 4] as usize >= cpu::STACK_SIZE {
+        return false;
+    }
+
+    state.v_registers.copy_from_slice(&registers[..v_count]);
+    state.i = u16::from_le_bytes([registers[v_count], registers[v_count + 1]]);
+    state.pc = u16::from_le_bytes([registers[v_count + 2], registers[v_count + 3]]);
+    state.sp = registers[v_count + 4];
+
+    true
+}
+
+fn fault_signal(error: &ExecutionError) -> u8
+{
+    match error.fault {
+        Chip8Fault::InvalidOpcode => SIGNAL_ILLEGAL_INSTRUCTION,
+        _ => SIGNAL_SEGMENTATION_FAULT,
+    }
+}
+
+fn stop_reply(signal: u8) -> String
+{
+    format!("S{:02x}", signal)
+}
+
+// Serves a single GDB client over the remote serial protocol.
+// The machine starts stopped, GDB then drives it with 's' and 'c' while the frontend keeps calling execute_step.
+pub struct GdbStub
+{
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+    debugger: Debugger,
+    last_signal: u8,
+}
+
+impl GdbStub
+{
+    // Blocks until a client connects.
+    pub fn accept(listener: &TcpListener) -> io::Result<GdbStub>
+    {
+        let (stream, _) = listener.accept()?;
+
+        stream.set_nodelay(true)?;
+
+        let mut debugger = Debugger::new();
+        debugger.pause();
+
+        Ok(GdbStub {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: stream,
+            debugger,
+            last_signal: SIGNAL_TRAP,
+        })
+    }
+
+    // Replaces execution::execute_step while a client is attached.
+    // Blocks serving packets as long as the machine is stopped, faults stop the machine and are reported to GDB.
+    pub fn execute_step(&mut self, state: &mut CPUState, delta_time_ms: u32) -> io::Result<GdbSession>
+    {
+        if !self.debugger.is_paused() && self.poll_interrupt()? {
+            self.debugger.pause();
+            self.stop(SIGNAL_INTERRUPT)?;
+        }
+
+        if !self.debugger.is_paused() {
+            match self.debugger.execute_step(state, delta_time_ms) {
+                Ok(()) if self.debugger.is_paused() => self.stop(SIGNAL_TRAP)?,
+                Ok(()) => return Ok(GdbSession::Attached),
+                Err(error) => {
+                    self.debugger.pause();
+                    self.stop(fault_signal(&error))?;
+                },
+            }
+        }
+
+        self.serve_packets(state)
+    }
+
+    fn stop(&mut self, signal: u8) -> io::Result<()>
+    {
+        self.last_signal = signal;
+        self.send_packet(&stop_reply(signal))
+    }
+
+    fn serve_packets(&mut self, state: &mut CPUState) -> io::Result<GdbSession>
+    {
+        loop {
+            let packet = match self.read_packet()? {
+                Some(packet) => packet,
+                None => return Ok(GdbSession::Detached),
+            };
+
+            match self.handle_packet(state, &packet) {
+                PacketAction::Reply(reply) => self.send_packet(&reply)?,
+                PacketAction::Step => {
+                    // Nothing runs while waiting for the display, GDB just gets the stop back
+                    let signal = if state.is_halted || state.is_waiting_for_display {
+                        SIGNAL_TRAP
+                    } else {
+                        execution::execute_next_instruction(state).map_or_else(|error| fault_signal(&error), |_| SIGNAL_TRAP)
+                    };
+
+                    self.stop(signal)?;
+                },
+                PacketAction::Continue => {
+                    if let Err(error) = self.debugger.continue_execution(state) {
+                        self.stop(fault_signal(&error))?;
+                    } else {
+                        return Ok(GdbSession::Attached);
+                    }
+                },
+                PacketAction::Detach => {
+                    self.send_packet("OK")?;
+                    return Ok(GdbSession::Detached);
+                },
+                PacketAction::Kill => return Ok(GdbSession::Killed),
+            }
+        }
+    }
+
+    fn handle_packet(&mut self, state: &mut CPUState, packet: &str) -> PacketAction
+    {
+        let error_reply = || PacketAction::Reply("E01".to_string());
+        let (command, arguments) = packet.split_at(packet.chars().next().map_or(0, char::len_utf8));
+
+        match command {
+            "?" => PacketAction::Reply(stop_reply(self.last_signal)),
+            "g" => PacketAction::Reply(encode_hex(&read_registers(state))),
+            "G" => match decode_hex(arguments) {
+                Some(registers) if write_registers(state, &registers) => PacketAction::Reply("OK".to_string()),
+                _ => error_reply(),
+            },
+            "m" => match parse_memory_range(state, arguments) {
+                Some((address, length)) => PacketAction::Reply(encode_hex(&state.memory[address..address + length])),
+                None => error_reply(),
+            },
+            "M" => {
+                let mut fields = arguments.splitn(2, ':');
+                let range = fields.next().and_then(|range| parse_memory_range(state, range));
+                let bytes = fields.next().and_then(decode_hex);
+
+                match (range, bytes) {
+                    (Some((address, length)), Some(bytes)) if bytes.len() == length => {
+                        state.memory[address..address + length].copy_from_slice(&bytes);
+                        PacketAction::Reply("OK".to_string())
+                    },
+                    _ => error_reply(),
+                }
+            },
+            "Z" | "z" => {
+                let fields: Vec<&str> = arguments.split(',').collect();
+
+                // Only software breakpoints are supported, an empty reply tells GDB so
+                if fields.len() != 3 || fields[0] != "0" {
+                    return PacketAction::Reply(String::new());
+                }
+
+                match u16::from_str_radix(fields[1], 16) {
+                    Ok(address) if (address as usize) < state.memory.len() => {
+                        if command == "Z" {
+                            self.debugger.set_breakpoint(address);
+                        } else {
+                            self.debugger.clear_breakpoint(address);
+                        }
+
+                        PacketAction::Reply("OK".to_string())
+                    },
+                    _ => error_reply(),
+                }
+            },
+            "s" | "c" => {
+                // An optional address to resume from
+                if !arguments.is_empty() {
+                    match u16::from_str_radix(arguments, 16) {
+                        Ok(address) => state.pc = address,
+                        Err(_) => return error_reply(),
+                    }
+                }
+
+                if command == "s" { PacketAction::Step } else { PacketAction::Continue }
+            },
+            "D" => PacketAction::Detach,
+            "k" => PacketAction::Kill,
+            _ if packet.starts_with("qSupported") => PacketAction::Reply("PacketSize=1000".to_string()),
+            _ => PacketAction::Reply(String::new()),
+        }
+    }
+
+    fn send_packet(&mut self, data: &str) -> io::Result<()>
+    {
+        self.writer.write_all(&encode_packet(data))?;
+        self.writer.flush()
+    }
+
+    fn read_byte(&mut self) -> io::Result<Option<u8>>
+    {
+        let mut byte = [0; 1];
+
+        match self.reader.read(&mut byte)? {
+            0 => Ok(None),
+            _ => Ok(Some(byte[0])),
+        }
+    }
+
+    // Returns None once the client has disconnected.
+    // Acknowledges every packet, the ones with a bad checksum are asked again.
+    fn read_packet(&mut self) -> io::Result<Option<String>>
+    {
+        loop {
+            // Skip acknowledgements and interrupts sent while already stopped
+            match self.read_byte()? {
+                Some(b'$') => {},
+                Some(_) => continue,
+                None => return Ok(None),
+            }
+
+            let mut data = Vec::new();
+
+            if self.reader.read_until(b'#', &mut data)? == 0 || data.pop() != Some(b'#') {
+                return Ok(None);
+            }
+
+            let mut checksum_text = [0; 2];
+            self.reader.read_exact(&mut checksum_text)?;
+
+            let expected_checksum = std::str::from_utf8(&checksum_text).ok().and_then(|text| u8::from_str_radix(text, 16).ok());
+
+            if expected_checksum != Some(checksum(&data)) {
+                self.writer.write_all(b"-")?;
+                continue;
+            }
+
+            self.writer.write_all(b"+")?;
+
+            match String::from_utf8(data) {
+                Ok(packet) => return Ok(Some(packet)),
+                Err(_) => self.send_packet("E01")?,
+            }
+        }
+    }
+
+    // Looks for a Ctrl-C from GDB without blocking.
+    fn poll_interrupt(&mut self) -> io::Result<bool>
+    {
+        if self.reader.buffer().is_empty() {
+            self.reader.get_ref().set_nonblocking(true)?;
+            let result = self.reader.fill_buf().map(|_| ());
+            self.reader.get_ref().set_nonblocking(false)?;
+
+            match result {
+                Err(ref error) if error.kind() == io::ErrorKind::WouldBlock => return Ok(false),
+                result => result?,
+            }
+        }
+
+        let is_interrupted = self.reader.buffer().contains(&INTERRUPT_BYTE);
+
+        if is_interrupted {
+            let length = self.reader.buffer().len();
+            self.reader.consume(length);
+        }
+
+        Ok(is_interrupted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::assembler;
+
+    use std::thread;
+
+    // Sends a packet and returns the reply, the way GDB does
+    fn exchange(stream: &mut TcpStream, data: &str) -> String
+    {
+        stream.write_all(&encode_packet(data)).unwrap();
+
+        read_reply(stream)
+    }
+
+    fn read_reply(stream: &mut TcpStream) -> String
+    {
+        let mut reply = Vec::new();
+        let mut byte = [0; 1];
+
+        loop {
+            stream.read_exact(&mut byte).unwrap();
+
+            match byte[0] {
+                b'+' if reply.is_empty() => {},
+                b'#' => break,
+                byte => reply.push(byte),
+            }
+        }
+
+        let mut checksum_text = [0; 2];
+        stream.read_exact(&mut checksum_text).unwrap();
+
+        assert_eq!(reply[0], b'$');
+        assert_eq!(u8::from_str_radix(std::str::from_utf8(&checksum_text).unwrap(), 16).unwrap(), checksum(&reply[1..]));
+
+        String::from_utf8(reply[1..].to_vec()).unwrap()
+    }
+
+    #[test]
+    fn gdb_stub() {
+        let mut state = cpu::create_chip8_state();
+
+        execution::load_program(&mut state, assembler::assemble("
+                LD V0, 0x11     ; 0x200
+                LD I, 0x300     ; 0x202
+                ADD V0, 1       ; 0x204
+            loop:
+                ADD V1, 1       ; 0x206
+                JP loop         ; 0x208
+        ").unwrap()).unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+
+        let client = thread::spawn(move || {
+            let mut stream = TcpStream::connect(address).unwrap();
+
+            assert_eq!(exchange(&mut stream, "qSupported:swbreak+"), "PacketSize=1000");
+            assert_eq!(exchange(&mut stream, "?"), "S05");
+            assert_eq!(exchange(&mut stream, "vMustReplyEmpty"), "");
+
+            // Registers: V0-VF, I, PC, SP
+            let registers = exchange(&mut stream, "g");
+
+            assert_eq!(registers.len(), 2 * GDB_REGISTER_SIZE_IN_BYTES);
+            assert_eq!(&registers[32..], "0000000200");
+
+            assert_eq!(exchange(&mut stream, "s"), "S05");
+            assert_eq!(exchange(&mut stream, "s"), "S05");
+            assert_eq!(&exchange(&mut stream, "g")[..2], "11");
+            assert_eq!(&exchange(&mut stream, "g")[32..], "0003040200");
+
+            // Memory
+            assert_eq!(exchange(&mut stream, "m200,4"), "6011a300");
+            assert_eq!(exchange(&mut stream, "M300,2:beef"), "OK");
+            assert_eq!(exchange(&mut stream, "m300,2"), "beef");
+            assert_eq!(exchange(&mut stream, "mffff,2"), "E01");
+            assert_eq!(exchange(&mut stream, "M300,2:be"), "E01");
+
+            // Breakpoints stop before the instruction
+            assert_eq!(exchange(&mut stream, "Z1,206,2"), "");
+            assert_eq!(exchange(&mut stream, "Z0,206,2"), "OK");
+            assert_eq!(exchange(&mut stream, "c"), "S05");
+            assert_eq!(&exchange(&mut stream, "g")[32..], "0003060200");
+            assert_eq!(&exchange(&mut stream, "g")[..4], "1200");
+            assert_eq!(exchange(&mut stream, "c"), "S05");
+            assert_eq!(&exchange(&mut stream, "g")[..4], "1201");
+            assert_eq!(exchange(&mut stream, "z0,206,2"), "OK");
+
+            // Interrupt a running machine
+            stream.write_all(&encode_packet("c")).unwrap();
+            stream.write_all(&[INTERRUPT_BYTE]).unwrap();
+
+            assert_eq!(read_reply(&mut stream), "S02");
+
+            // Writing the registers moves PC
+            let mut registers = exchange(&mut stream, "g");
+            registers.replace_range(36..40, "0402");
+
+            assert_eq!(exchange(&mut stream, &format!("G{}", registers)), "OK");
+            assert_eq!(exchange(&mut stream, "G00"), "E01");
+
+            // The stack pointer stays within the stack
+            registers.replace_range(40..42, &format!("{:02x}", cpu::STACK_SIZE));
+
+            assert_eq!(exchange(&mut stream, &format!("G{}", registers)), "E01");
+            assert_eq!(exchange(&mut stream, "D"), "OK");
+        });
+
+        let mut stub = GdbStub::accept(&listener).unwrap();
+        let mut frame_count = 0;
+
+        while stub.execute_step(&mut state, cpu::DELAY_TIMER_PERIOD_MS).unwrap() == GdbSession::Attached {
+            frame_count += 1;
+        }
+
+        client.join().unwrap();
+
+        assert!(frame_count > 0);
+        assert_eq!(state.pc, 0x204);
+        assert_eq!(state.memory[0x300..0x302], [0xBE, 0xEF]);
+
+        // SUBCASE: faults are reported as signals
+        {
+            let mut state = cpu::create_chip8_state();
+
+            // RET with an empty stack
+            execution::load_program(&mut state, vec![0x00, 0xEE]).unwrap();
+
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let address = listener.local_addr().unwrap();
+
+            let client = thread::spawn(move || {
+                let mut stream = TcpStream::connect(address).unwrap();
+
+                assert_eq!(exchange(&mut stream, "c"), "S0b");
+                assert_eq!(exchange(&mut stream, "?"), "S0b");
+
+                stream.write_all(&encode_packet("k")).unwrap();
+            });
+
+            let mut stub = GdbStub::accept(&listener).unwrap();
+
+            while stub.execute_step(&mut state, cpu::DELAY_TIMER_PERIOD_MS).unwrap() == GdbSession::Attached {}
+
+            client.join().unwrap();
+        }
+    }
+}
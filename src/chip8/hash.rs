@@ -0,0 +1,12 @@
+// 64-bit FNV-1a, stable across hosts and toolchains unlike std's hashers.
+pub fn fnv1a_64(bytes: &[u8]) -> u64
+{
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+
+    for &byte in bytes {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+
+    hash
+}
@@ -1,17 +1,19 @@
 use super::{
+    config::{IndexIncrement, Platform},
     cpu,
     cpu::CPUState,
     cpu::VRegisterName::*,
     display,
+    fault::Chip8Fault,
     keyboard,
     memory,
     memory::MemoryUsage,
+    opcode,
     opcode::OpCode,
+    random,
 };
 
-use rand::prelude::*;
-
-pub fn execute_instruction_internal(state: &mut cpu::CPUState, instruction: OpCode)
+pub fn execute_instruction_internal(state: &mut cpu::CPUState, instruction: OpCode) -> Result<(), Chip8Fault>
 {
     match instruction {
         OpCode::CLS => execute_cls(state),
@@ -49,125 +51,217 @@ pub fn execute_instruction_internal(state: &mut cpu::CPUState, instruction: OpCo
         OpCode::LDB{reg} => execute_ldb(state, reg),
         OpCode::LDAI{reg} => execute_ldai(state, reg),
         OpCode::LDM{reg} => execute_ldm(state, reg),
+        OpCode::SCD{size} => execute_scd(state, size),
+        OpCode::SCR => execute_scr(state),
+        OpCode::SCL => execute_scl(state),
+        OpCode::EXIT => execute_exit(state),
+        OpCode::LOW => execute_low(state),
+        OpCode::HIGH => execute_high(state),
+        OpCode::LDHF{reg} => execute_ldhf(state, reg),
+        OpCode::LDR{reg} => execute_ldr(state, reg),
+        OpCode::LDVR{reg} => execute_ldvr(state, reg),
+        OpCode::SCU{size} => execute_scu(state, size),
+        OpCode::SAVE{reg_x, reg_y} => execute_save(state, reg_x, reg_y),
+        OpCode::LOAD{reg_x, reg_y} => execute_load(state, reg_x, reg_y),
+        OpCode::LDIL => execute_ldil(state),
+        OpCode::PLANE{mask} => execute_plane(state, mask),
+        OpCode::AUDIO => execute_audio(state),
+        OpCode::PITCH{reg} => execute_pitch(state, reg),
+    }
+}
+
+// NOTE: XO-CHIP mixes 2 and 4-byte instructions, so unaligned jumps are allowed there.
+fn check_jump_address(state: &CPUState, address: u16) -> Result<(), Chip8Fault>
+{
+    if (address & 0x0001) != 0 && state.platform != Platform::XoChip {
+        return Err(Chip8Fault::UnalignedJump { addr: address });
+    }
+
+    memory::check_memory_range(state, address, 2, MemoryUsage::Execute)
+}
+
+// Every instruction that moves PC goes through here, so that PC isn't moved again after it,
+// even when the new PC is the address of the instruction itself.
+fn set_pc(state: &mut CPUState, pc: u16)
+{
+    state.pc = pc;
+    state.is_pc_written = true;
+}
+
+// Move PC past the next instruction, which takes 4 bytes for XO-CHIP's F000 nnnn.
+fn skip_next_instruction(state: &mut CPUState) -> Result<(), Chip8Fault>
+{
+    let next_pc = state.pc as usize + 2;
+    let is_next_instruction_long = state.platform == Platform::XoChip
+        && next_pc + 1 < state.memory.len()
+        && (u16::from(state.memory[next_pc]) << 8 | u16::from(state.memory[next_pc + 1])) == opcode::LONG_INSTRUCTION_PREFIX;
+    let skip_size_in_bytes: usize = if is_next_instruction_long { 4 } else { 2 };
+
+    // Current instruction, skipped one and the one we land on
+    memory::check_memory_range(state, state.pc, 2 + skip_size_in_bytes + 2, MemoryUsage::Execute)?;
+
+    set_pc(state, state.pc + 2 + skip_size_in_bytes as u16);
+
+    Ok(())
+}
+
+fn increment_index_after_load_store(state: &mut CPUState, register_index_max: usize)
+{
+    // The memory range was validated beforehand so this can't overflow.
+    state.i += match state.quirks.load_store_increment {
+        IndexIncrement::Unchanged => 0,
+        IndexIncrement::ByX => register_index_max as u16,
+        IndexIncrement::ByXPlusOne => register_index_max as u16 + 1,
+    };
+}
+
+fn rpl_flag_count(state: &CPUState) -> usize
+{
+    if state.platform == Platform::XoChip { cpu::RPL_FLAG_COUNT } else { cpu::SUPER_CHIP_RPL_FLAG_COUNT }
+}
+
+fn check_key(key: keyboard::KeyID) -> Result<(), Chip8Fault>
+{
+    if key >= keyboard::KEY_ID_COUNT {
+        return Err(Chip8Fault::InvalidKey { key });
     }
+
+    Ok(())
 }
 
 // Clear the display.
-pub fn execute_cls(state: &mut CPUState)
+pub fn execute_cls(state: &mut CPUState) -> Result<(), Chip8Fault>
 {
-    state.screen = vec![vec![0; cpu::SCREEN_LINE_SIZE_IN_BYTES]; cpu::SCREEN_HEIGHT];
+    display::clear_screen(state);
+
+    Ok(())
 }
 
 // Return from a subroutine.
 // The interpreter sets the program counter to the address at the top of the stack,
 // then subtracts 1 from the stack pointer.
-pub fn execute_ret(state: &mut CPUState)
+pub fn execute_ret(state: &mut CPUState) -> Result<(), Chip8Fault>
 {
-    assert!(state.sp > 0); // Stack Underflow
+    if state.sp == 0 {
+        return Err(Chip8Fault::StackUnderflow);
+    }
+
+    let next_pc_value: u16 = state.stack[state.sp as usize].wrapping_add(2);
+    memory::check_memory_range(state, next_pc_value, 2, MemoryUsage::Execute)?;
 
-    let next_pc_value: u16 = state.stack[state.sp as usize] + 2;
-    assert!(memory::is_valid_memory_range(next_pc_value, 2, MemoryUsage::Execute));
+    set_pc(state, next_pc_value);
+    state.sp -= 1;
 
-    state.pc = next_pc_value;
-    state.sp = if state.sp > 0 { state.sp - 1 } else { state.sp };
+    Ok(())
 }
 
 // Jump to a machine code routine at nnn.
 // This instruction is only used on the old computers on which Chip-8 was originally implemented.
 // NOTE: We choose to ignore it since we don't load any code into system memory.
-pub fn execute_sys(_state: &mut CPUState, _address: u16)
+pub fn execute_sys(_state: &mut CPUState, _address: u16) -> Result<(), Chip8Fault>
 {
     // noop
+    Ok(())
 }
 
 // Jump to location nnn.
 // The interpreter sets the program counter to nnn.
-pub fn execute_jp(state: &mut CPUState, address: u16)
+pub fn execute_jp(state: &mut CPUState, address: u16) -> Result<(), Chip8Fault>
 {
-    assert!((address & 0x0001) == 0); // Unaligned address
-    assert!(memory::is_valid_memory_range(address, 2, MemoryUsage::Execute));
+    check_jump_address(state, address)?;
+
+    set_pc(state, address);
 
-    state.pc = address;
+    Ok(())
 }
 
 // Call subroutine at nnn.
 // The interpreter increments the stack pointer, then puts the current PC on the top of the stack.
 // The PC is then set to nnn.
-pub fn execute_call(state: &mut CPUState, address: u16)
+pub fn execute_call(state: &mut CPUState, address: u16) -> Result<(), Chip8Fault>
 {
-    assert!((address & 0x0001) == 0); // Unaligned address
-    assert!(memory::is_valid_memory_range(address, 2, MemoryUsage::Execute));
+    check_jump_address(state, address)?;
 
-    assert!((state.sp as usize) < cpu::STACK_SIZE); // Stack overflow
+    // NOTE: The stack slot at index 0 is never used.
+    if (state.sp as usize) + 1 >= cpu::STACK_SIZE {
+        return Err(Chip8Fault::StackOverflow);
+    }
 
-    state.sp = if (state.sp as usize) < cpu::STACK_SIZE { state.sp + 1 } else { state.sp }; // Increment sp
+    state.sp += 1; // Increment sp
     state.stack[state.sp as usize] = state.pc; // Put PC on top of the stack
-    state.pc = address; // Set PC to new address
+    set_pc(state, address); // Set PC to new address
+
+    Ok(())
 }
 
 // Skip next instruction if Vx = kk.
 // The interpreter compares register Vx to kk, and if they are equal,
 // increments the program counter by 2.
-pub fn execute_se(state: &mut CPUState, register_name: u8, value: u8)
+pub fn execute_se(state: &mut CPUState, register_name: u8, value: u8) -> Result<(), Chip8Fault>
 {
     assert!((register_name & !0x0F) == 0); // Invalid register
-    assert!(memory::is_valid_memory_range(state.pc, 6, MemoryUsage::Execute));
 
     let register_value: u8 = state.v_registers[register_name as usize];
 
     if register_value == value {
-        state.pc += 4;
+        skip_next_instruction(state)?;
     }
+
+    Ok(())
 }
 
 // Skip next instruction if Vx != kk.
 // The interpreter compares register Vx to kk, and if they are not equal,
 // increments the program counter by 2.
-pub fn execute_sne(state: &mut CPUState, register_name: u8, value: u8)
+pub fn execute_sne(state: &mut CPUState, register_name: u8, value: u8) -> Result<(), Chip8Fault>
 {
     let register_value: u8 = state.v_registers[register_name as usize];
 
     assert!((register_name & !0x0F) == 0); // Invalid register
-    assert!(memory::is_valid_memory_range(state.pc, 6, MemoryUsage::Execute));
 
     if register_value != value {
-        state.pc += 4;
+        skip_next_instruction(state)?;
     }
+
+    Ok(())
 }
 
 // Skip next instruction if Vx = Vy.
 // The interpreter compares register Vx to register Vy, and if they are equal,
 // increments the program counter by 2.
-pub fn execute_se2(state: &mut CPUState, register_lhs: u8, register_rhs: u8)
+pub fn execute_se2(state: &mut CPUState, register_lhs: u8, register_rhs: u8) -> Result<(), Chip8Fault>
 {
     assert!((register_lhs & !0x0F) == 0); // Invalid register
     assert!((register_rhs & !0x0F) == 0); // Invalid register
-    assert!(memory::is_valid_memory_range(state.pc, 6, MemoryUsage::Execute));
 
     let register_value_lhs: u8 = state.v_registers[register_lhs as usize];
     let register_value_rhs: u8 = state.v_registers[register_rhs as usize];
 
     if register_value_lhs == register_value_rhs {
-        state.pc += 4;
+        skip_next_instruction(state)?;
     }
+
+    Ok(())
 }
 
 // Set Vx = kk.
 // The interpreter puts the value kk into register Vx.
-pub fn execute_ld(state: &mut CPUState, register_name: u8, value: u8)
+pub fn execute_ld(state: &mut CPUState, register_name: u8, value: u8) -> Result<(), Chip8Fault>
 {
     assert!((register_name & !0x0F) == 0); // Invalid register
 
     let register_name = register_name as usize;
 
     state.v_registers[register_name] = value;
+
+    Ok(())
 }
 
 // Set Vx = Vx + kk.
 // Adds the value kk to the value of register Vx, then stores the result in Vx.
 // NOTE: Carry in NOT set.
 // NOTE: Overflows will just wrap the value around.
-pub fn execute_add(state: &mut CPUState, register_name: u8, value: u8)
+pub fn execute_add(state: &mut CPUState, register_name: u8, value: u8) -> Result<(), Chip8Fault>
 {
     assert!((register_name & !0x0F) == 0); // Invalid register
 
@@ -177,11 +271,13 @@ pub fn execute_add(state: &mut CPUState, register_name: u8, value: u8)
     let sum: u8 = register_value.wrapping_add(value);
 
     state.v_registers[register_name] = sum;
+
+    Ok(())
 }
 
 // Set Vx = Vy.
 // Stores the value of register Vy in register Vx.
-pub fn execute_ld2(state: &mut CPUState, register_lhs: u8, register_rhs: u8)
+pub fn execute_ld2(state: &mut CPUState, register_lhs: u8, register_rhs: u8) -> Result<(), Chip8Fault>
 {
     assert!((register_lhs & !0x0F) == 0); // Invalid register
     assert!((register_rhs & !0x0F) == 0); // Invalid register
@@ -190,13 +286,15 @@ pub fn execute_ld2(state: &mut CPUState, register_lhs: u8, register_rhs: u8)
     let register_rhs = register_rhs as usize;
 
     state.v_registers[register_lhs] = state.v_registers[register_rhs];
+
+    Ok(())
 }
 
 // Set Vx = Vx OR Vy.
 // Performs a bitwise OR on the values of Vx and Vy, then stores the result in Vx.
 // A bitwise OR compares the corrseponding bits from two values, and if either bit is 1,
 // then the same bit in the result is also 1. Otherwise, it is 0.
-pub fn execute_or(state: &mut CPUState, register_lhs: u8, register_rhs: u8)
+pub fn execute_or(state: &mut CPUState, register_lhs: u8, register_rhs: u8) -> Result<(), Chip8Fault>
 {
     assert!((register_lhs & !0x0F) == 0); // Invalid register
     assert!((register_rhs & !0x0F) == 0); // Invalid register
@@ -205,13 +303,19 @@ pub fn execute_or(state: &mut CPUState, register_lhs: u8, register_rhs: u8)
     let register_rhs = register_rhs as usize;
 
     state.v_registers[register_lhs] |= state.v_registers[register_rhs];
+
+    if state.quirks.logic_resets_vf {
+        state.v_registers[VF as usize] = 0;
+    }
+
+    Ok(())
 }
 
 // Set Vx = Vx AND Vy.
 // Performs a bitwise AND on the values of Vx and Vy, then stores the result in Vx.
 // A bitwise AND compares the corrseponding bits from two values, and if both bits are 1,
 // then the same bit in the result is also 1. Otherwise, it is 0.
-pub fn execute_and(state: &mut CPUState, register_lhs: u8, register_rhs: u8)
+pub fn execute_and(state: &mut CPUState, register_lhs: u8, register_rhs: u8) -> Result<(), Chip8Fault>
 {
     assert!((register_lhs & !0x0F) == 0); // Invalid register
     assert!((register_rhs & !0x0F) == 0); // Invalid register
@@ -220,13 +324,19 @@ pub fn execute_and(state: &mut CPUState, register_lhs: u8, register_rhs: u8)
     let register_rhs = register_rhs as usize;
 
     state.v_registers[register_lhs] &= state.v_registers[register_rhs];
+
+    if state.quirks.logic_resets_vf {
+        state.v_registers[VF as usize] = 0;
+    }
+
+    Ok(())
 }
 
 // Set Vx = Vx XOR Vy.
 // Performs a bitwise exclusive OR on the values of Vx and Vy, then stores the result in Vx.
 // An exclusive OR compares the corrseponding bits from two values, and if the bits are not both the same,
 // then the corresponding bit in the result is set to 1.  Otherwise, it is 0.
-pub fn execute_xor(state: &mut CPUState, register_lhs: u8, register_rhs: u8)
+pub fn execute_xor(state: &mut CPUState, register_lhs: u8, register_rhs: u8) -> Result<(), Chip8Fault>
 {
     assert!((register_lhs & !0x0F) == 0); // Invalid register
     assert!((register_rhs & !0x0F) == 0); // Invalid register
@@ -235,13 +345,19 @@ pub fn execute_xor(state: &mut CPUState, register_lhs: u8, register_rhs: u8)
     let register_rhs = register_rhs as usize;
 
     state.v_registers[register_lhs] ^= state.v_registers[register_rhs];
+
+    if state.quirks.logic_resets_vf {
+        state.v_registers[VF as usize] = 0;
+    }
+
+    Ok(())
 }
 
 // Set Vx = Vx + Vy, set VF = carry.
 // The values of Vx and Vy are added together.
 // If the result is greater than 8 bits (i.e., > 255,) VF is set to 1, otherwise 0.
 // Only the lowest 8 bits of the result are kept, and stored in Vx.
-pub fn execute_add2(state: &mut CPUState, register_lhs: u8, register_rhs: u8)
+pub fn execute_add2(state: &mut CPUState, register_lhs: u8, register_rhs: u8) -> Result<(), Chip8Fault>
 {
     assert!((register_lhs & !0x0F) == 0); // Invalid register
     assert!((register_rhs & !0x0F) == 0); // Invalid register
@@ -251,16 +367,18 @@ pub fn execute_add2(state: &mut CPUState, register_lhs: u8, register_rhs: u8)
 
     let value_lhs: u8 = state.v_registers[register_lhs];
     let value_rhs: u8 = state.v_registers[register_rhs];
-    let result: u8 = value_lhs.wrapping_add(value_rhs);
+    let (result, has_carry) = value_lhs.overflowing_add(value_rhs);
 
     state.v_registers[register_lhs] = result;
-    state.v_registers[VF as usize] = if result > value_lhs { 0 } else { 1 }; // Set carry
+    state.v_registers[VF as usize] = has_carry as u8; // Set carry
+
+    Ok(())
 }
 
 // Set Vx = Vx - Vy, set VF = NOT borrow.
-// If Vx > Vy, then VF is set to 1, otherwise 0.
+// If Vx >= Vy, then VF is set to 1, otherwise 0.
 // Then Vy is subtracted from Vx, and the results stored in Vx.
-pub fn execute_sub(state: &mut CPUState, register_lhs: u8, register_rhs: u8)
+pub fn execute_sub(state: &mut CPUState, register_lhs: u8, register_rhs: u8) -> Result<(), Chip8Fault>
 {
     assert!((register_lhs & !0x0F) == 0); // Invalid register
     assert!((register_rhs & !0x0F) == 0); // Invalid register
@@ -273,29 +391,36 @@ pub fn execute_sub(state: &mut CPUState, register_lhs: u8, register_rhs: u8)
     let result: u8 = value_lhs.wrapping_sub(value_rhs);
 
     state.v_registers[register_lhs] = result;
-    state.v_registers[VF as usize] = if value_lhs > value_rhs { 1 } else { 0 }; // Set carry
+    state.v_registers[VF as usize] = if value_lhs >= value_rhs { 1 } else { 0 }; // Set carry
+
+    Ok(())
 }
 
 // Set Vx = Vx SHR 1.
 // If the least-significant bit of Vx is 1, then VF is set to 1, otherwise 0.
 // Then Vx is divided by 2.
-// NOTE: register_rhs is just ignored apparently.
-pub fn execute_shr(state: &mut CPUState, register_lhs: u8, _register_rhs: u8)
+// NOTE: register_rhs is ignored unless the shift quirk is enabled, in which case Vy is shifted instead.
+pub fn execute_shr(state: &mut CPUState, register_lhs: u8, register_rhs: u8) -> Result<(), Chip8Fault>
 {
     assert!((register_lhs & !0x0F) == 0); // Invalid register
+    assert!((register_rhs & !0x0F) == 0); // Invalid register
 
     let register_lhs = register_lhs as usize;
+    let register_rhs = register_rhs as usize;
 
-    let value_lhs: u8 = state.v_registers[register_lhs];
+    let register_src = if state.quirks.shift_uses_vy { register_rhs } else { register_lhs };
+    let value_src: u8 = state.v_registers[register_src];
 
-    state.v_registers[register_lhs] = value_lhs >> 1;
-    state.v_registers[VF as usize] = value_lhs & 0x01; // Set carry
+    state.v_registers[register_lhs] = value_src >> 1;
+    state.v_registers[VF as usize] = value_src & 0x01; // Set carry
+
+    Ok(())
 }
 
 // Set Vx = Vy - Vx, set VF = NOT borrow.
-// If Vy > Vx, then VF is set to 1, otherwise 0.
+// If Vy >= Vx, then VF is set to 1, otherwise 0.
 // Then Vx is subtracted from Vy, and the results stored in Vx.
-pub fn execute_subn(state: &mut CPUState, register_lhs: u8, register_rhs: u8)
+pub fn execute_subn(state: &mut CPUState, register_lhs: u8, register_rhs: u8) -> Result<(), Chip8Fault>
 {
     assert!((register_lhs & !0x0F) == 0); // Invalid register
     assert!((register_rhs & !0x0F) == 0); // Invalid register
@@ -308,31 +433,37 @@ pub fn execute_subn(state: &mut CPUState, register_lhs: u8, register_rhs: u8)
     let result: u8 = value_rhs.wrapping_sub(value_lhs);
 
     state.v_registers[register_lhs] = result;
-    state.v_registers[VF as usize] = if value_rhs > value_lhs { 1 } else { 0 }; // Set carry
+    state.v_registers[VF as usize] = if value_rhs >= value_lhs { 1 } else { 0 }; // Set carry
+
+    Ok(())
 }
 
 // Set Vx = Vx SHL 1.
 // If the most-significant bit of Vx is 1, then VF is set to 1, otherwise to 0. Then Vx is multiplied by 2.
-// NOTE: register_rhs is just ignored apparently.
-pub fn execute_shl(state: &mut CPUState, register_lhs: u8, _register_rhs: u8)
+// NOTE: register_rhs is ignored unless the shift quirk is enabled, in which case Vy is shifted instead.
+pub fn execute_shl(state: &mut CPUState, register_lhs: u8, register_rhs: u8) -> Result<(), Chip8Fault>
 {
     assert!((register_lhs & !0x0F) == 0); // Invalid register
+    assert!((register_rhs & !0x0F) == 0); // Invalid register
 
     let register_lhs = register_lhs as usize;
+    let register_rhs = register_rhs as usize;
 
-    let value_lhs: u8 = state.v_registers[register_lhs];
+    let register_src = if state.quirks.shift_uses_vy { register_rhs } else { register_lhs };
+    let value_src: u8 = state.v_registers[register_src];
+
+    state.v_registers[register_lhs] = value_src << 1;
+    state.v_registers[VF as usize] = if (value_src & 0x80) != 0 { 1 } else { 0 }; // Set carry
 
-    state.v_registers[register_lhs] = value_lhs << 1;
-    state.v_registers[VF as usize] = if (value_lhs & 0x80) != 0 { 1 } else { 0 }; // Set carry
+    Ok(())
 }
 
 // Skip next instruction if Vx != Vy.
 // The values of Vx and Vy are compared, and if they are not equal, the program counter is increased by 2.
-pub fn execute_sne2(state: &mut CPUState, register_lhs: u8, register_rhs: u8)
+pub fn execute_sne2(state: &mut CPUState, register_lhs: u8, register_rhs: u8) -> Result<(), Chip8Fault>
 {
     assert!((register_lhs & !0x0F) == 0); // Invalid register
     assert!((register_rhs & !0x0F) == 0); // Invalid register
-    assert!(memory::is_valid_memory_range(state.pc, 6, MemoryUsage::Execute));
 
     let register_lhs = register_lhs as usize;
     let register_rhs = register_rhs as usize;
@@ -341,41 +472,50 @@ pub fn execute_sne2(state: &mut CPUState, register_lhs: u8, register_rhs: u8)
     let value_rhs: u8 = state.v_registers[register_rhs];
 
     if value_lhs != value_rhs {
-        state.pc += 4;
+        skip_next_instruction(state)?;
     }
+
+    Ok(())
 }
 
 // Set I = nnn.
 // The value of register I is set to nnn.
-pub fn execute_ldi(state: &mut CPUState, address: u16)
+pub fn execute_ldi(state: &mut CPUState, address: u16) -> Result<(), Chip8Fault>
 {
     state.i = address;
+
+    Ok(())
 }
 
 // Jump to location nnn + V0.
 // The program counter is set to nnn plus the value of V0.
-pub fn execute_jp2(state: &mut CPUState, base_address: u16)
+// NOTE: With the jump quirk, the highest nibble of nnn selects the offset register instead of V0.
+pub fn execute_jp2(state: &mut CPUState, base_address: u16) -> Result<(), Chip8Fault>
 {
-    let offset = u16::from(state.v_registers[V0 as usize]);
+    let register_name = if state.quirks.jump_uses_vx { (base_address >> 8) & 0x0F } else { V0 as u16 };
+    let offset = u16::from(state.v_registers[register_name as usize]);
     let jump_address: u16 = base_address + offset;
 
-    assert!((jump_address & 0x0001) == 0); // Unaligned address
-    assert!(memory::is_valid_memory_range(jump_address, 2, MemoryUsage::Execute));
+    check_jump_address(state, jump_address)?;
+
+    set_pc(state, jump_address);
 
-    state.pc = jump_address;
+    Ok(())
 }
 
 // Set Vx = random byte AND kk.
 // The interpreter generates a random number from 0 to 255, which is then ANDed with the value kk.
 // The results are stored in Vx. See instruction 8xy2 for more information on AND.
-pub fn execute_rnd(state: &mut CPUState, register_name: u8, value: u8)
+pub fn execute_rnd(state: &mut CPUState, register_name: u8, value: u8) -> Result<(), Chip8Fault>
 {
     assert!((register_name & !0x0F) == 0); // Invalid register
 
     let register_name = register_name as usize;
 
-    let random_value: u8 = rand::thread_rng().gen();
+    let random_value: u8 = random::next_random_byte(state);
     state.v_registers[register_name] = random_value & value;
+
+    Ok(())
 }
 
 // Display n-byte sprite starting at memory location I at (Vx, Vy), set VF = collision.
@@ -386,90 +526,144 @@ pub fn execute_rnd(state: &mut CPUState, register_name: u8, value: u8)
 // If the sprite is positioned so part of it is outside the coordinates of the display,
 // it wraps around to the opposite side of the screen. See instruction 8xy3 for more information on XOR,
 // and section 2.4, Display, for more information on the Chip-8 screen and sprites.
-pub fn execute_drw(state: &mut CPUState, register_lhs: u8, register_rhs: u8, size: u8)
+// NOTE: With the clipping quirk, only the sprite origin wraps around and the rest is cut at the edges.
+// NOTE: With the display wait quirk, execution stops until the next frame starts.
+// NOTE: On SUPER-CHIP, a size of 0 draws a 16x16 sprite.
+// NOTE: On XO-CHIP, a sprite is drawn on each selected plane, reading data for each plane in turn.
+pub fn execute_drw(state: &mut CPUState, register_lhs: u8, register_rhs: u8, size: u8) -> Result<(), Chip8Fault>
 {
     assert!((register_lhs & !0x0F) == 0); // Invalid register
     assert!((register_rhs & !0x0F) == 0); // Invalid register
-    assert!(memory::is_valid_memory_range(state.i, size as usize, MemoryUsage::Read));
+
+    let is_large_sprite = size == 0 && state.platform != Platform::Chip8;
+    let sprite_width: usize = if is_large_sprite { 16 } else { 8 };
+    let sprite_height: usize = if is_large_sprite { 16 } else { size as usize };
+    let sprite_row_size_in_bytes = sprite_width / 8;
+    let sprite_size_in_bytes = sprite_height * sprite_row_size_in_bytes;
+
+    // Each selected plane gets its own sprite, stored one after the other.
+    let selected_planes: Vec<usize> = (0..cpu::SCREEN_PLANE_COUNT)
+        .filter(|&plane| display::is_plane_selected(state, plane))
+        .collect();
+
+    if sprite_size_in_bytes > 0 && !selected_planes.is_empty() {
+        memory::check_memory_range(state, state.i, sprite_size_in_bytes * selected_planes.len(), MemoryUsage::Read)?;
+    }
 
     let register_lhs = register_lhs as usize;
     let register_rhs = register_rhs as usize;
 
-    let sprite_start_x = state.v_registers[register_lhs] as usize;
-    let sprite_start_y = state.v_registers[register_rhs] as usize;
+    let screen_width = display::screen_width(state);
+    let screen_height = display::screen_height(state);
+
+    let sprite_start_x = state.v_registers[register_lhs] as usize % screen_width;
+    let sprite_start_y = state.v_registers[register_rhs] as usize % screen_height;
 
     let mut collision: bool = false;
 
-    // Sprites are made of rows of 1 byte each.
-    for row_index in 0..size
+    for (plane_index, &plane) in selected_planes.iter().enumerate()
     {
-        let sprite_address = (state.i + u16::from(row_index)) as usize;
-        let sprite_row: u8 = state.memory[sprite_address];
-        let screen_y = (sprite_start_y + row_index as usize) % cpu::SCREEN_HEIGHT;
+        let sprite_base_address = state.i as usize + plane_index * sprite_size_in_bytes;
 
-        for pixel_index in 0..8
+        // Sprites are made of rows of 1 byte each, or 2 bytes for large sprites.
+        for row_index in 0..sprite_height
         {
-            let sprite_pixel_value = ((sprite_row >> (7 - pixel_index)) & 0x1) != 0;
-            let screen_x: usize = (sprite_start_x + pixel_index) % cpu::SCREEN_WIDTH;
+            let sprite_address = sprite_base_address + row_index * sprite_row_size_in_bytes;
+            let sprite_row: u16 = if is_large_sprite {
+                u16::from(state.memory[sprite_address]) << 8 | u16::from(state.memory[sprite_address + 1])
+            } else {
+                u16::from(state.memory[sprite_address]) << 8
+            };
+            let screen_y = sprite_start_y + row_index;
+
+            if state.quirks.clip_sprites && screen_y >= screen_height {
+                break;
+            }
 
-            let screen_pixel_value = display::read_screen_pixel(state, screen_x, screen_y);
+            let screen_y = screen_y % screen_height;
 
-            let result = screen_pixel_value ^ sprite_pixel_value;
+            for pixel_index in 0..sprite_width
+            {
+                let sprite_pixel_value = ((sprite_row >> (15 - pixel_index)) & 0x1) != 0;
+                let screen_x: usize = sprite_start_x + pixel_index;
 
-            // A pixel was erased
-            if screen_pixel_value && !result {
-                collision = true;
-            }
+                if state.quirks.clip_sprites && screen_x >= screen_width {
+                    break;
+                }
+
+                let screen_x = screen_x % screen_width;
+
+                let screen_pixel_value = display::read_plane_pixel(state, plane, screen_x, screen_y);
 
-            display::write_screen_pixel(state, screen_x, screen_y, result);
+                let result = screen_pixel_value ^ sprite_pixel_value;
+
+                // A pixel was erased
+                if screen_pixel_value && !result {
+                    collision = true;
+                }
+
+                display::write_plane_pixel(state, plane, screen_x, screen_y, result);
+            }
         }
     }
 
     state.v_registers[VF as usize] = if collision { 1 } else { 0 };
+
+    if state.quirks.display_wait {
+        state.is_waiting_for_display = true;
+    }
+
+    Ok(())
 }
 
 // Skip next instruction if key with the value of Vx is pressed.
 // Checks the keyboard, and if the key corresponding to the value of Vx is currently in the down position,
 // PC is increased by 2.
-pub fn execute_skp(state: &mut CPUState, register_name: u8)
+pub fn execute_skp(state: &mut CPUState, register_name: u8) -> Result<(), Chip8Fault>
 {
     assert!((register_name & !0x0F) == 0); // Invalid register
-    assert!(memory::is_valid_memory_range(state.pc, 6, MemoryUsage::Execute));
 
     let key_id: u8 = state.v_registers[register_name as usize];
+    check_key(key_id)?;
 
     if keyboard::is_key_pressed(state, key_id) {
-        state.pc += 4;
+        skip_next_instruction(state)?;
     }
+
+    Ok(())
 }
 
 // Skip next instruction if key with the value of Vx is not pressed.
 // Checks the keyboard, and if the key corresponding to the value of Vx is currently in the up position,
 // PC is increased by 2.
-pub fn execute_sknp(state: &mut CPUState, register_name: u8)
+pub fn execute_sknp(state: &mut CPUState, register_name: u8) -> Result<(), Chip8Fault>
 {
     assert!((register_name & !0x0F) == 0); // Invalid register
-    assert!(memory::is_valid_memory_range(state.pc, 6, MemoryUsage::Execute));
 
     let key: keyboard::KeyID = state.v_registers[register_name as usize];
+    check_key(key)?;
 
     if !keyboard::is_key_pressed(state, key) {
-        state.pc += 4;
+        skip_next_instruction(state)?;
     }
+
+    Ok(())
 }
 
 // Set Vx = delay timer value.
 // The value of DT is placed into Vx.
-pub fn execute_ldt(state: &mut CPUState, register_name: u8)
+pub fn execute_ldt(state: &mut CPUState, register_name: u8) -> Result<(), Chip8Fault>
 {
     assert!((register_name & !0x0F) == 0); // Invalid register
 
     state.v_registers[register_name as usize] = state.delay_timer;
+
+    Ok(())
 }
 
 // Wait for a key press, store the value of the key in Vx.
 // All execution stops until a key is pressed, then the value of that key is stored in Vx.
-pub fn execute_ldk(state: &mut CPUState, register_name: u8)
+pub fn execute_ldk(state: &mut CPUState, register_name: u8) -> Result<(), Chip8Fault>
 {
     assert!((register_name & !0x0F) == 0); // Invalid register
 
@@ -484,64 +678,74 @@ pub fn execute_ldk(state: &mut CPUState, register_name: u8)
             state.is_waiting_for_key = false;
         }
     }
+
+    Ok(())
 }
 
 // Set delay timer = Vx.
 // DT is set equal to the value of Vx.
-pub fn execute_lddt(state: &mut CPUState, register_name: u8)
+pub fn execute_lddt(state: &mut CPUState, register_name: u8) -> Result<(), Chip8Fault>
 {
     assert!((register_name & !0x0F) == 0); // Invalid register
 
     state.delay_timer = state.v_registers[register_name as usize];
+
+    Ok(())
 }
 
 // Set sound timer = Vx.
 // ST is set equal to the value of Vx.
-pub fn execute_ldst(state: &mut CPUState, register_name: u8)
+pub fn execute_ldst(state: &mut CPUState, register_name: u8) -> Result<(), Chip8Fault>
 {
     assert!((register_name & !0x0F) == 0); // Invalid register
 
     state.sound_timer = state.v_registers[register_name as usize];
+
+    Ok(())
 }
 
 // Set I = I + Vx.
 // The values of I and Vx are added, and the results are stored in I.
 // NOTE: Carry in NOT set.
 // NOTE: Overflows will just wrap the value around.
-pub fn execute_addi(state: &mut CPUState, register_name: u8)
+pub fn execute_addi(state: &mut CPUState, register_name: u8) -> Result<(), Chip8Fault>
 {
     assert!((register_name & !0x0F) == 0); // Invalid register
 
     let register_value = u16::from(state.v_registers[register_name as usize]);
     let i_value: u16 = state.i;
-    let sum: u16 = i_value + register_value;
-
-    assert!(sum >= i_value); // Overflow
+    let sum: u16 = i_value.checked_add(register_value).ok_or(Chip8Fault::IndexOverflow)?;
 
     state.i = sum;
+
+    Ok(())
 }
 
 // Set I = location of sprite for digit Vx.
 // The value of I is set to the location for the hexadecimal sprite corresponding to the value of Vx.
 // See section 2.4, Display, for more information on the Chip-8 hexadecimal font.
-pub fn execute_ldf(state: &mut CPUState, register_name: u8)
+pub fn execute_ldf(state: &mut CPUState, register_name: u8) -> Result<(), Chip8Fault>
 {
     assert!((register_name & !0x0F) == 0); // Invalid register
 
     let glyph_index: u8 = state.v_registers[register_name as usize];
 
-    assert!((glyph_index & !0x0F) == 0); // Invalid index
+    if (glyph_index & !0x0F) != 0 {
+        return Err(Chip8Fault::InvalidGlyph { glyph: glyph_index });
+    }
 
     state.i = state.font_table_offsets[glyph_index as usize];
+
+    Ok(())
 }
 
 // Store BCD representation of Vx in memory locations I, I+1, and I+2.
 // The interpreter takes the decimal value of Vx, and places the hundreds digit in memory at location in I,
 // the tens digit at location I+1, and the ones digit at location I+2.
-pub fn execute_ldb(state: &mut CPUState, register_name: u8)
+pub fn execute_ldb(state: &mut CPUState, register_name: u8) -> Result<(), Chip8Fault>
 {
     assert!((register_name & !0x0F) == 0); // Invalid register
-    assert!(memory::is_valid_memory_range(state.i, 3, MemoryUsage::Write));
+    memory::check_memory_range(state, state.i, 3, MemoryUsage::Write)?;
 
     let register_value: u8 = state.v_registers[register_name as usize];
 
@@ -549,38 +753,249 @@ pub fn execute_ldb(state: &mut CPUState, register_name: u8)
     state.memory[ip]     = (register_value / 100) % 10;
     state.memory[ip + 1] = (register_value / 10) % 10;
     state.memory[ip + 2] = (register_value) % 10;
+
+    Ok(())
 }
 
 // Store registers V0 through Vx in memory starting at location I.
 // The interpreter copies the values of registers V0 through Vx into memory,
 // starting at the address in I.
-pub fn execute_ldai(state: &mut CPUState, register_name: u8)
+pub fn execute_ldai(state: &mut CPUState, register_name: u8) -> Result<(), Chip8Fault>
 {
     let register_index_max = register_name as usize;
 
     assert!((register_index_max & !0x0F) == 0); // Invalid register
-    assert!(memory::is_valid_memory_range(state.i, register_index_max + 1, MemoryUsage::Write));
+    memory::check_memory_range(state, state.i, register_index_max + 1, MemoryUsage::Write)?;
 
     for index in 0..=register_index_max {
         state.memory[state.i as usize + index] = state.v_registers[index];
     }
+
+    increment_index_after_load_store(state, register_index_max);
+
+    Ok(())
 }
 
 // Read registers V0 through Vx from memory starting at location I.
 // The interpreter reads values from memory starting at location I into registers V0 through Vx.
-pub fn execute_ldm(state: &mut CPUState, register_name: u8)
+pub fn execute_ldm(state: &mut CPUState, register_name: u8) -> Result<(), Chip8Fault>
 {
     let register_index_max = register_name as usize;
 
     assert!((register_index_max & !0x0F) == 0); // Invalid register
-    assert!(memory::is_valid_memory_range(state.i, register_index_max + 1, MemoryUsage::Read));
+    memory::check_memory_range(state, state.i, register_index_max + 1, MemoryUsage::Read)?;
 
     for index in 0..=register_index_max {
         state.v_registers[index] = state.memory[state.i as usize + index];
     }
+
+    increment_index_after_load_store(state, register_index_max);
+
+    Ok(())
+}
+
+// Scroll display n lines down.
+pub fn execute_scd(state: &mut CPUState, size: u8) -> Result<(), Chip8Fault>
+{
+    display::scroll_down(state, size as usize);
+
+    Ok(())
+}
+
+// Scroll display 4 pixels right.
+pub fn execute_scr(state: &mut CPUState) -> Result<(), Chip8Fault>
+{
+    display::scroll_right(state, 4);
+
+    Ok(())
+}
+
+// Scroll display 4 pixels left.
+pub fn execute_scl(state: &mut CPUState) -> Result<(), Chip8Fault>
+{
+    display::scroll_left(state, 4);
+
+    Ok(())
+}
+
+// Exit the interpreter.
+// NOTE: The machine is halted and will not execute anything else.
+pub fn execute_exit(state: &mut CPUState) -> Result<(), Chip8Fault>
+{
+    state.is_halted = true;
+
+    Ok(())
+}
+
+// Disable extended screen mode.
+pub fn execute_low(state: &mut CPUState) -> Result<(), Chip8Fault>
+{
+    display::set_hires(state, false);
+
+    Ok(())
+}
+
+// Enable extended screen mode for full-screen graphics.
+pub fn execute_high(state: &mut CPUState) -> Result<(), Chip8Fault>
+{
+    display::set_hires(state, true);
+
+    Ok(())
+}
+
+// Set I = location of the 10-byte sprite for digit Vx.
+// Same as Fx29 with the large font.
+pub fn execute_ldhf(state: &mut CPUState, register_name: u8) -> Result<(), Chip8Fault>
+{
+    assert!((register_name & !0x0F) == 0); // Invalid register
+
+    let glyph_index: u8 = state.v_registers[register_name as usize];
+
+    if (glyph_index & !0x0F) != 0 {
+        return Err(Chip8Fault::InvalidGlyph { glyph: glyph_index });
+    }
+
+    state.i = state.big_font_table_offsets[glyph_index as usize];
+
+    Ok(())
+}
+
+// Store V0 through Vx in RPL user flags.
+// NOTE: Only the first 8 registers can be saved on SUPER-CHIP.
+pub fn execute_ldr(state: &mut CPUState, register_name: u8) -> Result<(), Chip8Fault>
+{
+    let register_index_max = register_name as usize;
+
+    if register_index_max >= rpl_flag_count(state) {
+        return Err(Chip8Fault::InvalidOpcode);
+    }
+
+    state.rpl_flags[..=register_index_max].clone_from_slice(&state.v_registers[..=register_index_max]);
+
+    Ok(())
+}
+
+// Read V0 through Vx from RPL user flags.
+// NOTE: Only the first 8 registers can be restored on SUPER-CHIP.
+pub fn execute_ldvr(state: &mut CPUState, register_name: u8) -> Result<(), Chip8Fault>
+{
+    let register_index_max = register_name as usize;
+
+    if register_index_max >= rpl_flag_count(state) {
+        return Err(Chip8Fault::InvalidOpcode);
+    }
+
+    state.v_registers[..=register_index_max].clone_from_slice(&state.rpl_flags[..=register_index_max]);
+
+    Ok(())
+}
+
+// Scroll display n lines up.
+pub fn execute_scu(state: &mut CPUState, size: u8) -> Result<(), Chip8Fault>
+{
+    display::scroll_up(state, size as usize);
+
+    Ok(())
+}
+
+// Store registers Vx through Vy in memory starting at location I.
+// The registers are stored in reverse order if x > y. I is not modified.
+pub fn execute_save(state: &mut CPUState, register_lhs: u8, register_rhs: u8) -> Result<(), Chip8Fault>
+{
+    assert!((register_lhs & !0x0F) == 0); // Invalid register
+    assert!((register_rhs & !0x0F) == 0); // Invalid register
+
+    let register_indices = register_range(register_lhs, register_rhs);
+
+    memory::check_memory_range(state, state.i, register_indices.len(), MemoryUsage::Write)?;
+
+    for (offset, &index) in register_indices.iter().enumerate() {
+        state.memory[state.i as usize + offset] = state.v_registers[index];
+    }
+
+    Ok(())
+}
+
+// Read registers Vx through Vy from memory starting at location I.
+// The registers are loaded in reverse order if x > y. I is not modified.
+pub fn execute_load(state: &mut CPUState, register_lhs: u8, register_rhs: u8) -> Result<(), Chip8Fault>
+{
+    assert!((register_lhs & !0x0F) == 0); // Invalid register
+    assert!((register_rhs & !0x0F) == 0); // Invalid register
+
+    let register_indices = register_range(register_lhs, register_rhs);
+
+    memory::check_memory_range(state, state.i, register_indices.len(), MemoryUsage::Read)?;
+
+    for (offset, &index) in register_indices.iter().enumerate() {
+        state.v_registers[index] = state.memory[state.i as usize + offset];
+    }
+
+    Ok(())
+}
+
+fn register_range(register_lhs: u8, register_rhs: u8) -> Vec<usize>
+{
+    let register_lhs = register_lhs as usize;
+    let register_rhs = register_rhs as usize;
+
+    if register_lhs <= register_rhs {
+        (register_lhs..=register_rhs).collect()
+    } else {
+        (register_rhs..=register_lhs).rev().collect()
+    }
+}
+
+// Set I = nnnn, read from the word following the instruction.
+// The program counter is increased by 4 instead of 2.
+pub fn execute_ldil(state: &mut CPUState) -> Result<(), Chip8Fault>
+{
+    memory::check_memory_range(state, state.pc, 4, MemoryUsage::Execute)?;
+
+    let operand_address = state.pc as usize + 2;
+
+    state.i = u16::from(state.memory[operand_address]) << 8 | u16::from(state.memory[operand_address + 1]);
+    set_pc(state, state.pc.wrapping_add(4)); // Wraps like any other PC increment
+
+    Ok(())
+}
+
+// Select the planes affected by drawing, clearing and scrolling.
+// Bit 0 selects the first plane, bit 1 the second one.
+pub fn execute_plane(state: &mut CPUState, mask: u8) -> Result<(), Chip8Fault>
+{
+    assert!((mask & !0x03) == 0); // Invalid plane mask
+
+    state.selected_planes = mask;
+
+    Ok(())
+}
+
+// Load the 16-byte audio pattern buffer from memory starting at location I.
+pub fn execute_audio(state: &mut CPUState) -> Result<(), Chip8Fault>
+{
+    memory::check_memory_range(state, state.i, cpu::AUDIO_PATTERN_SIZE_IN_BYTES, MemoryUsage::Read)?;
+
+    let range_begin = state.i as usize;
+    let range_end = range_begin + cpu::AUDIO_PATTERN_SIZE_IN_BYTES;
+
+    state.audio_pattern.clone_from_slice(&state.memory[range_begin..range_end]);
+
+    Ok(())
+}
+
+// Set the audio pattern playback rate = Vx.
+pub fn execute_pitch(state: &mut CPUState, register_name: u8) -> Result<(), Chip8Fault>
+{
+    assert!((register_name & !0x0F) == 0); // Invalid register
+
+    state.audio_pitch = state.v_registers[register_name as usize];
+
+    Ok(())
 }
 
 #[cfg(test)]
+#[allow(clippy::identity_op)]
 mod tests {
     use super::*;
     use super::super::execution;
@@ -590,23 +1005,28 @@ mod tests {
         //SUBCASE("CLS")
         {
             let mut state = cpu::create_chip8_state();
-            state.screen[0][0] = 0b11001100;
-            state.screen[cpu::SCREEN_HEIGHT - 1][cpu::SCREEN_LINE_SIZE_IN_BYTES - 1] = 0b10101010;
+            state.screen[0][0][0] = 0b11001100;
+            state.screen[0][cpu::SCREEN_HEIGHT - 1][cpu::SCREEN_LINE_SIZE_IN_BYTES - 1] = 0b10101010;
 
-            execution::execute_instruction(&mut state, 0x00E0);
+            execution::execute_instruction(&mut state, 0x00E0).unwrap();
 
-            assert_eq!(state.screen[0][0], 0x00);
-            assert_eq!(state.screen[cpu::SCREEN_HEIGHT - 1][cpu::SCREEN_LINE_SIZE_IN_BYTES - 1], 0x00);
+            assert_eq!(state.screen[0][0][0], 0x00);
+            assert_eq!(state.screen[0][cpu::SCREEN_HEIGHT - 1][cpu::SCREEN_LINE_SIZE_IN_BYTES - 1], 0x00);
         }
 
         //SUBCASE("JP")
         {
             let mut state = cpu::create_chip8_state();
-            execution::execute_instruction(&mut state, 0x1240);
+            execution::execute_instruction(&mut state, 0x1240).unwrap();
 
             assert_eq!(state.pc, 0x0240);
 
-            execution::execute_instruction(&mut state, 0x1FFE);
+            execution::execute_instruction(&mut state, 0x1FFE).unwrap();
+
+            assert_eq!(state.pc, 0x0FFE);
+
+            // Jumping to itself keeps PC in place
+            execution::execute_instruction(&mut state, 0x1FFE).unwrap();
 
             assert_eq!(state.pc, 0x0FFE);
         }
@@ -614,22 +1034,22 @@ mod tests {
         //SUBCASE("CALL/RET")
         {
             let mut state = cpu::create_chip8_state();
-            execution::execute_instruction(&mut state, 0x2F00);
+            execution::execute_instruction(&mut state, 0x2F00).unwrap();
 
             assert_eq!(state.sp, 1);
             assert_eq!(state.pc, 0x0F00);
 
-            execution::execute_instruction(&mut state, 0x2A00);
+            execution::execute_instruction(&mut state, 0x2A00).unwrap();
 
             assert_eq!(state.sp, 2);
             assert_eq!(state.pc, 0x0A00);
 
-            execution::execute_instruction(&mut state, 0x00EE);
+            execution::execute_instruction(&mut state, 0x00EE).unwrap();
 
             assert_eq!(state.sp, 1);
             assert_eq!(state.pc, 0x0F02);
 
-            execution::execute_instruction(&mut state, 0x00EE);
+            execution::execute_instruction(&mut state, 0x00EE).unwrap();
 
             assert_eq!(state.sp, 0);
             assert_eq!(state.pc, cpu::MIN_PROGRAM_ADDRESS as u16 + 2);
@@ -638,7 +1058,7 @@ mod tests {
         //SUBCASE("SE")
         {
             let mut state = cpu::create_chip8_state();
-            execution::execute_instruction(&mut state, 0x3000);
+            execution::execute_instruction(&mut state, 0x3000).unwrap();
 
             assert_eq!(state.v_registers[V0 as usize], 0);
             assert_eq!(state.pc, cpu::MIN_PROGRAM_ADDRESS as u16 + 4);
@@ -647,7 +1067,7 @@ mod tests {
         //SUBCASE("SNE")
         {
             let mut state = cpu::create_chip8_state();
-            execution::execute_instruction(&mut state, 0x40FF);
+            execution::execute_instruction(&mut state, 0x40FF).unwrap();
 
             assert_eq!(state.v_registers[V0 as usize], 0);
             assert_eq!(state.pc, cpu::MIN_PROGRAM_ADDRESS as u16 + 4);
@@ -656,7 +1076,7 @@ mod tests {
         //SUBCASE("SE2")
         {
             let mut state = cpu::create_chip8_state();
-            execution::execute_instruction(&mut state, 0x5120);
+            execution::execute_instruction(&mut state, 0x5120).unwrap();
 
             assert_eq!(state.v_registers[V0 as usize], 0);
             assert_eq!(state.v_registers[V1 as usize], 0);
@@ -666,11 +1086,11 @@ mod tests {
         //SUBCASE("LD")
         {
             let mut state = cpu::create_chip8_state();
-            execution::execute_instruction(&mut state, 0x06042);
+            execution::execute_instruction(&mut state, 0x06042).unwrap();
 
             assert_eq!(state.v_registers[V0 as usize], 0x42);
 
-            execution::execute_instruction(&mut state, 0x06A33);
+            execution::execute_instruction(&mut state, 0x06A33).unwrap();
 
             assert_eq!(state.v_registers[VA as usize], 0x33);
         }
@@ -680,11 +1100,11 @@ mod tests {
             let mut state = cpu::create_chip8_state();
             assert_eq!(state.v_registers[V2 as usize], 0x00);
 
-            execution::execute_instruction(&mut state, 0x7203);
+            execution::execute_instruction(&mut state, 0x7203).unwrap();
 
             assert_eq!(state.v_registers[V2 as usize], 0x03);
 
-            execution::execute_instruction(&mut state, 0x7204);
+            execution::execute_instruction(&mut state, 0x7204).unwrap();
 
             assert_eq!(state.v_registers[V2 as usize], 0x07);
         }
@@ -695,7 +1115,7 @@ mod tests {
 
             state.v_registers[V3 as usize] = 32;
 
-            execution::execute_instruction(&mut state, 0x8030);
+            execution::execute_instruction(&mut state, 0x8030).unwrap();
 
             assert_eq!(state.v_registers[V0 as usize], 32);
         }
@@ -707,7 +1127,7 @@ mod tests {
             state.v_registers[VC as usize] = 0xF0;
             state.v_registers[VD as usize] = 0x0F;
 
-            execution::execute_instruction(&mut state, 0x8CD1);
+            execution::execute_instruction(&mut state, 0x8CD1).unwrap();
 
             assert_eq!(state.v_registers[VC as usize], 0xFF);
         }
@@ -719,14 +1139,14 @@ mod tests {
             state.v_registers[VC as usize] = 0xF0;
             state.v_registers[VD as usize] = 0x0F;
 
-            execution::execute_instruction(&mut state, 0x8CD2);
+            execution::execute_instruction(&mut state, 0x8CD2).unwrap();
 
             assert_eq!(state.v_registers[VC as usize], 0x00);
 
             state.v_registers[VC as usize] = 0xF0;
             state.v_registers[VD as usize] = 0xFF;
 
-            execution::execute_instruction(&mut state, 0x8CD2);
+            execution::execute_instruction(&mut state, 0x8CD2).unwrap();
 
             assert_eq!(state.v_registers[VC as usize], 0xF0);
         }
@@ -738,7 +1158,7 @@ mod tests {
             state.v_registers[VC as usize] = 0x10;
             state.v_registers[VD as usize] = 0x1F;
 
-            execution::execute_instruction(&mut state, 0x8CD3);
+            execution::execute_instruction(&mut state, 0x8CD3).unwrap();
 
             assert_eq!(state.v_registers[VC as usize], 0x0F);
         }
@@ -750,7 +1170,7 @@ mod tests {
             state.v_registers[V0 as usize] = 8;
             state.v_registers[V1 as usize] = 8;
 
-            execution::execute_instruction(&mut state, 0x8014);
+            execution::execute_instruction(&mut state, 0x8014).unwrap();
 
             assert_eq!(state.v_registers[V0 as usize], 16);
             assert_eq!(state.v_registers[VF as usize], 0);
@@ -758,10 +1178,26 @@ mod tests {
             state.v_registers[V0 as usize] = 128;
             state.v_registers[V1 as usize] = 130;
 
-            execution::execute_instruction(&mut state, 0x8014);
+            execution::execute_instruction(&mut state, 0x8014).unwrap();
 
             assert_eq!(state.v_registers[V0 as usize], 2);
             assert_eq!(state.v_registers[VF as usize], 1);
+
+            state.v_registers[V0 as usize] = 200;
+            state.v_registers[V1 as usize] = 0;
+
+            execution::execute_instruction(&mut state, 0x8014).unwrap();
+
+            assert_eq!(state.v_registers[V0 as usize], 200);
+            assert_eq!(state.v_registers[VF as usize], 0);
+
+            state.v_registers[V0 as usize] = 255;
+            state.v_registers[V1 as usize] = 1;
+
+            execution::execute_instruction(&mut state, 0x8014).unwrap();
+
+            assert_eq!(state.v_registers[V0 as usize], 0);
+            assert_eq!(state.v_registers[VF as usize], 1);
         }
 
         //SUBCASE("SUB")
@@ -771,7 +1207,7 @@ mod tests {
             state.v_registers[V0 as usize] = 8;
             state.v_registers[V1 as usize] = 7;
 
-            execution::execute_instruction(&mut state, 0x8015);
+            execution::execute_instruction(&mut state, 0x8015).unwrap();
 
             assert_eq!(state.v_registers[V0 as usize], 1);
             assert_eq!(state.v_registers[VF as usize], 1);
@@ -779,10 +1215,18 @@ mod tests {
             state.v_registers[V0 as usize] = 8;
             state.v_registers[V1 as usize] = 9;
 
-            execution::execute_instruction(&mut state, 0x8015);
+            execution::execute_instruction(&mut state, 0x8015).unwrap();
 
             assert_eq!(state.v_registers[V0 as usize], 255);
             assert_eq!(state.v_registers[VF as usize], 0);
+
+            state.v_registers[V0 as usize] = 9;
+            state.v_registers[V1 as usize] = 9;
+
+            execution::execute_instruction(&mut state, 0x8015).unwrap();
+
+            assert_eq!(state.v_registers[V0 as usize], 0);
+            assert_eq!(state.v_registers[VF as usize], 1);
         }
 
         //SUBCASE("SHR")
@@ -791,22 +1235,22 @@ mod tests {
 
             state.v_registers[V0 as usize] = 8;
 
-            execution::execute_instruction(&mut state, 0x8016);
+            execution::execute_instruction(&mut state, 0x8016).unwrap();
 
             assert_eq!(state.v_registers[V0 as usize], 4);
             assert_eq!(state.v_registers[VF as usize], 0);
 
-            execution::execute_instruction(&mut state, 0x8026);
+            execution::execute_instruction(&mut state, 0x8026).unwrap();
 
             assert_eq!(state.v_registers[V0 as usize], 2);
             assert_eq!(state.v_registers[VF as usize], 0);
 
-            execution::execute_instruction(&mut state, 0x8026);
+            execution::execute_instruction(&mut state, 0x8026).unwrap();
 
             assert_eq!(state.v_registers[V0 as usize], 1);
             assert_eq!(state.v_registers[VF as usize], 0);
 
-            execution::execute_instruction(&mut state, 0x8026);
+            execution::execute_instruction(&mut state, 0x8026).unwrap();
 
             assert_eq!(state.v_registers[V0 as usize], 0);
             assert_eq!(state.v_registers[VF as usize], 1);
@@ -819,7 +1263,7 @@ mod tests {
             state.v_registers[V0 as usize] = 7;
             state.v_registers[V1 as usize] = 8;
 
-            execution::execute_instruction(&mut state, 0x8017);
+            execution::execute_instruction(&mut state, 0x8017).unwrap();
 
             assert_eq!(state.v_registers[V0 as usize], 1);
             assert_eq!(state.v_registers[VF as usize], 1);
@@ -827,10 +1271,18 @@ mod tests {
             state.v_registers[V0 as usize] = 2;
             state.v_registers[V1 as usize] = 1;
 
-            execution::execute_instruction(&mut state, 0x8017);
+            execution::execute_instruction(&mut state, 0x8017).unwrap();
 
             assert_eq!(state.v_registers[V0 as usize], 255);
             assert_eq!(state.v_registers[VF as usize], 0);
+
+            state.v_registers[V0 as usize] = 9;
+            state.v_registers[V1 as usize] = 9;
+
+            execution::execute_instruction(&mut state, 0x8017).unwrap();
+
+            assert_eq!(state.v_registers[V0 as usize], 0);
+            assert_eq!(state.v_registers[VF as usize], 1);
         }
 
         //SUBCASE("SHL")
@@ -839,12 +1291,12 @@ mod tests {
 
             state.v_registers[V0 as usize] = 64;
 
-            execution::execute_instruction(&mut state, 0x801E);
+            execution::execute_instruction(&mut state, 0x801E).unwrap();
 
             assert_eq!(state.v_registers[V0 as usize], 128);
             assert_eq!(state.v_registers[VF as usize], 0);
 
-            execution::execute_instruction(&mut state, 0x801E);
+            execution::execute_instruction(&mut state, 0x801E).unwrap();
 
             assert_eq!(state.v_registers[V0 as usize], 0);
             assert_eq!(state.v_registers[VF as usize], 1);
@@ -857,12 +1309,12 @@ mod tests {
             state.v_registers[V9 as usize] = 64;
             state.v_registers[VA as usize] = 64;
 
-            execution::execute_instruction(&mut state, 0x99A0);
+            execution::execute_instruction(&mut state, 0x99A0).unwrap();
 
             assert_eq!(state.pc, cpu::MIN_PROGRAM_ADDRESS as u16 + 2);
 
             state.v_registers[VA as usize] = 0;
-            execution::execute_instruction(&mut state, 0x99A0);
+            execution::execute_instruction(&mut state, 0x99A0).unwrap();
 
             assert_eq!(state.pc, cpu::MIN_PROGRAM_ADDRESS as u16 + 6);
         }
@@ -871,7 +1323,7 @@ mod tests {
         {
             let mut state = cpu::create_chip8_state();
 
-            execution::execute_instruction(&mut state, 0xA242);
+            execution::execute_instruction(&mut state, 0xA242).unwrap();
 
             assert_eq!(state.i, 0x0242);
         }
@@ -882,7 +1334,7 @@ mod tests {
 
             state.v_registers[V0 as usize] = 0x02;
 
-            execution::execute_instruction(&mut state, 0xB240);
+            execution::execute_instruction(&mut state, 0xB240).unwrap();
 
             assert_eq!(state.pc, 0x0242);
         }
@@ -891,23 +1343,33 @@ mod tests {
         {
             let mut state = cpu::create_chip8_state();
 
-            execution::execute_instruction(&mut state, 0xC10F);
+            execution::execute_instruction(&mut state, 0xC10F).unwrap();
 
             assert_eq!(state.v_registers[V1 as usize] & !0x0F, 0);
 
-            execution::execute_instruction(&mut state, 0xC1F0);
+            execution::execute_instruction(&mut state, 0xC1F0).unwrap();
 
             assert_eq!(state.v_registers[V1 as usize] & !0xF0, 0);
+
+            // Seeded runs are reproducible
+            let mut other_state = cpu::create_chip8_state();
+
+            random::seed_rng(&mut state, 42);
+            random::seed_rng(&mut other_state, 42);
+
+            execution::execute_instruction(&mut state, 0xC1FF).unwrap();
+
+            assert_eq!(state.v_registers[V1 as usize], random::next_random_byte(&mut other_state));
         }
 
         //SUBCASE("DRW")
         {
             // TODO
-            // execution::execute_instruction(&mut state, 0x00E0); // Clear screen
+            // execution::execute_instruction(&mut state, 0x00E0).unwrap(); // Clear screen
             // state.v_registers[V0 as usize] = 0x0F; // Set digit to print
             // state.v_registers[V1 as usize] = 0x00; // Set digit to print
-            // execution::execute_instruction(&mut state, 0xF029); // Load digit sprite address
-            // execution::execute_instruction(&mut state, 0xD115); // Draw sprite
+            // execution::execute_instruction(&mut state, 0xF029).unwrap(); // Load digit sprite address
+            // execution::execute_instruction(&mut state, 0xD115).unwrap(); // Draw sprite
             // for (int i = 0; i < 10; i++)
             // {
             //     chip8::write_screen_pixel(state, chip8::SCREEN_WIDTH - i - 1, chip8::SCREEN_HEIGHT - i - 1, 1);
@@ -921,11 +1383,11 @@ mod tests {
             state.v_registers[VA as usize] = 0x0F;
             state.key_state = 0x8000;
 
-            execution::execute_instruction(&mut state, 0xEA9E);
+            execution::execute_instruction(&mut state, 0xEA9E).unwrap();
 
             assert_eq!(state.pc, cpu::MIN_PROGRAM_ADDRESS as u16 + 4); // Skipped
 
-            execution::execute_instruction(&mut state, 0xEB9E);
+            execution::execute_instruction(&mut state, 0xEB9E).unwrap();
 
             assert_eq!(state.pc, cpu::MIN_PROGRAM_ADDRESS as u16 + 6); // Did not skip
 
@@ -938,11 +1400,11 @@ mod tests {
             state.v_registers[VA as usize] = 0xF;
             state.key_state = 0x8000;
 
-            execution::execute_instruction(&mut state, 0xEBA1);
+            execution::execute_instruction(&mut state, 0xEBA1).unwrap();
 
             assert_eq!(state.pc, cpu::MIN_PROGRAM_ADDRESS as u16 + 4); // Skipped
 
-            execution::execute_instruction(&mut state, 0xEAA1);
+            execution::execute_instruction(&mut state, 0xEAA1).unwrap();
 
             assert_eq!(state.pc, cpu::MIN_PROGRAM_ADDRESS as u16 + 6); // Did not skip
         }
@@ -954,7 +1416,7 @@ mod tests {
             state.delay_timer = 42;
             state.v_registers[V4 as usize] = 0;
 
-            execution::execute_instruction(&mut state, 0xF407);
+            execution::execute_instruction(&mut state, 0xF407).unwrap();
 
             assert_eq!(state.v_registers[V4 as usize], 42);
         }
@@ -966,17 +1428,28 @@ mod tests {
             assert!(!state.is_waiting_for_key);
             assert_eq!(state.v_registers[V1 as usize], 0);
 
-            execution::execute_instruction(&mut state, 0xF10A);
+            execution::execute_instruction(&mut state, 0xF10A).unwrap();
 
             assert!(state.is_waiting_for_key);
             assert_eq!(state.v_registers[V1 as usize], 0);
 
             keyboard::set_key_pressed(&mut state, 0xA, true);
 
-            execution::execute_instruction(&mut state, 0xF10A);
+            execution::execute_instruction(&mut state, 0xF10A).unwrap();
 
             assert!(!state.is_waiting_for_key);
             assert_eq!(state.v_registers[V1 as usize], 0xA);
+
+            // Key 0 alone
+            state.v_registers[V1 as usize] = 0xF;
+
+            execution::execute_instruction(&mut state, 0xF10A).unwrap();
+            keyboard::set_key_pressed(&mut state, 0xA, false);
+            keyboard::set_key_pressed(&mut state, 0x0, true);
+            execution::execute_instruction(&mut state, 0xF10A).unwrap();
+
+            assert!(!state.is_waiting_for_key);
+            assert_eq!(state.v_registers[V1 as usize], 0x0);
         }
 
         //SUBCASE("LDDT")
@@ -985,7 +1458,7 @@ mod tests {
 
             state.v_registers[V5 as usize] = 66;
 
-            execution::execute_instruction(&mut state, 0xF515);
+            execution::execute_instruction(&mut state, 0xF515).unwrap();
 
             assert_eq!(state.delay_timer, 66);
         }
@@ -996,7 +1469,7 @@ mod tests {
 
             state.v_registers[V6 as usize] = 33;
 
-            execution::execute_instruction(&mut state, 0xF618);
+            execution::execute_instruction(&mut state, 0xF618).unwrap();
 
             assert_eq!(state.sound_timer, 33);
         }
@@ -1008,7 +1481,7 @@ mod tests {
             state.v_registers[V9 as usize] = 10;
             state.i = cpu::MIN_PROGRAM_ADDRESS as u16;
 
-            execution::execute_instruction(&mut state, 0xF91E);
+            execution::execute_instruction(&mut state, 0xF91E).unwrap();
 
             assert_eq!(state.i, cpu::MIN_PROGRAM_ADDRESS as u16 + 10);
         }
@@ -1019,13 +1492,13 @@ mod tests {
 
             state.v_registers[V0 as usize] = 9;
 
-            execution::execute_instruction(&mut state, 0xF029);
+            execution::execute_instruction(&mut state, 0xF029).unwrap();
 
             assert_eq!(state.i, state.font_table_offsets[9]);
 
             state.v_registers[V0 as usize] = 0xF;
 
-            execution::execute_instruction(&mut state, 0xF029);
+            execution::execute_instruction(&mut state, 0xF029).unwrap();
 
             assert_eq!(state.i, state.font_table_offsets[0xF]);
         }
@@ -1037,7 +1510,7 @@ mod tests {
             state.i = cpu::MIN_PROGRAM_ADDRESS as u16;
             state.v_registers[V7 as usize] = 109;
 
-            execution::execute_instruction(&mut state, 0xF733);
+            execution::execute_instruction(&mut state, 0xF733).unwrap();
 
             assert_eq!(state.memory[state.i as usize + 0], 1);
             assert_eq!(state.memory[state.i as usize + 1], 0);
@@ -1045,7 +1518,7 @@ mod tests {
 
             state.v_registers[V7 as usize] = 255;
 
-            execution::execute_instruction(&mut state, 0xF733);
+            execution::execute_instruction(&mut state, 0xF733).unwrap();
 
             assert_eq!(state.memory[state.i as usize + 0], 2);
             assert_eq!(state.memory[state.i as usize + 1], 5);
@@ -1066,7 +1539,7 @@ mod tests {
             state.v_registers[V1 as usize] = 0x23;
             state.v_registers[V2 as usize] = 0x00;
 
-            execute_instruction_internal(&mut state, OpCode::LDAI{reg: V1 as u8});
+            execute_instruction_internal(&mut state, OpCode::LDAI{reg: V1 as u8}).unwrap();
 
             assert_eq!(state.memory[state.i as usize + 0], 0xE4);
             assert_eq!(state.memory[state.i as usize + 1], 0x23);
@@ -1088,7 +1561,7 @@ mod tests {
             state.memory[state.i as usize + 1] = 0x23;
             state.memory[state.i as usize + 2] = 0x00;
 
-            execute_instruction_internal(&mut state, OpCode::LDM{reg: V1 as u8});
+            execute_instruction_internal(&mut state, OpCode::LDM{reg: V1 as u8}).unwrap();
 
             assert_eq!(state.v_registers[V0 as usize], 0xE4);
             assert_eq!(state.v_registers[V1 as usize], 0x23);
@@ -1096,4 +1569,487 @@ mod tests {
             assert_eq!(state.v_registers[V3 as usize], 0x73);
         }
     }
+
+    #[test]
+    fn faults() {
+        //SUBCASE("Invalid opcode")
+        {
+            let mut state = cpu::create_chip8_state();
+            let error = execution::execute_instruction(&mut state, 0x8008).unwrap_err();
+
+            assert_eq!(error.fault, Chip8Fault::InvalidOpcode);
+            assert_eq!(error.pc, cpu::MIN_PROGRAM_ADDRESS as u16);
+            assert_eq!(error.instruction, 0x8008);
+            assert_eq!(state.pc, cpu::MIN_PROGRAM_ADDRESS as u16);
+        }
+
+        //SUBCASE("Stack overflow/underflow")
+        {
+            let mut state = cpu::create_chip8_state();
+
+            assert_eq!(execution::execute_instruction(&mut state, 0x00EE).unwrap_err().fault, Chip8Fault::StackUnderflow);
+
+            for _ in 1..cpu::STACK_SIZE {
+                execution::execute_instruction(&mut state, 0x2300).unwrap();
+            }
+
+            assert_eq!(execution::execute_instruction(&mut state, 0x2300).unwrap_err().fault, Chip8Fault::StackOverflow);
+        }
+
+        //SUBCASE("Unaligned jump")
+        {
+            let mut state = cpu::create_chip8_state();
+
+            assert_eq!(execution::execute_instruction(&mut state, 0x1301).unwrap_err().fault, Chip8Fault::UnalignedJump{addr: 0x0301});
+        }
+
+        //SUBCASE("Memory violation")
+        {
+            let mut state = cpu::create_chip8_state();
+
+            // Jumping into the interpreter area
+            assert_eq!(execution::execute_instruction(&mut state, 0x1100).unwrap_err().fault,
+                       Chip8Fault::MemoryViolation{addr: 0x0100, usage: MemoryUsage::Execute});
+
+            // Writing over the font table
+            state.i = 0x0000;
+            assert_eq!(execution::execute_instruction(&mut state, 0xF055).unwrap_err().fault,
+                       Chip8Fault::MemoryViolation{addr: 0x0000, usage: MemoryUsage::Write});
+
+            // Running off the end of memory
+            state.pc = cpu::MAX_PROGRAM_ADDRESS as u16 + 1;
+            assert_eq!(execution::execute_step(&mut state, cpu::INSTRUCTION_EXECUTION_PERIOD_MS).unwrap_err().fault,
+                       Chip8Fault::MemoryViolation{addr: cpu::MAX_PROGRAM_ADDRESS as u16 + 1, usage: MemoryUsage::Execute});
+        }
+
+        //SUBCASE("I overflow")
+        {
+            let mut state = cpu::create_chip8_state();
+
+            state.i = 0xFFFF;
+            state.v_registers[V0 as usize] = 1;
+
+            assert_eq!(execution::execute_instruction(&mut state, 0xF01E).unwrap_err().fault, Chip8Fault::IndexOverflow);
+        }
+
+        //SUBCASE("Invalid key/glyph")
+        {
+            let mut state = cpu::create_chip8_state();
+
+            state.v_registers[V0 as usize] = 0x10;
+
+            assert_eq!(execution::execute_instruction(&mut state, 0xE09E).unwrap_err().fault, Chip8Fault::InvalidKey{key: 0x10});
+            assert_eq!(execution::execute_instruction(&mut state, 0xF029).unwrap_err().fault, Chip8Fault::InvalidGlyph{glyph: 0x10});
+        }
+
+        //SUBCASE("Invalid program")
+        {
+            let mut state = cpu::create_chip8_state();
+
+            assert_eq!(execution::load_program(&mut state, vec![0x00; 3]), Err(Chip8Fault::InvalidProgramSize{size: 3}));
+            assert_eq!(execution::load_program(&mut state, vec![0x00; cpu::MEMORY_SIZE_IN_BYTES]),
+                       Err(Chip8Fault::InvalidProgramSize{size: cpu::MEMORY_SIZE_IN_BYTES}));
+        }
+    }
+
+    #[test]
+    fn quirks() {
+        //SUBCASE("Shift source")
+        {
+            let mut state = cpu::create_chip8_state();
+            state.quirks.shift_uses_vy = true;
+
+            state.v_registers[V0 as usize] = 0x00;
+            state.v_registers[V1 as usize] = 0x81;
+
+            execution::execute_instruction(&mut state, 0x8016).unwrap();
+
+            assert_eq!(state.v_registers[V0 as usize], 0x40);
+            assert_eq!(state.v_registers[VF as usize], 1);
+
+            execution::execute_instruction(&mut state, 0x801E).unwrap();
+
+            assert_eq!(state.v_registers[V0 as usize], 0x02);
+            assert_eq!(state.v_registers[VF as usize], 1);
+        }
+
+        //SUBCASE("Load/store I increment")
+        {
+            let mut state = cpu::create_chip8_state();
+            state.i = cpu::MIN_PROGRAM_ADDRESS as u16;
+
+            state.quirks.load_store_increment = IndexIncrement::ByXPlusOne;
+            execution::execute_instruction(&mut state, 0xF255).unwrap();
+
+            assert_eq!(state.i, cpu::MIN_PROGRAM_ADDRESS as u16 + 3);
+
+            state.quirks.load_store_increment = IndexIncrement::ByX;
+            execution::execute_instruction(&mut state, 0xF265).unwrap();
+
+            assert_eq!(state.i, cpu::MIN_PROGRAM_ADDRESS as u16 + 5);
+        }
+
+        //SUBCASE("Jump offset register")
+        {
+            let mut state = cpu::create_chip8_state();
+            state.quirks.jump_uses_vx = true;
+
+            state.v_registers[V0 as usize] = 0x10;
+            state.v_registers[V3 as usize] = 0x02;
+
+            execution::execute_instruction(&mut state, 0xB340).unwrap();
+
+            assert_eq!(state.pc, 0x0342);
+        }
+
+        //SUBCASE("VF reset")
+        {
+            let mut state = cpu::create_chip8_state();
+            state.quirks.logic_resets_vf = true;
+
+            for instruction in [0x8011, 0x8012, 0x8013].iter() {
+                state.v_registers[VF as usize] = 1;
+
+                execution::execute_instruction(&mut state, *instruction).unwrap();
+
+                assert_eq!(state.v_registers[VF as usize], 0);
+            }
+        }
+
+        //SUBCASE("Sprite clipping")
+        {
+            let mut wrapped_state = cpu::create_chip8_state();
+            let mut clipped_state = cpu::create_chip8_state();
+            clipped_state.quirks.clip_sprites = true;
+
+            for state in [&mut wrapped_state, &mut clipped_state].iter_mut() {
+                state.i = state.font_table_offsets[0x0]; // 0xF0 top row
+                state.v_registers[V0 as usize] = (cpu::SCREEN_WIDTH - 2) as u8;
+                state.v_registers[V1 as usize] = (cpu::SCREEN_HEIGHT - 1) as u8;
+
+                execution::execute_instruction(state, 0xD015).unwrap();
+            }
+
+            assert!(display::read_screen_pixel(&wrapped_state, cpu::SCREEN_WIDTH - 1, cpu::SCREEN_HEIGHT - 1));
+            assert!(display::read_screen_pixel(&wrapped_state, 1, cpu::SCREEN_HEIGHT - 1));
+            assert!(display::read_screen_pixel(&wrapped_state, 1, 0));
+
+            assert!(display::read_screen_pixel(&clipped_state, cpu::SCREEN_WIDTH - 1, cpu::SCREEN_HEIGHT - 1));
+            assert!(!display::read_screen_pixel(&clipped_state, 1, cpu::SCREEN_HEIGHT - 1));
+            assert!(!display::read_screen_pixel(&clipped_state, 1, 0));
+        }
+
+        //SUBCASE("Display wait")
+        {
+            let mut state = cpu::create_chip8_state();
+            state.quirks.display_wait = true;
+
+            // DRW V0, V0, 1 in a loop
+            execution::load_program(&mut state, vec![0xD0, 0x01, 0x12, 0x00]).unwrap();
+
+            // A whole frame worth of instructions only gets through the first draw
+            execution::execute_step(&mut state, cpu::DELAY_TIMER_PERIOD_MS - 1).unwrap();
+
+            assert!(state.is_waiting_for_display);
+            assert_eq!(state.pc, cpu::MIN_PROGRAM_ADDRESS as u16 + 2);
+
+            execution::execute_step(&mut state, 1).unwrap();
+
+            assert!(!state.is_waiting_for_display);
+
+            execution::execute_step(&mut state, cpu::INSTRUCTION_EXECUTION_PERIOD_MS * 2).unwrap();
+
+            assert!(state.is_waiting_for_display);
+            assert_eq!(state.pc, cpu::MIN_PROGRAM_ADDRESS as u16 + 2);
+        }
+    }
+
+    #[test]
+    fn super_chip() {
+        //SUBCASE("Platform support")
+        {
+            let mut state = cpu::create_chip8_state();
+
+            assert_eq!(execution::execute_instruction(&mut state, 0x00FF).unwrap_err().fault, Chip8Fault::InvalidOpcode);
+
+            state.platform = Platform::SuperChip;
+            execution::execute_instruction(&mut state, 0x00FF).unwrap();
+        }
+
+        //SUBCASE("HIGH/LOW")
+        {
+            let mut state = cpu::create_chip8_state();
+            state.platform = Platform::SuperChip;
+
+            execution::execute_instruction(&mut state, 0x00FF).unwrap();
+
+            assert_eq!(display::screen_width(&state), cpu::HIRES_SCREEN_WIDTH);
+            assert_eq!(display::screen_height(&state), cpu::HIRES_SCREEN_HEIGHT);
+            assert_eq!(state.screen[0].len(), cpu::HIRES_SCREEN_HEIGHT);
+            assert_eq!(state.screen[0][0].len(), cpu::HIRES_SCREEN_WIDTH / 8);
+
+            execution::execute_instruction(&mut state, 0x00FE).unwrap();
+
+            assert_eq!(state.screen[0].len(), cpu::SCREEN_HEIGHT);
+            assert_eq!(state.screen[0][0].len(), cpu::SCREEN_LINE_SIZE_IN_BYTES);
+        }
+
+        //SUBCASE("DRW 16x16")
+        {
+            let mut state = cpu::create_chip8_state();
+            state.platform = Platform::SuperChip;
+
+            execution::execute_instruction(&mut state, 0x00FF).unwrap();
+
+            state.i = cpu::MIN_PROGRAM_ADDRESS as u16;
+            state.memory[state.i as usize + 0] = 0x80;
+            state.memory[state.i as usize + 1] = 0x01;
+            state.memory[state.i as usize + 31] = 0x01;
+            state.v_registers[V0 as usize] = 100;
+            state.v_registers[V1 as usize] = 40;
+
+            execution::execute_instruction(&mut state, 0xD010).unwrap();
+
+            assert!(display::read_screen_pixel(&state, 100, 40));
+            assert!(display::read_screen_pixel(&state, 115, 40));
+            assert!(display::read_screen_pixel(&state, 115, 55));
+            assert!(!display::read_screen_pixel(&state, 101, 40));
+            assert_eq!(state.v_registers[VF as usize], 0);
+
+            execution::execute_instruction(&mut state, 0xD010).unwrap();
+
+            assert!(!display::read_screen_pixel(&state, 100, 40));
+            assert_eq!(state.v_registers[VF as usize], 1);
+        }
+
+        //SUBCASE("SCD/SCR/SCL")
+        {
+            let mut state = cpu::create_chip8_state();
+            state.platform = Platform::SuperChip;
+
+            display::write_plane_pixel(&mut state, 0, 10, 10, true);
+
+            execution::execute_instruction(&mut state, 0x00C3).unwrap();
+
+            assert!(!display::read_screen_pixel(&state, 10, 10));
+            assert!(display::read_screen_pixel(&state, 10, 13));
+            assert_eq!(state.screen[0].len(), cpu::SCREEN_HEIGHT);
+
+            execution::execute_instruction(&mut state, 0x00FB).unwrap();
+
+            assert!(display::read_screen_pixel(&state, 14, 13));
+
+            execution::execute_instruction(&mut state, 0x00FC).unwrap();
+            execution::execute_instruction(&mut state, 0x00FC).unwrap();
+
+            assert!(display::read_screen_pixel(&state, 6, 13));
+            assert!(!display::read_screen_pixel(&state, 14, 13));
+        }
+
+        //SUBCASE("EXIT")
+        {
+            let mut state = cpu::create_chip8_state();
+            state.platform = Platform::SuperChip;
+
+            execution::load_program(&mut state, vec![0x00, 0xFD, 0x60, 0x01]).unwrap();
+            execution::execute_step(&mut state, cpu::INSTRUCTION_EXECUTION_PERIOD_MS * 4).unwrap();
+
+            assert!(state.is_halted);
+            assert_eq!(state.v_registers[V0 as usize], 0);
+        }
+
+        //SUBCASE("LDHF")
+        {
+            let mut state = cpu::create_chip8_state();
+            state.platform = Platform::SuperChip;
+
+            state.v_registers[V2 as usize] = 8;
+
+            execution::execute_instruction(&mut state, 0xF230).unwrap();
+
+            assert_eq!(state.i, state.big_font_table_offsets[8]);
+            assert_eq!(state.memory[state.i as usize], 0xFF);
+        }
+
+        //SUBCASE("LDR/LDVR")
+        {
+            let mut state = cpu::create_chip8_state();
+            state.platform = Platform::SuperChip;
+
+            state.v_registers[V0 as usize] = 0x12;
+            state.v_registers[V1 as usize] = 0x34;
+            state.v_registers[V2 as usize] = 0x56;
+
+            execution::execute_instruction(&mut state, 0xF175).unwrap();
+
+            state.v_registers = [0; cpu::V_REGISTER_COUNT];
+
+            execution::execute_instruction(&mut state, 0xF285).unwrap();
+
+            assert_eq!(state.v_registers[V0 as usize], 0x12);
+            assert_eq!(state.v_registers[V1 as usize], 0x34);
+            assert_eq!(state.v_registers[V2 as usize], 0x00);
+
+            assert_eq!(execution::execute_instruction(&mut state, 0xF875).unwrap_err().fault, Chip8Fault::InvalidOpcode);
+        }
+    }
+
+    #[test]
+    fn xo_chip() {
+        //SUBCASE("Platform support")
+        {
+            let mut state = cpu::create_chip8_state_for_platform(Platform::SuperChip);
+
+            assert_eq!(execution::execute_instruction(&mut state, 0xF201).unwrap_err().fault, Chip8Fault::InvalidOpcode);
+            assert_eq!(state.memory.len(), cpu::MEMORY_SIZE_IN_BYTES);
+
+            let state = cpu::create_chip8_state_for_platform(Platform::XoChip);
+
+            assert_eq!(state.memory.len(), cpu::XO_CHIP_MEMORY_SIZE_IN_BYTES);
+        }
+
+        //SUBCASE("LDIL")
+        {
+            let mut state = cpu::create_chip8_state_for_platform(Platform::XoChip);
+
+            execution::load_program(&mut state, vec![0xF0, 0x00, 0xE1, 0x23, 0x00]).unwrap();
+            execution::execute_instruction(&mut state, 0xF000).unwrap();
+
+            assert_eq!(state.i, 0xE123);
+            assert_eq!(state.pc, cpu::MIN_PROGRAM_ADDRESS as u16 + 4);
+
+            // Memory past 0x0FFF is usable
+            state.v_registers[V0 as usize] = 0x42;
+            execution::execute_instruction(&mut state, 0xF055).unwrap();
+
+            assert_eq!(state.memory[0xE123], 0x42);
+        }
+
+        //SUBCASE("Skip over LDIL")
+        {
+            let mut state = cpu::create_chip8_state_for_platform(Platform::XoChip);
+
+            execution::load_program(&mut state, vec![0x30, 0x00, 0xF0, 0x00, 0x12, 0x34, 0x60, 0x01]).unwrap();
+            execution::execute_instruction(&mut state, 0x3000).unwrap();
+
+            assert_eq!(state.pc, cpu::MIN_PROGRAM_ADDRESS as u16 + 6);
+        }
+
+        //SUBCASE("End of memory")
+        {
+            let mut state = cpu::create_chip8_state_for_platform(Platform::XoChip);
+
+            // The last instruction runs, then PC wraps into the interpreter area
+            state.pc = 0xFFFE;
+            state.memory[0xFFFE..].copy_from_slice(&[0x60, 0x01]);
+            execution::execute_step(&mut state, cpu::INSTRUCTION_EXECUTION_PERIOD_MS).unwrap();
+
+            assert_eq!(state.v_registers[V0 as usize], 0x01);
+            assert_eq!(state.pc, 0x0000);
+            assert_eq!(execution::execute_step(&mut state, cpu::INSTRUCTION_EXECUTION_PERIOD_MS).unwrap_err().fault,
+                       Chip8Fault::MemoryViolation{addr: 0x0000, usage: MemoryUsage::Execute});
+
+            state.pc = 0xFFFC;
+            state.memory[0xFFFC..].copy_from_slice(&[0xF0, 0x00, 0x12, 0x34]);
+            execution::execute_instruction(&mut state, 0xF000).unwrap();
+
+            assert_eq!(state.pc, 0x0000);
+
+            // Returning from a call at the last address
+            state.pc = 0xFFFE;
+            execution::execute_instruction(&mut state, 0x2300).unwrap();
+
+            assert_eq!(execution::execute_instruction(&mut state, 0x00EE).unwrap_err().fault,
+                       Chip8Fault::MemoryViolation{addr: 0x0000, usage: MemoryUsage::Execute});
+        }
+
+        //SUBCASE("SAVE/LOAD")
+        {
+            let mut state = cpu::create_chip8_state_for_platform(Platform::XoChip);
+
+            state.i = cpu::MIN_PROGRAM_ADDRESS as u16;
+            state.v_registers[V2 as usize] = 0x22;
+            state.v_registers[V3 as usize] = 0x33;
+            state.v_registers[V4 as usize] = 0x44;
+
+            execution::execute_instruction(&mut state, 0x5242).unwrap();
+
+            assert_eq!(state.memory[state.i as usize..state.i as usize + 3], [0x22, 0x33, 0x44]);
+            assert_eq!(state.i, cpu::MIN_PROGRAM_ADDRESS as u16);
+
+            // Reversed range
+            execution::execute_instruction(&mut state, 0x5A83).unwrap();
+
+            assert_eq!(state.v_registers[VA as usize], 0x22);
+            assert_eq!(state.v_registers[V9 as usize], 0x33);
+            assert_eq!(state.v_registers[V8 as usize], 0x44);
+        }
+
+        //SUBCASE("PLANE/DRW/CLS")
+        {
+            let mut state = cpu::create_chip8_state_for_platform(Platform::XoChip);
+
+            state.i = cpu::MIN_PROGRAM_ADDRESS as u16;
+            state.memory[state.i as usize + 0] = 0x80; // First plane
+            state.memory[state.i as usize + 1] = 0xC0; // Second plane
+
+            execution::execute_instruction(&mut state, 0xF301).unwrap();
+            execution::execute_instruction(&mut state, 0xD001).unwrap();
+
+            assert_eq!(display::read_screen_pixel_color_index(&state, 0, 0), 0x3);
+            assert_eq!(display::read_screen_pixel_color_index(&state, 1, 0), 0x2);
+
+            execution::execute_instruction(&mut state, 0xF201).unwrap();
+            execution::execute_instruction(&mut state, 0x00E0).unwrap();
+
+            assert_eq!(display::read_screen_pixel_color_index(&state, 0, 0), 0x1);
+            assert_eq!(display::read_screen_pixel_color_index(&state, 1, 0), 0x0);
+
+            // No plane selected draws nothing
+            execution::execute_instruction(&mut state, 0xF001).unwrap();
+            execution::execute_instruction(&mut state, 0xD001).unwrap();
+
+            assert_eq!(display::read_screen_pixel_color_index(&state, 0, 0), 0x1);
+            assert_eq!(state.v_registers[VF as usize], 0);
+        }
+
+        //SUBCASE("SCU")
+        {
+            let mut state = cpu::create_chip8_state_for_platform(Platform::XoChip);
+
+            display::write_plane_pixel(&mut state, 0, 3, 10, true);
+
+            execution::execute_instruction(&mut state, 0x00D4).unwrap();
+
+            assert!(display::read_screen_pixel(&state, 3, 6));
+            assert!(!display::read_screen_pixel(&state, 3, 10));
+            assert_eq!(state.screen[0].len(), cpu::SCREEN_HEIGHT);
+        }
+
+        //SUBCASE("AUDIO/PITCH")
+        {
+            let mut state = cpu::create_chip8_state_for_platform(Platform::XoChip);
+
+            state.i = cpu::MIN_PROGRAM_ADDRESS as u16;
+            state.memory[state.i as usize + 15] = 0xAA;
+            state.v_registers[V1 as usize] = 112;
+
+            execution::execute_instruction(&mut state, 0xF002).unwrap();
+            execution::execute_instruction(&mut state, 0xF13A).unwrap();
+
+            assert_eq!(state.audio_pattern[15], 0xAA);
+            assert_eq!(state.audio_pitch, 112);
+        }
+
+        //SUBCASE("RPL flags")
+        {
+            let mut state = cpu::create_chip8_state_for_platform(Platform::XoChip);
+
+            state.v_registers[VF as usize] = 0x0F;
+
+            execution::execute_instruction(&mut state, 0xFF75).unwrap();
+
+            assert_eq!(state.rpl_flags[0xF], 0x0F);
+        }
+    }
 }
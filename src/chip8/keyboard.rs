@@ -7,7 +7,7 @@ use super::cpu::CPUState;
 // A  0  B  F
 pub type KeyID = u8;
 
-const KEY_ID_COUNT: u8 = 16;
+pub const KEY_ID_COUNT: u8 = 16;
 
 pub fn is_key_pressed(state: &CPUState, key: KeyID) -> bool
 {
@@ -21,7 +21,7 @@ pub fn get_key_pressed(key_state: u16) -> KeyID
 {
     assert!(key_state != 0);
 
-    for i in 1..16 {
+    for i in 0..KEY_ID_COUNT {
         if ((1 << i) & key_state) != 0 {
             return i;
         }
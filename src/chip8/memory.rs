@@ -1,5 +1,12 @@
-use super::cpu;
+use super::{
+    cpu,
+    cpu::CPUState,
+    fault::Chip8Fault,
+};
 
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum MemoryUsage
 {
     Read,
@@ -7,19 +14,42 @@ pub enum MemoryUsage
     Execute,
 }
 
-pub fn is_valid_memory_range(base_address: u16, size_in_bytes: usize, usage: MemoryUsage) -> bool
+impl fmt::Display for MemoryUsage
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        match self {
+            MemoryUsage::Read => write!(f, "read"),
+            MemoryUsage::Write => write!(f, "write"),
+            MemoryUsage::Execute => write!(f, "execute"),
+        }
+    }
+}
+
+// The addressable range depends on the platform, XO-CHIP extends it to 64 KiB.
+pub fn is_valid_memory_range(state: &CPUState, base_address: u16, size_in_bytes: usize, usage: MemoryUsage) -> bool
 {
     assert!(size_in_bytes > 0); // Invalid address range size
 
     let base_address = base_address as usize;
     let end_address = base_address + (size_in_bytes - 1);
+    let max_address = state.memory.len() - 1;
 
     if end_address < base_address {
         return false; // Overflow
     }
 
     match usage {
-        MemoryUsage::Read => (end_address <= cpu::MAX_PROGRAM_ADDRESS),
-        MemoryUsage::Write | MemoryUsage::Execute => (base_address >= cpu::MIN_PROGRAM_ADDRESS && end_address <= cpu::MAX_PROGRAM_ADDRESS),
+        MemoryUsage::Read => end_address <= max_address,
+        MemoryUsage::Write | MemoryUsage::Execute => base_address >= cpu::MIN_PROGRAM_ADDRESS && end_address <= max_address,
+    }
+}
+
+pub fn check_memory_range(state: &CPUState, base_address: u16, size_in_bytes: usize, usage: MemoryUsage) -> Result<(), Chip8Fault>
+{
+    if is_valid_memory_range(state, base_address, size_in_bytes, usage) {
+        Ok(())
+    } else {
+        Err(Chip8Fault::MemoryViolation { addr: base_address, usage })
     }
 }
@@ -1,15 +1,34 @@
+pub mod assembler;
+pub mod audio;
 pub mod config;
 pub mod cpu;
+pub mod debugger;
+pub mod disassembler;
 pub mod display;
 pub mod execution;
+pub mod fault;
+pub mod gdbstub;
+pub mod hash;
 pub mod keyboard;
+pub mod movie;
 pub mod opcode;
+pub mod random;
+pub mod rewind;
+pub mod savestate;
+pub mod timing;
+pub mod trace;
 
 pub use self::{
+    audio::*,
     config::*,
     cpu::*,
     display::*,
     execution::*,
+    fault::*,
+    memory::MemoryUsage,
+    movie::*,
+    rewind::*,
+    savestate::SaveStateError,
 };
 
 mod instruction;
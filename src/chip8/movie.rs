@@ -0,0 +1,460 @@
+use super::{
+    config::{IndexIncrement, Platform, Quirks, RandomMode, TimingMode},
+    cpu::CPUState,
+    execution,
+    fault::ExecutionError,
+    hash,
+    keyboard::KEY_ID_COUNT,
+    random,
+    savestate,
+};
+
+use std::{
+    error,
+    fmt,
+};
+
+// Text format, one frame per 'f' line:
+// chip8-movie <version>
+// platform <name>
+// seed <hex>
+// rom <hex hash>
+// quirks <name>=<value> ...
+// hash-interval <frames>
+// f <delta ms> [<key><+|->]...
+// h <hex hash of the state after the frames so far>
+pub const MOVIE_VERSION: u32 = 1;
+
+const MOVIE_MAGIC: &str = "chip8-movie";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MovieError
+{
+    InvalidFormat { line: usize },
+    UnsupportedVersion { version: u32 },
+    PlatformMismatch { platform: Platform },
+    RomMismatch,
+    Desync { frame: u32 },
+    Fault(ExecutionError),
+}
+
+impl fmt::Display for MovieError
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        match self {
+            MovieError::InvalidFormat{line} => write!(f, "invalid movie at line {}", line),
+            MovieError::UnsupportedVersion{version} => write!(f, "unsupported movie version {}", version),
+            MovieError::PlatformMismatch{platform} => write!(f, "movie was recorded on another platform ({})", platform.name()),
+            MovieError::RomMismatch => write!(f, "movie was recorded with another ROM"),
+            MovieError::Desync{frame} => write!(f, "playback desynchronized at frame {}", frame),
+            MovieError::Fault(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl error::Error for MovieError {}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MovieFrame
+{
+    pub delta_time_ms: u32,
+    pub key_state: u16, // Held for the whole frame
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Movie
+{
+    pub platform: Platform,
+    pub quirks: Quirks,
+    pub seed: u64,
+    pub rom_hash: u64,
+    pub hash_interval: u32,
+    pub frames: Vec<MovieFrame>,
+    pub state_hashes: Vec<(u32, u64)>, // Frame count and state hash, in frame order
+}
+
+fn index_increment_to_name(increment: IndexIncrement) -> &'static str
+{
+    match increment {
+        IndexIncrement::Unchanged => "unchanged",
+        IndexIncrement::ByX => "x",
+        IndexIncrement::ByXPlusOne => "x+1",
+    }
+}
+
+fn index_increment_from_name(name: &str) -> Option<IndexIncrement>
+{
+    match name {
+        "unchanged" => Some(IndexIncrement::Unchanged),
+        "x" => Some(IndexIncrement::ByX),
+        "x+1" => Some(IndexIncrement::ByXPlusOne),
+        _ => None,
+    }
+}
+
+fn parse_quirks(fields: &[&str]) -> Option<Quirks>
+{
+    let mut quirks = Quirks::default();
+
+    for field in fields {
+        let mut pair = field.splitn(2, '=');
+        let (name, value) = (pair.next()?, pair.next()?);
+        let flag = value == "1";
+
+        match name {
+            "shift_uses_vy" => quirks.shift_uses_vy = flag,
+            "load_store_increment" => quirks.load_store_increment = index_increment_from_name(value)?,
+            "jump_uses_vx" => quirks.jump_uses_vx = flag,
+            "logic_resets_vf" => quirks.logic_resets_vf = flag,
+            "clip_sprites" => quirks.clip_sprites = flag,
+            "display_wait" => quirks.display_wait = flag,
+            "random" => quirks.random_mode = RandomMode::from_name(value)?,
+            "timing" => quirks.timing = TimingMode::from_name(value)?,
+            "ipf" => quirks.instructions_per_frame = Some(value.parse::<u32>().ok()?).filter(|&ipf| ipf > 0),
+            _ => return None,
+        }
+    }
+
+    Some(quirks)
+}
+
+// Covers everything playback depends on, including the timer accumulators and the RNG.
+pub fn hash_state(state: &CPUState) -> u64
+{
+    hash::fnv1a_64(&savestate::save_state(state))
+}
+
+impl Movie
+{
+    pub fn to_text(&self) -> String
+    {
+        let mut text = String::new();
+        let quirks = &self.quirks;
+
+        text += &format!("{} {}\n", MOVIE_MAGIC, MOVIE_VERSION);
+        text += &format!("platform {}\n", self.platform.name());
+        text += &format!("seed {:016x}\n", self.seed);
+        text += &format!("rom {:016x}\n", self.rom_hash);
+        text += &format!("quirks shift_uses_vy={} load_store_increment={} jump_uses_vx={} logic_resets_vf={} clip_sprites={} display_wait={} random={} timing={} ipf={}\n",
+            quirks.shift_uses_vy as u8, index_increment_to_name(quirks.load_store_increment), quirks.jump_uses_vx as u8,
+            quirks.logic_resets_vf as u8, quirks.clip_sprites as u8, quirks.display_wait as u8, quirks.random_mode.name(), quirks.timing.name(),
+            quirks.instructions_per_frame.unwrap_or(0));
+        text += &format!("hash-interval {}\n", self.hash_interval);
+
+        let mut key_state: u16 = 0;
+        let mut state_hashes = self.state_hashes.iter().peekable();
+
+        for (frame_index, frame) in self.frames.iter().enumerate() {
+            text += &format!("f {}", frame.delta_time_ms);
+
+            // Only key changes are written
+            for key in 0..KEY_ID_COUNT {
+                let key_mask: u16 = 1 << key;
+
+                if (key_state ^ frame.key_state) & key_mask != 0 {
+                    text += &format!(" {:x}{}", key, if frame.key_state & key_mask != 0 { '+' } else { '-' });
+                }
+            }
+
+            text += "\n";
+            key_state = frame.key_state;
+
+            while let Some((_, state_hash)) = state_hashes.next_if(|(hash_frame, _)| *hash_frame as usize == frame_index + 1) {
+                text += &format!("h {:016x}\n", state_hash);
+            }
+        }
+
+        text
+    }
+
+    pub fn parse(text: &str) -> Result<Movie, MovieError>
+    {
+        let mut lines = text.lines().enumerate().map(|(line_index, line)| (line_index + 1, line.split_whitespace().collect::<Vec<&str>>()));
+
+        let mut header_field = |name: &str| -> Result<(usize, Vec<&str>), MovieError> {
+            match lines.next() {
+                Some((line, fields)) if fields.first() == Some(&name) => Ok((line, fields[1..].to_vec())),
+                Some((line, _)) => Err(MovieError::InvalidFormat { line }),
+                None => Err(MovieError::InvalidFormat { line: 0 }),
+            }
+        };
+
+        let parse_hex = |line: usize, field: Option<&&str>| {
+            field.and_then(|field| u64::from_str_radix(field, 16).ok()).ok_or(MovieError::InvalidFormat { line })
+        };
+
+        let (line, fields) = header_field(MOVIE_MAGIC)?;
+        let version = fields.first().and_then(|field| field.parse::<u32>().ok()).ok_or(MovieError::InvalidFormat { line })?;
+        if version != MOVIE_VERSION {
+            return Err(MovieError::UnsupportedVersion { version });
+        }
+
+        let (line, fields) = header_field("platform")?;
+        let platform = fields.first().and_then(|name| Platform::from_name(name)).ok_or(MovieError::InvalidFormat { line })?;
+
+        let (line, fields) = header_field("seed")?;
+        let seed = parse_hex(line, fields.first())?;
+
+        let (line, fields) = header_field("rom")?;
+        let rom_hash = parse_hex(line, fields.first())?;
+
+        let (line, fields) = header_field("quirks")?;
+        let quirks = parse_quirks(&fields).ok_or(MovieError::InvalidFormat { line })?;
+
+        let (line, fields) = header_field("hash-interval")?;
+        let hash_interval = fields.first().and_then(|field| field.parse::<u32>().ok()).ok_or(MovieError::InvalidFormat { line })?;
+
+        let mut frames = Vec::new();
+        let mut state_hashes = Vec::new();
+        let mut key_state: u16 = 0;
+
+        for (line, fields) in lines {
+            match fields.split_first() {
+                None => continue,
+                Some((&"f", changes)) => {
+                    let (delta_time_ms, changes) = changes.split_first()
+                        .and_then(|(delta, changes)| delta.parse::<u32>().ok().map(|delta| (delta, changes)))
+                        .ok_or(MovieError::InvalidFormat { line })?;
+
+                    for change in changes {
+                        let (key, direction) = change.split_at(change.len().saturating_sub(1));
+                        let key = u8::from_str_radix(key, 16).ok()
+                            .filter(|&key| key < KEY_ID_COUNT)
+                            .ok_or(MovieError::InvalidFormat { line })?;
+
+                        match direction {
+                            "+" => key_state |= 1 << key,
+                            "-" => key_state &= !(1 << key),
+                            _ => return Err(MovieError::InvalidFormat { line }),
+                        }
+                    }
+
+                    frames.push(MovieFrame { delta_time_ms, key_state });
+                },
+                Some((&"h", fields)) => state_hashes.push((frames.len() as u32, parse_hex(line, fields.first())?)),
+                Some(_) => return Err(MovieError::InvalidFormat { line }),
+            }
+        }
+
+        Ok(Movie { platform, quirks, seed, rom_hash, hash_interval, frames, state_hashes })
+    }
+}
+
+// Records the inputs of a run started from a freshly loaded ROM.
+// Loading a save state or rewinding while recording makes the movie useless.
+pub struct MovieRecorder
+{
+    movie: Movie,
+}
+
+impl MovieRecorder
+{
+    // The RNG is reseeded so that the run can be reproduced.
+    pub fn new(state: &mut CPUState, seed: u64, hash_interval: u32) -> MovieRecorder
+    {
+        random::seed_rng(state, seed);
+
+        MovieRecorder {
+            movie: Movie {
+                platform: state.platform,
+                quirks: state.quirks,
+                seed,
+                rom_hash: state.rom_hash,
+                hash_interval,
+                frames: Vec::new(),
+                state_hashes: Vec::new(),
+            },
+        }
+    }
+
+    // Call once per frame, right after the step that used these keys and time delta.
+    pub fn record_frame(&mut self, state: &CPUState, delta_time_ms: u32)
+    {
+        self.movie.frames.push(MovieFrame { delta_time_ms, key_state: state.key_state });
+
+        let frame_count = self.movie.frames.len() as u32;
+
+        // An interval of 0 disables the checks
+        if frame_count.is_multiple_of(self.movie.hash_interval) {
+            self.movie.state_hashes.push((frame_count, hash_state(state)));
+        }
+    }
+
+    pub fn movie(&self) -> &Movie
+    {
+        &self.movie
+    }
+}
+
+// Replays a movie onto a freshly loaded ROM, checking state hashes along the way.
+pub struct MoviePlayer
+{
+    movie: Movie,
+    frame_count: usize,
+    next_state_hash: usize,
+    elapsed_time_ms: u64,
+}
+
+impl MoviePlayer
+{
+    // The recorded quirks and seed replace the ones of the state.
+    pub fn new(movie: Movie, state: &mut CPUState) -> Result<MoviePlayer, MovieError>
+    {
+        if movie.platform != state.platform {
+            return Err(MovieError::PlatformMismatch { platform: movie.platform });
+        }
+
+        if movie.rom_hash != state.rom_hash {
+            return Err(MovieError::RomMismatch);
+        }
+
+        state.quirks = movie.quirks;
+        random::seed_rng(state, movie.seed);
+
+        Ok(MoviePlayer {
+            movie,
+            frame_count: 0,
+            next_state_hash: 0,
+            elapsed_time_ms: 0,
+        })
+    }
+
+    // Returns false once every frame was played.
+    // A desync is reported on the first hash check that fails.
+    pub fn play_frame(&mut self, state: &mut CPUState) -> Result<bool, MovieError>
+    {
+        let frame = match self.movie.frames.get(self.frame_count) {
+            Some(frame) => *frame,
+            None => return Ok(false),
+        };
+
+        state.key_state = frame.key_state;
+
+        execution::execute_step(state, frame.delta_time_ms).map_err(MovieError::Fault)?;
+
+        self.frame_count += 1;
+        self.elapsed_time_ms += u64::from(frame.delta_time_ms);
+
+        if let Some(&(hash_frame, state_hash)) = self.movie.state_hashes.get(self.next_state_hash) {
+            if hash_frame as usize == self.frame_count {
+                self.next_state_hash += 1;
+
+                if hash_state(state) != state_hash {
+                    return Err(MovieError::Desync { frame: hash_frame });
+                }
+            }
+        }
+
+        Ok(true)
+    }
+
+    pub fn frame_count(&self) -> u32
+    {
+        self.frame_count as u32
+    }
+
+    pub fn elapsed_time_ms(&self) -> u64
+    {
+        self.elapsed_time_ms
+    }
+
+    pub fn is_finished(&self) -> bool
+    {
+        self.frame_count >= self.movie.frames.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::{
+        config::QuirksProfile,
+        cpu,
+        keyboard,
+    };
+
+    // RND V0, 0xFF / LD V1, K / ADD V2, V1 / ADD V3, V0 / JP 0x200
+    const MOVIE_TEST_PROGRAM: [u8; 10] = [0xC0, 0xFF, 0xF1, 0x0A, 0x82, 0x14, 0x83, 0x04, 0x12, 0x00];
+
+    fn create_test_state() -> CPUState
+    {
+        let mut state = cpu::create_chip8_state();
+
+        state.quirks = Quirks::from_profile(QuirksProfile::CosmacVip);
+        execution::load_program(&mut state, MOVIE_TEST_PROGRAM.to_vec()).unwrap();
+
+        state
+    }
+
+    #[test]
+    fn movie() {
+        let mut state = create_test_state();
+        let mut recorder = MovieRecorder::new(&mut state, 0xC0FFEE, 4);
+
+        // Irregular frame times, as a real host would produce
+        for frame in 0..40u32 {
+            keyboard::set_key_pressed(&mut state, 0x5, frame % 6 < 3);
+            keyboard::set_key_pressed(&mut state, 0xA, frame % 10 == 7);
+
+            let delta_time_ms = 14 + frame % 5;
+
+            execution::execute_step(&mut state, delta_time_ms).unwrap();
+            recorder.record_frame(&state, delta_time_ms);
+        }
+
+        let recorded_hash = hash_state(&state);
+        let text = recorder.movie().to_text();
+        let movie = Movie::parse(&text).unwrap();
+
+        assert_eq!(&movie, recorder.movie());
+        assert_eq!(movie.state_hashes.len(), 10);
+        assert!(text.contains("\nf 14 5+\n"));
+
+        // Playback starts from a fresh state with another seed and quirks
+        let mut replayed_state = create_test_state();
+
+        replayed_state.quirks = Quirks::default();
+
+        let mut player = MoviePlayer::new(movie.clone(), &mut replayed_state).unwrap();
+
+        while player.play_frame(&mut replayed_state).unwrap() {}
+
+        assert!(player.is_finished());
+        assert_eq!(player.frame_count(), 40);
+        assert_eq!(hash_state(&replayed_state), recorded_hash);
+
+        // SUBCASE: desync
+        {
+            let mut replayed_state = create_test_state();
+            let mut tampered_movie = movie.clone();
+
+            tampered_movie.frames[9].key_state ^= 0x0002;
+
+            let mut player = MoviePlayer::new(tampered_movie, &mut replayed_state).unwrap();
+            let mut result = Ok(true);
+
+            while let Ok(true) = result {
+                result = player.play_frame(&mut replayed_state);
+            }
+
+            assert_eq!(result, Err(MovieError::Desync { frame: 12 }));
+        }
+
+        // SUBCASE: mismatching ROM
+        {
+            let mut other_state = cpu::create_chip8_state();
+
+            execution::load_program(&mut other_state, vec![0x12, 0x00]).unwrap();
+
+            assert_eq!(MoviePlayer::new(movie.clone(), &mut other_state).err(), Some(MovieError::RomMismatch));
+        }
+
+        // SUBCASE: malformed movies
+        {
+            assert_eq!(Movie::parse("chip8-movie 2\n"), Err(MovieError::UnsupportedVersion { version: 2 }));
+            assert_eq!(Movie::parse("not a movie\n"), Err(MovieError::InvalidFormat { line: 1 }));
+            assert_eq!(Movie::parse(&text.replace("f 14 5+", "f 14 5*")), Err(MovieError::InvalidFormat { line: 7 }));
+        }
+    }
+}
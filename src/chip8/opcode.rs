@@ -1,3 +1,9 @@
+use super::{
+    config::Platform,
+    fault::Chip8Fault,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum OpCode
 {
     CLS, // 00E0 - CLS
@@ -35,17 +41,45 @@ pub enum OpCode
     LDB { reg: u8 }, // Fx33 - LD B, Vx
     LDAI { reg: u8 }, // Fx55 - LD [I], Vx
     LDM { reg: u8 }, // Fx65 - LD Vx, [I]
+    // SUPER-CHIP 1.1
+    SCD { size: u8 }, // 00Cn - SCD nibble
+    SCR, // 00FB - SCR
+    SCL, // 00FC - SCL
+    EXIT, // 00FD - EXIT
+    LOW, // 00FE - LOW
+    HIGH, // 00FF - HIGH
+    LDHF { reg: u8 }, // Fx30 - LD HF, Vx
+    LDR { reg: u8 }, // Fx75 - LD R, Vx
+    LDVR { reg: u8 }, // Fx85 - LD Vx, R
+    // XO-CHIP
+    SCU { size: u8 }, // 00Dn - SCU nibble
+    SAVE { reg_x: u8, reg_y: u8 }, // 5xy2 - SAVE Vx, Vy
+    LOAD { reg_x: u8, reg_y: u8 }, // 5xy3 - LOAD Vx, Vy
+    LDIL, // F000 nnnn - LD I, long addr
+    PLANE { mask: u8 }, // Fn01 - PLANE n
+    AUDIO, // F002 - AUDIO
+    PITCH { reg: u8 }, // Fx3A - PITCH Vx
 }
 
-pub fn decode_instruction(instruction: u16) -> OpCode
+// The only instruction spanning two words, its operand is the following word.
+pub const LONG_INSTRUCTION_PREFIX: u16 = 0xF000;
+
+pub fn decode_instruction(instruction: u16) -> Result<OpCode, Chip8Fault>
 {
     let first_nibble = instruction & 0xF000;
 
-    match first_nibble {
+    let opcode = match first_nibble {
         0x0000 => {
             match decode_0xxx(instruction) {
                 0x00E0 => OpCode::CLS,
                 0x00EE => OpCode::RET,
+                0x00FB => OpCode::SCR,
+                0x00FC => OpCode::SCL,
+                0x00FD => OpCode::EXIT,
+                0x00FE => OpCode::LOW,
+                0x00FF => OpCode::HIGH,
+                addr if (addr & 0xFFF0) == 0x00C0 => OpCode::SCD {size: decode_000x(instruction)},
+                addr if (addr & 0xFFF0) == 0x00D0 => OpCode::SCU {size: decode_000x(instruction)},
                 _ => OpCode::SYS {addr: decode_0xxx(instruction)},
             }
         },
@@ -53,7 +87,16 @@ pub fn decode_instruction(instruction: u16) -> OpCode
         0x2000 => OpCode::CALL {addr: decode_0xxx(instruction)},
         0x3000 => OpCode::SE {reg: decode_0x00(instruction), value: decode_00xx(instruction)},
         0x4000 => OpCode::SNE {reg: decode_0x00(instruction), value: decode_00xx(instruction)},
-        0x5000 => OpCode::SE2 {reg_x: decode_0x00(instruction), reg_y: decode_00x0(instruction)},
+        0x5000 => {
+            let reg_x = decode_0x00(instruction);
+            let reg_y = decode_00x0(instruction);
+            match decode_000x(instruction) {
+                0x0 => OpCode::SE2 {reg_x, reg_y},
+                0x2 => OpCode::SAVE {reg_x, reg_y},
+                0x3 => OpCode::LOAD {reg_x, reg_y},
+                _ => return Err(Chip8Fault::InvalidOpcode),
+            }
+        },
         0x6000 => OpCode::LD {reg: decode_0x00(instruction), value: decode_00xx(instruction)},
         0x7000 => OpCode::ADD {reg: decode_0x00(instruction), value: decode_00xx(instruction)},
         0x8000 => {
@@ -69,7 +112,7 @@ pub fn decode_instruction(instruction: u16) -> OpCode
                 0x6 => OpCode::SHR {reg_x, reg_y},
                 0x7 => OpCode::SUBN {reg_x, reg_y},
                 0xE => OpCode::SHL {reg_x, reg_y},
-                _ => panic!("error: invalid opcode: 0x{:X}", instruction),
+                _ => return Err(Chip8Fault::InvalidOpcode),
             }
         },
         0x9000 => OpCode::SNE2 {reg_x: decode_0x00(instruction), reg_y: decode_00x0(instruction)},
@@ -82,28 +125,123 @@ pub fn decode_instruction(instruction: u16) -> OpCode
             match decode_00xx(instruction) {
                 0x9E => OpCode::SKP {reg},
                 0xA1 => OpCode::SKNP {reg},
-                _ => panic!("error: invalid opcode: 0x{:X}", instruction),
+                _ => return Err(Chip8Fault::InvalidOpcode),
             }
         },
         0xF000 => {
             let reg = decode_0x00(instruction);
             match decode_00xx(instruction) {
+                0x00 if reg == 0 => OpCode::LDIL,
+                0x01 if reg <= 0x3 => OpCode::PLANE {mask: reg},
+                0x02 if reg == 0 => OpCode::AUDIO,
                 0x07 => OpCode::LDT {reg},
                 0x0A => OpCode::LDK {reg},
                 0x15 => OpCode::LDDT {reg},
                 0x18 => OpCode::LDST {reg},
                 0x1E => OpCode::ADDI {reg},
                 0x29 => OpCode::LDF {reg},
+                0x30 => OpCode::LDHF {reg},
                 0x33 => OpCode::LDB {reg},
+                0x3A => OpCode::PITCH {reg},
                 0x55 => OpCode::LDAI {reg},
                 0x65 => OpCode::LDM {reg},
-                _ => panic!("error: invalid opcode: 0x{:X}", instruction),
+                0x75 => OpCode::LDR {reg},
+                0x85 => OpCode::LDVR {reg},
+                _ => return Err(Chip8Fault::InvalidOpcode),
             }
         },
-        _ => panic!("error: invalid opcode: 0x{:X}", instruction),
+        _ => return Err(Chip8Fault::InvalidOpcode),
+    };
+
+    Ok(opcode)
+}
+
+// Inverse of decode_instruction, operands are expected to fit their fields.
+// The operand of LDIL is not part of the returned word.
+pub fn encode_instruction(opcode: &OpCode) -> u16
+{
+    let encode_x = |reg: u8| u16::from(reg & 0xF) << 8;
+    let encode_xy = |reg_x: u8, reg_y: u8| encode_x(reg_x) | u16::from(reg_y & 0xF) << 4;
+    let encode_xkk = |reg: u8, value: u8| encode_x(reg) | u16::from(value);
+
+    match *opcode {
+        OpCode::CLS => 0x00E0,
+        OpCode::RET => 0x00EE,
+        OpCode::SYS{addr} => addr & 0x0FFF,
+        OpCode::JP{addr} => 0x1000 | (addr & 0x0FFF),
+        OpCode::CALL{addr} => 0x2000 | (addr & 0x0FFF),
+        OpCode::SE{reg, value} => 0x3000 | encode_xkk(reg, value),
+        OpCode::SNE{reg, value} => 0x4000 | encode_xkk(reg, value),
+        OpCode::SE2{reg_x, reg_y} => 0x5000 | encode_xy(reg_x, reg_y),
+        OpCode::LD{reg, value} => 0x6000 | encode_xkk(reg, value),
+        OpCode::ADD{reg, value} => 0x7000 | encode_xkk(reg, value),
+        OpCode::LD2{reg_x, reg_y} => 0x8000 | encode_xy(reg_x, reg_y),
+        OpCode::OR{reg_x, reg_y} => 0x8001 | encode_xy(reg_x, reg_y),
+        OpCode::AND{reg_x, reg_y} => 0x8002 | encode_xy(reg_x, reg_y),
+        OpCode::XOR{reg_x, reg_y} => 0x8003 | encode_xy(reg_x, reg_y),
+        OpCode::ADD2{reg_x, reg_y} => 0x8004 | encode_xy(reg_x, reg_y),
+        OpCode::SUB{reg_x, reg_y} => 0x8005 | encode_xy(reg_x, reg_y),
+        OpCode::SHR{reg_x, reg_y} => 0x8006 | encode_xy(reg_x, reg_y),
+        OpCode::SUBN{reg_x, reg_y} => 0x8007 | encode_xy(reg_x, reg_y),
+        OpCode::SHL{reg_x, reg_y} => 0x800E | encode_xy(reg_x, reg_y),
+        OpCode::SNE2{reg_x, reg_y} => 0x9000 | encode_xy(reg_x, reg_y),
+        OpCode::LDI{addr} => 0xA000 | (addr & 0x0FFF),
+        OpCode::JP2{addr} => 0xB000 | (addr & 0x0FFF),
+        OpCode::RND{reg, value} => 0xC000 | encode_xkk(reg, value),
+        OpCode::DRW{reg_x, reg_y, size} => 0xD000 | encode_xy(reg_x, reg_y) | u16::from(size & 0xF),
+        OpCode::SKP{reg} => 0xE09E | encode_x(reg),
+        OpCode::SKNP{reg} => 0xE0A1 | encode_x(reg),
+        OpCode::LDT{reg} => 0xF007 | encode_x(reg),
+        OpCode::LDK{reg} => 0xF00A | encode_x(reg),
+        OpCode::LDDT{reg} => 0xF015 | encode_x(reg),
+        OpCode::LDST{reg} => 0xF018 | encode_x(reg),
+        OpCode::ADDI{reg} => 0xF01E | encode_x(reg),
+        OpCode::LDF{reg} => 0xF029 | encode_x(reg),
+        OpCode::LDB{reg} => 0xF033 | encode_x(reg),
+        OpCode::LDAI{reg} => 0xF055 | encode_x(reg),
+        OpCode::LDM{reg} => 0xF065 | encode_x(reg),
+        OpCode::SCD{size} => 0x00C0 | u16::from(size & 0xF),
+        OpCode::SCR => 0x00FB,
+        OpCode::SCL => 0x00FC,
+        OpCode::EXIT => 0x00FD,
+        OpCode::LOW => 0x00FE,
+        OpCode::HIGH => 0x00FF,
+        OpCode::LDHF{reg} => 0xF030 | encode_x(reg),
+        OpCode::LDR{reg} => 0xF075 | encode_x(reg),
+        OpCode::LDVR{reg} => 0xF085 | encode_x(reg),
+        OpCode::SCU{size} => 0x00D0 | u16::from(size & 0xF),
+        OpCode::SAVE{reg_x, reg_y} => 0x5002 | encode_xy(reg_x, reg_y),
+        OpCode::LOAD{reg_x, reg_y} => 0x5003 | encode_xy(reg_x, reg_y),
+        OpCode::LDIL => LONG_INSTRUCTION_PREFIX,
+        OpCode::PLANE{mask} => 0xF001 | encode_x(mask),
+        OpCode::AUDIO => 0xF002,
+        OpCode::PITCH{reg} => 0xF03A | encode_x(reg),
     }
 }
 
+// Instructions introduced by later platforms are rejected on older ones.
+pub fn check_platform_support(opcode: &OpCode, platform: Platform) -> Result<(), Chip8Fault>
+{
+    let is_super_chip_opcode = matches!(opcode,
+        OpCode::SCD{..} | OpCode::SCR | OpCode::SCL | OpCode::EXIT | OpCode::LOW | OpCode::HIGH
+            | OpCode::LDHF{..} | OpCode::LDR{..} | OpCode::LDVR{..});
+    let is_xo_chip_opcode = matches!(opcode,
+        OpCode::SCU{..} | OpCode::SAVE{..} | OpCode::LOAD{..} | OpCode::LDIL | OpCode::PLANE{..}
+            | OpCode::AUDIO | OpCode::PITCH{..});
+
+    let is_supported = match platform {
+        Platform::Chip8 => !is_super_chip_opcode && !is_xo_chip_opcode,
+        Platform::SuperChip => !is_xo_chip_opcode,
+        Platform::XoChip => true,
+    };
+
+    if !is_supported {
+        return Err(Chip8Fault::InvalidOpcode);
+    }
+
+    Ok(())
+}
+
 // Address
 fn decode_0xxx(instruction: u16) -> u16
 {
@@ -131,3 +269,21 @@ fn decode_000x(instruction: u16) -> u8
 {
     (instruction & 0x000F) as u8
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode() {
+        // Every opcode survives a round trip, some words like 9xy1 are not canonical though
+        for instruction in 0..=0xFFFF_u16 {
+            if let Ok(opcode) = decode_instruction(instruction) {
+                assert_eq!(decode_instruction(encode_instruction(&opcode)), Ok(opcode));
+            }
+        }
+
+        assert_eq!(encode_instruction(&OpCode::DRW { reg_x: 0x3, reg_y: 0xA, size: 0xF }), 0xD3AF);
+        assert_eq!(encode_instruction(&OpCode::LDM { reg: 0x2 }), 0xF265);
+    }
+}
@@ -0,0 +1,97 @@
+use super::{
+    config::RandomMode,
+    cpu::CPUState,
+};
+
+// Galois feedback taps of x^8 + x^6 + x^5 + x^4 + 1, which go through all 255 non-zero states
+const LFSR_TAPS: u8 = 0xB8;
+
+// The generators are small enough to live in the machine state so that save states,
+// rewind and movie playback reproduce the exact same random sequence.
+pub fn seed_rng(state: &mut CPUState, seed: u64)
+{
+    state.rng_state = seed;
+}
+
+pub fn next_random_byte(state: &mut CPUState) -> u8
+{
+    match state.quirks.random_mode {
+        RandomMode::Modern => next_splitmix_byte(state),
+        RandomMode::Lfsr => next_lfsr_byte(state),
+    }
+}
+
+// SplitMix64
+fn next_splitmix_byte(state: &mut CPUState) -> u8
+{
+    state.rng_state = state.rng_state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+
+    let mut z = state.rng_state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^= z >> 31;
+
+    // The high bits are the best mixed
+    (z >> 56) as u8
+}
+
+// Only the low 8 bits of the state are used, a zero seed starts from 1 since the register would stay stuck at 0.
+fn next_lfsr_byte(state: &mut CPUState) -> u8
+{
+    let register = (state.rng_state as u8).max(1);
+    let value = if register & 1 != 0 { (register >> 1) ^ LFSR_TAPS } else { register >> 1 };
+
+    state.rng_state = u64::from(value);
+
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::cpu;
+
+    #[test]
+    fn random() {
+        let mut state = cpu::create_chip8_state();
+        let mut other_state = cpu::create_chip8_state();
+
+        seed_rng(&mut state, 1234);
+        seed_rng(&mut other_state, 1234);
+
+        let sequence: Vec<u8> = (0..64).map(|_| next_random_byte(&mut state)).collect();
+        let other_sequence: Vec<u8> = (0..64).map(|_| next_random_byte(&mut other_state)).collect();
+
+        assert_eq!(sequence, other_sequence);
+        assert!(sequence.iter().any(|&value| value != sequence[0]));
+
+        seed_rng(&mut other_state, 4321);
+
+        assert_ne!(next_random_byte(&mut other_state), sequence[0]);
+
+        // SUBCASE: LFSR
+        {
+            let mut state = cpu::create_chip8_state();
+
+            state.quirks.random_mode = RandomMode::Lfsr;
+            seed_rng(&mut state, 0);
+
+            assert_eq!(next_random_byte(&mut state), 0xB8);
+            assert_eq!(next_random_byte(&mut state), 0x5C);
+            assert_eq!(next_random_byte(&mut state), 0x2E);
+            assert_eq!(next_random_byte(&mut state), 0x17);
+            assert_eq!(next_random_byte(&mut state), 0xB3);
+
+            // Every non-zero byte comes once per period, whatever the program writes to memory
+            state.memory.iter_mut().for_each(|byte| *byte = 0xFF);
+
+            let mut sequence: Vec<u8> = (0..255).map(|_| next_random_byte(&mut state)).collect();
+
+            assert_eq!(state.rng_state, 0xB3);
+
+            sequence.sort_unstable();
+
+            assert_eq!(sequence, (1..=255).collect::<Vec<u8>>());
+        }
+    }
+}
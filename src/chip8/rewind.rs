@@ -0,0 +1,329 @@
+use super::{
+    cpu::CPUState,
+    savestate,
+};
+
+use std::collections::VecDeque;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RewindConfig
+{
+    pub buffer_size_in_bytes: usize,
+    pub snapshot_interval: u32, // In frames
+}
+
+impl Default for RewindConfig
+{
+    fn default() -> Self
+    {
+        RewindConfig {
+            buffer_size_in_bytes: 4 * 1024 * 1024,
+            snapshot_interval: 1,
+        }
+    }
+}
+
+// Zero runs shorter than this are cheaper to keep in the literal bytes
+const MIN_SKIPPED_RUN_SIZE_IN_BYTES: usize = 8;
+
+const DELTA_TAG_FULL: u8 = 0;
+const DELTA_TAG_XOR: u8 = 1;
+
+// Encodes how to get back from 'current' to 'previous'.
+// Snapshots of the same size are XORed, and the runs of unchanged bytes are skipped:
+// tag | (skip: u32, count: u32, count XORed bytes)*
+fn encode_delta(current: &[u8], previous: &[u8]) -> Vec<u8>
+{
+    // The screen size changes with the resolution, fall back to a full copy
+    if current.len() != previous.len() {
+        let mut delta = Vec::with_capacity(previous.len() + 1);
+
+        delta.push(DELTA_TAG_FULL);
+        delta.extend_from_slice(previous);
+
+        return delta;
+    }
+
+    let xor: Vec<u8> = current.iter().zip(previous.iter()).map(|(a, b)| a ^ b).collect();
+    let mut delta = vec![DELTA_TAG_XOR];
+    let mut offset = 0;
+
+    while offset < xor.len() {
+        let run_begin = offset;
+
+        while offset < xor.len() && xor[offset] == 0 {
+            offset += 1;
+        }
+
+        if offset == xor.len() {
+            break;
+        }
+
+        let literal_begin = offset;
+        let mut zero_count = 0;
+
+        // Stop the literal at the first long enough run of zeros
+        while offset < xor.len() && zero_count < MIN_SKIPPED_RUN_SIZE_IN_BYTES {
+            zero_count = if xor[offset] == 0 { zero_count + 1 } else { 0 };
+            offset += 1;
+        }
+
+        let literal_end = offset - zero_count;
+        offset = literal_end;
+
+        delta.extend_from_slice(&((literal_begin - run_begin) as u32).to_le_bytes());
+        delta.extend_from_slice(&((literal_end - literal_begin) as u32).to_le_bytes());
+        delta.extend_from_slice(&xor[literal_begin..literal_end]);
+    }
+
+    delta
+}
+
+fn read_u32(data: &[u8], offset: usize) -> usize
+{
+    let mut bytes = [0; 4];
+    bytes.copy_from_slice(&data[offset..offset + 4]);
+
+    u32::from_le_bytes(bytes) as usize
+}
+
+fn decode_delta(current: &[u8], delta: &[u8]) -> Vec<u8>
+{
+    if delta[0] == DELTA_TAG_FULL {
+        return delta[1..].to_vec();
+    }
+
+    let mut previous = current.to_vec();
+    let mut position = 0;
+    let mut offset = 1;
+
+    while offset < delta.len() {
+        let skip = read_u32(delta, offset);
+        let count = read_u32(delta, offset + 4);
+
+        offset += 8;
+        position += skip;
+
+        for (byte, xor) in previous[position..position + count].iter_mut().zip(&delta[offset..offset + count]) {
+            *byte ^= xor;
+        }
+
+        offset += count;
+        position += count;
+    }
+
+    previous
+}
+
+// History of the machine for rewinding, bounded by a memory budget.
+// Only the newest snapshot is kept whole, older ones are stored as deltas walking back from it.
+pub struct RewindBuffer
+{
+    config: RewindConfig,
+    newest_snapshot: Option<Vec<u8>>,
+    deltas: VecDeque<Vec<u8>>, // Oldest first
+    size_in_bytes: usize,
+    frames_since_snapshot: u32,
+}
+
+impl RewindBuffer
+{
+    pub fn new(config: RewindConfig) -> RewindBuffer
+    {
+        RewindBuffer {
+            config,
+            newest_snapshot: None,
+            deltas: VecDeque::new(),
+            size_in_bytes: 0,
+            frames_since_snapshot: 0,
+        }
+    }
+
+    // Call once per emulated frame, a snapshot is taken every 'snapshot_interval' frames.
+    pub fn record_frame(&mut self, state: &CPUState)
+    {
+        self.frames_since_snapshot += 1;
+
+        if self.frames_since_snapshot < self.config.snapshot_interval.max(1) {
+            return;
+        }
+
+        self.frames_since_snapshot = 0;
+
+        let snapshot = savestate::save_state(state);
+
+        if let Some(previous_snapshot) = self.newest_snapshot.take() {
+            let delta = encode_delta(&snapshot, &previous_snapshot);
+
+            self.size_in_bytes += delta.len();
+            self.size_in_bytes -= previous_snapshot.len();
+            self.deltas.push_back(delta);
+        }
+
+        self.size_in_bytes += snapshot.len();
+        self.newest_snapshot = Some(snapshot);
+
+        // Forget the oldest history first
+        while self.size_in_bytes > self.config.buffer_size_in_bytes {
+            match self.deltas.pop_front() {
+                Some(delta) => self.size_in_bytes -= delta.len(),
+                None => break,
+            }
+        }
+    }
+
+    // Restore the snapshot before the newest one and drop the newest.
+    // Returns false once the oldest snapshot is reached, the state is then restored to it.
+    pub fn rewind_frame(&mut self, state: &mut CPUState) -> bool
+    {
+        let newest_snapshot = match self.newest_snapshot.take() {
+            Some(snapshot) => snapshot,
+            None => return false,
+        };
+
+        let (snapshot, has_rewound) = match self.deltas.pop_back() {
+            Some(delta) => {
+                self.size_in_bytes -= newest_snapshot.len() + delta.len();
+
+                let previous_snapshot = decode_delta(&newest_snapshot, &delta);
+
+                self.size_in_bytes += previous_snapshot.len();
+
+                (previous_snapshot, true)
+            },
+            None => (newest_snapshot, false),
+        };
+
+        // Snapshots always come from the running ROM, so they can't be rejected
+        savestate::load_state(state, &snapshot).expect("invalid rewind snapshot");
+
+        self.newest_snapshot = Some(snapshot);
+        self.frames_since_snapshot = 0;
+
+        has_rewound
+    }
+
+    pub fn clear(&mut self)
+    {
+        self.newest_snapshot = None;
+        self.deltas.clear();
+        self.size_in_bytes = 0;
+        self.frames_since_snapshot = 0;
+    }
+
+    pub fn snapshot_count(&self) -> usize
+    {
+        self.deltas.len() + self.newest_snapshot.is_some() as usize
+    }
+
+    pub fn size_in_bytes(&self) -> usize
+    {
+        self.size_in_bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::{
+        config::Platform,
+        cpu,
+        display,
+        execution,
+        instruction,
+    };
+
+    #[test]
+    fn rewind() {
+        let mut state = cpu::create_chip8_state();
+
+        // ADD V0, 1 / JP 0x200
+        execution::load_program(&mut state, vec![0x70, 0x01, 0x12, 0x00]).unwrap();
+
+        let mut rewind = RewindBuffer::new(RewindConfig { buffer_size_in_bytes: 1024 * 1024, snapshot_interval: 2 });
+
+        for _ in 0..10 {
+            execution::execute_step(&mut state, cpu::DELAY_TIMER_PERIOD_MS).unwrap();
+            rewind.record_frame(&state);
+        }
+
+        // 8 instructions per frame, half of them adding
+        assert_eq!(state.v_registers[0], 40);
+        assert_eq!(rewind.snapshot_count(), 5);
+
+        // Deltas only hold the changed bytes
+        assert!(rewind.size_in_bytes() < savestate::save_state(&state).len() + 4 * 64);
+
+        assert!(rewind.rewind_frame(&mut state));
+        assert_eq!(state.v_registers[0], 32);
+
+        assert!(rewind.rewind_frame(&mut state));
+        assert_eq!(state.v_registers[0], 24);
+
+        // Recording resumes from the rewound state
+        execution::execute_step(&mut state, cpu::DELAY_TIMER_PERIOD_MS).unwrap();
+        rewind.record_frame(&state);
+        execution::execute_step(&mut state, cpu::DELAY_TIMER_PERIOD_MS).unwrap();
+        rewind.record_frame(&state);
+
+        assert_eq!(rewind.snapshot_count(), 4);
+
+        for expected_v0 in [24, 16, 8].iter() {
+            assert!(rewind.rewind_frame(&mut state));
+            assert_eq!(state.v_registers[0], *expected_v0);
+        }
+
+        // Stuck on the oldest snapshot
+        assert!(!rewind.rewind_frame(&mut state));
+        assert_eq!(state.v_registers[0], 8);
+
+        // SUBCASE: the budget drops the oldest snapshots
+        {
+            let snapshot_size = savestate::save_state(&state).len();
+            let mut rewind = RewindBuffer::new(RewindConfig { buffer_size_in_bytes: snapshot_size + 64, snapshot_interval: 1 });
+
+            for _ in 0..10 {
+                execution::execute_step(&mut state, cpu::DELAY_TIMER_PERIOD_MS).unwrap();
+                rewind.record_frame(&state);
+            }
+
+            assert!(rewind.size_in_bytes() <= snapshot_size + 64);
+            assert!(rewind.snapshot_count() > 1 && rewind.snapshot_count() < 10);
+        }
+
+        // SUBCASE: resolution changes
+        {
+            let mut state = cpu::create_chip8_state_for_platform(Platform::SuperChip);
+            let mut rewind = RewindBuffer::new(RewindConfig::default());
+
+            execution::load_program(&mut state, vec![0x00, 0xFF]).unwrap();
+
+            rewind.record_frame(&state);
+            display::set_hires(&mut state, true);
+            instruction::execute_drw(&mut state, 0, 0, 5).unwrap();
+            rewind.record_frame(&state);
+
+            assert!(rewind.rewind_frame(&mut state));
+            assert!(!state.is_hires);
+            assert_eq!(display::screen_width(&state), cpu::SCREEN_WIDTH);
+        }
+    }
+
+    #[test]
+    fn delta() {
+        let previous: Vec<u8> = (0..100).collect();
+        let mut current = previous.clone();
+
+        current[3] = 0xFF;
+        current[4] = 0xFE;
+        current[50] = 0x00;
+        current[99] = 0x42;
+
+        let delta = encode_delta(&current, &previous);
+
+        assert_eq!(delta.len(), 1 + 3 * 8 + 2 + 1 + 1);
+        assert_eq!(decode_delta(&current, &delta), previous);
+        assert_eq!(encode_delta(&previous, &previous), vec![DELTA_TAG_XOR]);
+        assert_eq!(decode_delta(&current[..10], &encode_delta(&current[..10], &previous)), previous);
+    }
+}
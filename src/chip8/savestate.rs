@@ -0,0 +1,372 @@
+use super::{
+    config::Platform,
+    cpu,
+    cpu::CPUState,
+    hash,
+};
+
+use std::{
+    error,
+    fmt,
+};
+
+// Layout, all integers little endian:
+// magic (4) | version (2) | platform (1) | ROM hash (8) | machine state | checksum (8)
+// The checksum is the FNV-1a hash of everything before it.
+const SAVE_STATE_MAGIC: [u8; 4] = *b"C8SS";
+pub const SAVE_STATE_VERSION: u16 = 4;
+
+const HEADER_SIZE_IN_BYTES: usize = 4 + 2 + 1 + 8;
+const CHECKSUM_SIZE_IN_BYTES: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SaveStateError
+{
+    InvalidFormat,
+    ChecksumMismatch,
+    UnsupportedVersion { version: u16 },
+    PlatformMismatch { platform: Platform },
+    RomMismatch,
+}
+
+impl fmt::Display for SaveStateError
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        match self {
+            SaveStateError::InvalidFormat => write!(f, "not a save state"),
+            SaveStateError::ChecksumMismatch => write!(f, "save state is corrupted"),
+            SaveStateError::UnsupportedVersion{version} => write!(f, "unsupported save state version {}", version),
+            SaveStateError::PlatformMismatch{platform} => write!(f, "save state was made on another platform ({:?})", platform),
+            SaveStateError::RomMismatch => write!(f, "save state was made with another ROM"),
+        }
+    }
+}
+
+impl error::Error for SaveStateError {}
+
+fn platform_to_id(platform: Platform) -> u8
+{
+    match platform {
+        Platform::Chip8 => 0,
+        Platform::SuperChip => 1,
+        Platform::XoChip => 2,
+    }
+}
+
+fn platform_from_id(id: u8) -> Option<Platform>
+{
+    match id {
+        0 => Some(Platform::Chip8),
+        1 => Some(Platform::SuperChip),
+        2 => Some(Platform::XoChip),
+        _ => None,
+    }
+}
+
+struct Reader<'a>
+{
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a>
+{
+    fn read_bytes(&mut self, size: usize) -> Result<&'a [u8], SaveStateError>
+    {
+        let bytes = self.data.get(self.offset..self.offset + size).ok_or(SaveStateError::InvalidFormat)?;
+
+        self.offset += size;
+
+        Ok(bytes)
+    }
+
+    fn read_u8(&mut self) -> Result<u8, SaveStateError>
+    {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    fn read_u16(&mut self) -> Result<u16, SaveStateError>
+    {
+        let bytes = self.read_bytes(2)?;
+
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn read_u32(&mut self) -> Result<u32, SaveStateError>
+    {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(self.read_bytes(4)?);
+
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    fn read_u64(&mut self) -> Result<u64, SaveStateError>
+    {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.read_bytes(8)?);
+
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    fn read_bool(&mut self) -> Result<bool, SaveStateError>
+    {
+        match self.read_u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(SaveStateError::InvalidFormat),
+        }
+    }
+}
+
+// Configuration such as quirks is not part of the snapshot, the host keeps it.
+pub fn save_state(state: &CPUState) -> Vec<u8>
+{
+    let mut data = Vec::with_capacity(HEADER_SIZE_IN_BYTES + state.memory.len() + 0x1000);
+
+    data.extend_from_slice(&SAVE_STATE_MAGIC);
+    data.extend_from_slice(&SAVE_STATE_VERSION.to_le_bytes());
+    data.push(platform_to_id(state.platform));
+    data.extend_from_slice(&state.rom_hash.to_le_bytes());
+
+    data.extend_from_slice(&state.pc.to_le_bytes());
+    data.push(state.sp);
+    for address in state.stack.iter() {
+        data.extend_from_slice(&address.to_le_bytes());
+    }
+    data.extend_from_slice(&state.v_registers);
+    data.extend_from_slice(&state.i.to_le_bytes());
+
+    data.push(state.delay_timer);
+    data.push(state.sound_timer);
+    data.extend_from_slice(&state.delay_timer_accumulator.to_le_bytes());
+    data.extend_from_slice(&state.execution_timer_accumulator.to_le_bytes());
+    data.extend_from_slice(&state.cycle_budget.to_le_bytes());
+    data.extend_from_slice(&state.frame_time_accumulator.to_le_bytes());
+
+    data.extend_from_slice(&state.memory);
+
+    data.extend_from_slice(&state.key_state.to_le_bytes());
+    data.extend_from_slice(&state.key_state_prev.to_le_bytes());
+    data.push(state.is_waiting_for_key as u8);
+    data.push(state.is_waiting_for_display as u8);
+    data.push(state.is_halted as u8);
+
+    data.extend_from_slice(&state.rpl_flags);
+    data.extend_from_slice(&state.audio_pattern);
+    data.push(state.audio_pitch);
+    data.extend_from_slice(&state.rng_state.to_le_bytes());
+
+    // The screen size follows from the resolution flag
+    data.push(state.is_hires as u8);
+    data.push(state.selected_planes);
+    for plane in state.screen.iter() {
+        for scanline in plane.iter() {
+            data.extend_from_slice(scanline);
+        }
+    }
+
+    let checksum = hash::fnv1a_64(&data);
+    data.extend_from_slice(&checksum.to_le_bytes());
+
+    data
+}
+
+// The state is left untouched if the snapshot is rejected.
+pub fn load_state(state: &mut CPUState, data: &[u8]) -> Result<(), SaveStateError>
+{
+    if data.len() < HEADER_SIZE_IN_BYTES + CHECKSUM_SIZE_IN_BYTES || data[0..4] != SAVE_STATE_MAGIC {
+        return Err(SaveStateError::InvalidFormat);
+    }
+
+    let (content, checksum) = data.split_at(data.len() - CHECKSUM_SIZE_IN_BYTES);
+
+    if hash::fnv1a_64(content) != (Reader { data: checksum, offset: 0 }).read_u64()? {
+        return Err(SaveStateError::ChecksumMismatch);
+    }
+
+    let mut reader = Reader { data: content, offset: SAVE_STATE_MAGIC.len() };
+
+    let version = reader.read_u16()?;
+    if version != SAVE_STATE_VERSION {
+        return Err(SaveStateError::UnsupportedVersion { version });
+    }
+
+    let platform = platform_from_id(reader.read_u8()?).ok_or(SaveStateError::InvalidFormat)?;
+    if platform != state.platform {
+        return Err(SaveStateError::PlatformMismatch { platform });
+    }
+
+    if reader.read_u64()? != state.rom_hash {
+        return Err(SaveStateError::RomMismatch);
+    }
+
+    // Decode into a fresh machine first so that a truncated snapshot can't leave a half loaded state
+    let mut new_state = cpu::create_chip8_state_for_platform(platform);
+
+    new_state.quirks = state.quirks;
+    new_state.rom_hash = state.rom_hash;
+
+    new_state.pc = reader.read_u16()?;
+    new_state.sp = reader.read_u8()?;
+    // CALL writes to stack[sp] once sp is incremented, so the last slot is the deepest sp can go
+    if new_state.sp as usize >= cpu::STACK_SIZE {
+        return Err(SaveStateError::InvalidFormat);
+    }
+    for address in new_state.stack.iter_mut() {
+        *address = reader.read_u16()?;
+    }
+    new_state.v_registers.copy_from_slice(reader.read_bytes(cpu::V_REGISTER_COUNT)?);
+    new_state.i = reader.read_u16()?;
+
+    new_state.delay_timer = reader.read_u8()?;
+    new_state.sound_timer = reader.read_u8()?;
+    new_state.delay_timer_accumulator = reader.read_u32()?;
+    new_state.execution_timer_accumulator = reader.read_u32()?;
+    new_state.cycle_budget = reader.read_u32()? as i32;
+    new_state.frame_time_accumulator = reader.read_u64()?;
+
+    let memory_size = new_state.memory.len();
+    new_state.memory.copy_from_slice(reader.read_bytes(memory_size)?);
+
+    new_state.key_state = reader.read_u16()?;
+    new_state.key_state_prev = reader.read_u16()?;
+    new_state.is_waiting_for_key = reader.read_bool()?;
+    new_state.is_waiting_for_display = reader.read_bool()?;
+    new_state.is_halted = reader.read_bool()?;
+
+    new_state.rpl_flags.copy_from_slice(reader.read_bytes(cpu::RPL_FLAG_COUNT)?);
+    new_state.audio_pattern.copy_from_slice(reader.read_bytes(cpu::AUDIO_PATTERN_SIZE_IN_BYTES)?);
+    new_state.audio_pitch = reader.read_u8()?;
+    new_state.rng_state = reader.read_u64()?;
+
+    new_state.is_hires = reader.read_bool()?;
+    new_state.selected_planes = reader.read_u8()?;
+
+    let (width, height) = if new_state.is_hires {
+        (cpu::HIRES_SCREEN_WIDTH, cpu::HIRES_SCREEN_HEIGHT)
+    } else {
+        (cpu::SCREEN_WIDTH, cpu::SCREEN_HEIGHT)
+    };
+
+    for plane in new_state.screen.iter_mut() {
+        *plane = (0..height)
+            .map(|_| reader.read_bytes(width / 8).map(|scanline| scanline.to_vec()))
+            .collect::<Result<_, _>>()?;
+    }
+
+    if reader.offset != content.len() {
+        return Err(SaveStateError::InvalidFormat);
+    }
+
+    // Not part of the machine, they are kept as is
+    new_state.frame_count = state.frame_count;
+    new_state.instruction_count = state.instruction_count;
+    new_state.tracer = state.tracer.take();
+
+    *state = new_state;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::{
+        display,
+        execution,
+    };
+
+    #[test]
+    fn save_state_round_trip() {
+        let mut state = cpu::create_chip8_state_for_platform(Platform::SuperChip);
+
+        // LD V0, 0x0A / LD F, V0 / HIGH / DRW V1, V1, 5 / CALL 0x20A / RET
+        execution::load_program(&mut state, vec![0x60, 0x0A, 0xF0, 0x29, 0x00, 0xFF, 0xD1, 0x15, 0x22, 0x0A, 0x00, 0xEE]).unwrap();
+
+        for _ in 0..5 {
+            execution::execute_step(&mut state, cpu::INSTRUCTION_EXECUTION_PERIOD_MS).unwrap();
+        }
+
+        state.delay_timer = 42;
+        state.key_state = 0x0100;
+
+        let snapshot = save_state(&state);
+
+        let mut restored = cpu::create_chip8_state_for_platform(Platform::SuperChip);
+
+        execution::load_program(&mut restored, vec![0x60, 0x0A, 0xF0, 0x29, 0x00, 0xFF, 0xD1, 0x15, 0x22, 0x0A, 0x00, 0xEE]).unwrap();
+        load_state(&mut restored, &snapshot).unwrap();
+
+        assert_eq!(restored.pc, state.pc);
+        assert_eq!(restored.sp, 1);
+        assert_eq!(restored.stack, state.stack);
+        assert_eq!(restored.v_registers, state.v_registers);
+        assert_eq!(restored.i, state.i);
+        assert_eq!(restored.delay_timer, 42);
+        assert_eq!(restored.key_state, 0x0100);
+        assert_eq!(restored.rng_state, state.rng_state);
+        assert_eq!(restored.memory, state.memory);
+        assert!(restored.is_hires);
+        assert_eq!(display::screen_width(&restored), cpu::HIRES_SCREEN_WIDTH);
+        assert_eq!(restored.screen, state.screen);
+        assert!(display::read_screen_pixel(&restored, 0, 0));
+
+        // Both keep running identically
+        execution::execute_step(&mut state, cpu::INSTRUCTION_EXECUTION_PERIOD_MS).unwrap();
+        execution::execute_step(&mut restored, cpu::INSTRUCTION_EXECUTION_PERIOD_MS).unwrap();
+
+        assert_eq!(restored.pc, state.pc);
+        assert_eq!(restored.sp, 0);
+        assert_eq!(save_state(&restored), save_state(&state));
+
+        // SUBCASE: mismatching ROM
+        {
+            let mut other = cpu::create_chip8_state_for_platform(Platform::SuperChip);
+
+            execution::load_program(&mut other, vec![0x12, 0x00]).unwrap();
+
+            assert_eq!(load_state(&mut other, &snapshot), Err(SaveStateError::RomMismatch));
+            assert_eq!(other.pc, cpu::MIN_PROGRAM_ADDRESS as u16);
+        }
+
+        // SUBCASE: mismatching platform
+        {
+            let mut other = cpu::create_chip8_state();
+
+            other.rom_hash = state.rom_hash;
+
+            assert_eq!(load_state(&mut other, &snapshot), Err(SaveStateError::PlatformMismatch { platform: Platform::SuperChip }));
+        }
+
+        // SUBCASE: corrupted or truncated data
+        {
+            let mut corrupted = snapshot.clone();
+            corrupted[HEADER_SIZE_IN_BYTES] ^= 0x01;
+
+            assert_eq!(load_state(&mut restored, &corrupted), Err(SaveStateError::ChecksumMismatch));
+            assert_eq!(load_state(&mut restored, &snapshot[..HEADER_SIZE_IN_BYTES]), Err(SaveStateError::InvalidFormat));
+            assert_eq!(load_state(&mut restored, b"not a save state"), Err(SaveStateError::InvalidFormat));
+
+            // A stack pointer past the last slot, with a checksum that matches
+            let mut overflowing = snapshot[..snapshot.len() - CHECKSUM_SIZE_IN_BYTES].to_vec();
+            overflowing[HEADER_SIZE_IN_BYTES + 2] = cpu::STACK_SIZE as u8;
+
+            let checksum = hash::fnv1a_64(&overflowing);
+            overflowing.extend_from_slice(&checksum.to_le_bytes());
+
+            assert_eq!(load_state(&mut restored, &overflowing), Err(SaveStateError::InvalidFormat));
+        }
+
+        // SUBCASE: future versions
+        {
+            let mut future = snapshot[..snapshot.len() - CHECKSUM_SIZE_IN_BYTES].to_vec();
+            future[4..6].copy_from_slice(&(SAVE_STATE_VERSION + 1).to_le_bytes());
+
+            let checksum = hash::fnv1a_64(&future);
+            future.extend_from_slice(&checksum.to_le_bytes());
+
+            assert_eq!(load_state(&mut restored, &future), Err(SaveStateError::UnsupportedVersion { version: SAVE_STATE_VERSION + 1 }));
+        }
+    }
+}
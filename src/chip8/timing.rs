@@ -0,0 +1,175 @@
+use super::{
+    cpu::CPUState,
+    opcode::OpCode,
+};
+
+// COSMAC VIP timing, in 1802 machine cycles of 8 clock cycles at 1.76 MHz.
+// The costs follow the published analyses of the VIP interpreter. They are close to the real machine
+// but not cycle exact, the interpreter's own loops are averaged per instruction.
+pub const VIP_CYCLES_PER_FRAME: i32 = 3668;
+
+// The 60 Hz interrupt updates the timers and feeds the display DMA, which takes this much of every frame
+pub const VIP_INTERRUPT_CYCLES: i32 = 1832;
+
+pub const VIP_INTERPRETER_CYCLES_PER_FRAME: i32 = VIP_CYCLES_PER_FRAME - VIP_INTERRUPT_CYCLES;
+
+// Fetching and decoding, paid by every instruction
+const VIP_FETCH_CYCLES: i32 = 68;
+
+// Added to a skip instruction when the condition holds
+pub const VIP_SKIP_CYCLES: i32 = 4;
+
+fn digit_sum(value: u8) -> i32
+{
+    i32::from(value / 100 + value / 10 % 10 + value % 10)
+}
+
+fn draw_cycles(state: &CPUState, reg_x: u8, size: u8) -> i32
+{
+    // Sprites that are not byte aligned are shifted one bit at a time into two bytes
+    let shift = i32::from(state.v_registers[reg_x as usize] % 8);
+    let row_cycles = if shift == 0 { 34 } else { 46 + 8 * shift };
+
+    26 + i32::from(size) * row_cycles
+}
+
+// Cost of the instruction about to be executed, reads the registers it depends on.
+// Skips taken are charged separately, see VIP_SKIP_CYCLES.
+pub fn instruction_cycles(state: &CPUState, opcode: &OpCode) -> i32
+{
+    let reg_value = |reg: u8| state.v_registers[reg as usize];
+
+    let execution_cycles = match *opcode {
+        OpCode::CLS => 3078,
+        OpCode::RET => 10,
+        OpCode::SYS{..} => 26,
+        OpCode::JP{..} => 12,
+        OpCode::CALL{..} => 26,
+        OpCode::SE{..} | OpCode::SNE{..} => 10,
+        OpCode::SE2{..} | OpCode::SNE2{..} => 14,
+        OpCode::LD{..} => 6,
+        OpCode::ADD{..} => 10,
+        OpCode::LD2{..} => 12,
+        OpCode::OR{..} | OpCode::AND{..} | OpCode::XOR{..} | OpCode::ADD2{..}
+            | OpCode::SUB{..} | OpCode::SHR{..} | OpCode::SUBN{..} | OpCode::SHL{..} => 44,
+        OpCode::LDI{..} => 12,
+        OpCode::JP2{..} => 22,
+        OpCode::RND{..} => 36,
+        OpCode::DRW{reg_x, size, ..} => draw_cycles(state, reg_x, size),
+        OpCode::SKP{..} | OpCode::SKNP{..} => 14,
+        OpCode::LDT{..} | OpCode::LDDT{..} | OpCode::LDST{..} => 10,
+        OpCode::LDK{..} => 18,
+        OpCode::ADDI{..} => 16,
+        OpCode::LDF{..} => 20,
+        OpCode::LDB{reg} => 80 + 16 * digit_sum(reg_value(reg)),
+        OpCode::LDAI{reg} | OpCode::LDM{reg} => 18 + 14 * (i32::from(reg) + 1),
+        // Not on the VIP, priced like a register load
+        _ => 12,
+    };
+
+    VIP_FETCH_CYCLES + execution_cycles
+}
+
+pub fn is_skip(opcode: &OpCode) -> bool
+{
+    matches!(opcode, OpCode::SE{..} | OpCode::SNE{..} | OpCode::SE2{..} | OpCode::SNE2{..} | OpCode::SKP{..} | OpCode::SKNP{..})
+}
+
+// Called by the 60 Hz interrupt. Cycles left over while waiting are lost,
+// an instruction that ran past the end of the previous frame eats into this one.
+pub fn start_frames(state: &mut CPUState, frame_count: u32)
+{
+    let frame_cycles = (frame_count.min(i32::MAX as u32) as i32).saturating_mul(VIP_INTERPRETER_CYCLES_PER_FRAME);
+
+    state.cycle_budget = state.cycle_budget.min(0).saturating_add(frame_cycles);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::{
+        assembler,
+        config::TimingMode,
+        cpu,
+        execution,
+    };
+
+    fn run_frames(source: &str, timing: TimingMode, frame_count: u32) -> CPUState
+    {
+        let mut state = cpu::create_chip8_state();
+
+        state.quirks.timing = timing;
+        execution::load_program(&mut state, assembler::assemble(source).unwrap()).unwrap();
+
+        for _ in 0..frame_count {
+            execution::execute_step(&mut state, cpu::DELAY_TIMER_PERIOD_MS).unwrap();
+        }
+
+        state
+    }
+
+    const COUNTER: &str = "
+        loop:
+            ADD V0, 1
+            JP back
+        back:
+            JP loop
+    ";
+
+    #[test]
+    fn timing() {
+        let mut state = cpu::create_chip8_state();
+
+        assert_eq!(instruction_cycles(&state, &OpCode::LD { reg: 0, value: 0 }), 74);
+        assert_eq!(instruction_cycles(&state, &OpCode::DRW { reg_x: 0, reg_y: 1, size: 5 }), 68 + 26 + 5 * 34);
+
+        state.v_registers[0] = 3;
+
+        assert_eq!(instruction_cycles(&state, &OpCode::DRW { reg_x: 0, reg_y: 1, size: 5 }), 68 + 26 + 5 * (46 + 24));
+
+        state.v_registers[2] = 128;
+
+        assert_eq!(instruction_cycles(&state, &OpCode::LDB { reg: 2 }), 68 + 80 + 16 * 11);
+        assert_eq!(instruction_cycles(&state, &OpCode::LDAI { reg: 3 }), 68 + 18 + 4 * 14);
+        assert!(is_skip(&OpCode::SKNP { reg: 0 }));
+        assert!(!is_skip(&OpCode::JP { addr: 0x200 }));
+
+        // Fixed timing runs 8 instructions per 16 ms frame, VIP timing as many as the frame's cycles pay for
+        let state = run_frames(COUNTER, TimingMode::Fixed, 1);
+
+        assert_eq!(state.v_registers[0], 3);
+
+        let state = run_frames(COUNTER, TimingMode::CosmacVip, 1);
+
+        // A round costs 78 cycles for ADD and 80 for each JP, 7 rounds fit and the 8th starts with 170 cycles left
+        assert_eq!(state.v_registers[0], 8);
+        assert!(state.cycle_budget <= 0);
+
+        // SUBCASE: overrun
+        {
+            // Clearing the screen takes more than a frame, the counter only starts on the next one
+            let source = format!("CLS\n{}", COUNTER);
+
+            let state = run_frames(&source, TimingMode::CosmacVip, 1);
+
+            assert_eq!(state.v_registers[0], 0);
+            assert_eq!(state.cycle_budget, VIP_INTERPRETER_CYCLES_PER_FRAME - 3146);
+
+            let state = run_frames(&source, TimingMode::CosmacVip, 2);
+
+            assert_eq!(state.v_registers[0], 3);
+        }
+
+        // SUBCASE: waiting
+        {
+            // Cycles are not saved up while waiting for a key
+            let mut state = run_frames("LD V1, K\nADD V0, 1\nJP 0x202", TimingMode::CosmacVip, 10);
+
+            state.key_state = 0x0002;
+            execution::execute_step(&mut state, cpu::DELAY_TIMER_PERIOD_MS).unwrap();
+
+            assert!(state.cycle_budget <= 0);
+            assert!(state.v_registers[0] < 24);
+        }
+    }
+}
@@ -0,0 +1,258 @@
+use super::{
+    cpu::CPUState,
+    disassembler,
+    fault::ExecutionError,
+    opcode::OpCode,
+};
+
+use std::{
+    collections::VecDeque,
+    io::{self, Write},
+    ops::RangeInclusive,
+};
+
+// Instructions outside of any range are left out of the trace
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TraceFilter
+{
+    pub addresses: Option<RangeInclusive<u16>>,
+    pub frames: Option<RangeInclusive<u32>>,
+    pub calls_only: bool, // Only CALL and RET
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TraceConfig
+{
+    pub filter: TraceFilter,
+    pub ring_buffer_size: usize, // When not 0, only the last instructions are written, once a fault happens
+}
+
+// Writes one line per executed instruction, with the machine state after it:
+// <frame> <pc> <opcode> V=<V0-VF> I=<i> SP=<sp> <mnemonic>
+// A fault ends with: <frame> <pc> <opcode> FAULT <description>
+pub struct Tracer
+{
+    config: TraceConfig,
+    writer: Box<dyn Write>,
+    ring_buffer: VecDeque<String>,
+    error: Option<io::Error>, // The first write error, execution goes on regardless
+}
+
+fn format_entry(state: &CPUState, pc: u16, instruction: u16, opcode: &OpCode) -> String
+{
+    let long_operand = match opcode {
+        OpCode::LDIL => {
+            let address = pc as usize + 2;
+            state.memory.get(address..address + 2).map_or(0, |bytes| u16::from(bytes[0]) << 8 | u16::from(bytes[1]))
+        },
+        _ => 0,
+    };
+
+    let v_registers: String = state.v_registers.iter().map(|value| format!("{:02X}", value)).collect();
+    let mnemonic = disassembler::format_opcode(opcode, long_operand, &disassembler::format_raw_address);
+
+    format!("{} {:04X} {:04X} V={} I={:04X} SP={} {}", state.frame_count, pc, instruction, v_registers, state.i, state.sp, mnemonic)
+}
+
+impl Tracer
+{
+    pub fn new(config: TraceConfig, writer: Box<dyn Write>) -> Tracer
+    {
+        Tracer {
+            config,
+            writer,
+            ring_buffer: VecDeque::new(),
+            error: None,
+        }
+    }
+
+    fn is_traced(&self, state: &CPUState, pc: u16, opcode: &OpCode) -> bool
+    {
+        let filter = &self.config.filter;
+
+        filter.addresses.as_ref().is_none_or(|range| range.contains(&pc))
+            && filter.frames.as_ref().is_none_or(|range| range.contains(&state.frame_count))
+            && (!filter.calls_only || matches!(opcode, OpCode::CALL{..} | OpCode::RET))
+    }
+
+    fn write_line(&mut self, line: &str)
+    {
+        if self.error.is_none() {
+            self.error = writeln!(self.writer, "{}", line).err();
+        }
+    }
+
+    // Called once the instruction at 'pc' has been executed.
+    pub fn record(&mut self, state: &CPUState, pc: u16, instruction: u16, opcode: &OpCode)
+    {
+        if !self.is_traced(state, pc, opcode) {
+            return;
+        }
+
+        let line = format_entry(state, pc, instruction, opcode);
+
+        if self.config.ring_buffer_size == 0 {
+            self.write_line(&line);
+        } else {
+            if self.ring_buffer.len() == self.config.ring_buffer_size {
+                self.ring_buffer.pop_front();
+            }
+
+            self.ring_buffer.push_back(line);
+        }
+    }
+
+    // Faults are always written, after the instructions that led to them in ring buffer mode.
+    pub fn record_fault(&mut self, state: &CPUState, error: &ExecutionError)
+    {
+        while let Some(line) = self.ring_buffer.pop_front() {
+            self.write_line(&line);
+        }
+
+        let line = format!("{} {:04X} {:04X} FAULT {}", state.frame_count, error.pc, error.instruction, error.fault);
+
+        self.write_line(&line);
+    }
+
+    // Flushes the trace, returning the first write error if any.
+    pub fn finish(mut self) -> io::Result<()>
+    {
+        match self.error.take() {
+            Some(error) => Err(error),
+            None => self.writer.flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::{
+        assembler,
+        cpu,
+        execution,
+    };
+
+    use std::{
+        cell::RefCell,
+        rc::Rc,
+    };
+
+    // Keeps the output readable once the tracer owns the writer
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedBuffer
+    {
+        fn write(&mut self, data: &[u8]) -> io::Result<usize>
+        {
+            self.0.borrow_mut().write(data)
+        }
+
+        fn flush(&mut self) -> io::Result<()>
+        {
+            Ok(())
+        }
+    }
+
+    const PROGRAM: &str = "
+            LD V0, 0x10     ; 0x200
+            CALL sub        ; 0x202
+        loop:
+            ADD V1, 1       ; 0x204
+            JP back         ; 0x206
+        back:
+            JP loop         ; 0x208
+        sub:
+            LD I, 0x300     ; 0x20A
+            RET             ; 0x20C
+    ";
+
+    // Runs 'frame_count' frames and returns the trace lines
+    fn trace_program(source: &str, config: TraceConfig, frame_count: u32) -> (Vec<String>, Option<ExecutionError>)
+    {
+        let mut state = cpu::create_chip8_state();
+        let buffer = SharedBuffer::default();
+
+        execution::load_program(&mut state, assembler::assemble(source).unwrap()).unwrap();
+        state.tracer = Some(Box::new(Tracer::new(config, Box::new(buffer.clone()))));
+
+        let mut fault = None;
+
+        for _ in 0..frame_count {
+            if let Err(error) = execution::execute_step(&mut state, cpu::DELAY_TIMER_PERIOD_MS) {
+                fault = Some(error);
+                break;
+            }
+        }
+
+        state.tracer.take().unwrap().finish().unwrap();
+
+        let text = String::from_utf8(buffer.0.borrow().clone()).unwrap();
+
+        (text.lines().map(str::to_string).collect(), fault)
+    }
+
+    #[test]
+    fn trace() {
+        let (lines, _) = trace_program(PROGRAM, TraceConfig::default(), 2);
+
+        // 8 instructions per frame
+        assert_eq!(lines.len(), 16);
+        assert_eq!(lines[0], "1 0200 6010 V=10000000000000000000000000000000 I=0000 SP=0 LD V0, 0x10");
+        assert_eq!(lines[1], "1 0202 220A V=10000000000000000000000000000000 I=0000 SP=1 CALL 0x20A");
+        assert_eq!(lines[2], "1 020A A300 V=10000000000000000000000000000000 I=0300 SP=1 LD I, 0x300");
+        assert_eq!(lines[4], "1 0204 7101 V=10010000000000000000000000000000 I=0300 SP=0 ADD V1, 0x01");
+        assert!(lines[15].starts_with("2 "));
+
+        // SUBCASE: filters
+        {
+            let config = TraceConfig {
+                filter: TraceFilter { addresses: Some(0x204..=0x206), frames: Some(2..=3), calls_only: false },
+                ring_buffer_size: 0,
+            };
+            let (lines, _) = trace_program(PROGRAM, config, 4);
+
+            assert!(lines.iter().all(|line| line.starts_with("2 0204 ") || line.starts_with("2 0206 ")
+                || line.starts_with("3 0204 ") || line.starts_with("3 0206 ")));
+
+            // 16 instructions in frames 2 and 3, 5 of them at 0x208
+            assert_eq!(lines.len(), 11);
+
+            let config = TraceConfig {
+                filter: TraceFilter { calls_only: true, ..TraceFilter::default() },
+                ring_buffer_size: 0,
+            };
+            let (lines, _) = trace_program(PROGRAM, config, 4);
+
+            assert_eq!(lines.len(), 2);
+            assert!(lines[0].ends_with(" CALL 0x20A"));
+            assert!(lines[1].ends_with(" RET"));
+        }
+
+        // SUBCASE: ring buffer
+        {
+            let config = TraceConfig { ring_buffer_size: 3, ..TraceConfig::default() };
+            let (lines, _) = trace_program(PROGRAM, config.clone(), 4);
+
+            assert!(lines.is_empty());
+
+            // RET with an empty stack
+            let (lines, fault) = trace_program("LD V0, 1\nLD V1, 2\nLD V2, 3\nLD V3, 4\nRET", config.clone(), 1);
+
+            assert!(fault.is_some());
+            assert_eq!(lines.len(), 4);
+            assert!(lines[0].ends_with(" LD V1, 0x02"));
+            assert!(lines[2].ends_with(" LD V3, 0x04"));
+            assert_eq!(lines[3], "1 0208 00EE FAULT stack underflow");
+
+            // PC running off the end of memory, past an instruction copied to the last 2 bytes
+            let (lines, fault) = trace_program("LD I, 0xFFE\nLD V0, 0x60\nLD V1, 0x01\nLD [I], V1\nJP 0xFFE", config, 1);
+
+            assert_eq!(fault.map(|error| error.pc), Some(0x1000));
+            assert_eq!(lines.len(), 4);
+            assert!(lines[2].ends_with(" LD V0, 0x01"));
+            assert!(lines[3].starts_with("1 1000 0000 FAULT "));
+        }
+    }
+}
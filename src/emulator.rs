@@ -0,0 +1,191 @@
+use crate::chip8::{
+    audio,
+    config::EmuConfig,
+    cpu,
+    cpu::CPUState,
+    display,
+    execution,
+    fault::{Chip8Fault, ExecutionError},
+    keyboard,
+    keyboard::KeyID,
+    random,
+    savestate,
+    savestate::SaveStateError,
+};
+
+use std::time::Duration;
+
+// Stable entry point for frontends and tools embedding the interpreter.
+// It owns the machine state and only exposes what a host needs to drive it.
+pub struct Emulator
+{
+    state: CPUState,
+}
+
+impl Default for Emulator
+{
+    fn default() -> Self
+    {
+        Self::new()
+    }
+}
+
+impl Emulator
+{
+    pub fn new() -> Emulator
+    {
+        Emulator {
+            state: cpu::create_chip8_state(),
+        }
+    }
+
+    // Only the settings affecting emulation are taken from the config.
+    pub fn with_config(config: &EmuConfig) -> Emulator
+    {
+        let mut state = cpu::create_chip8_state_for_platform(config.platform);
+
+        state.quirks = config.quirks;
+
+        Emulator {
+            state,
+        }
+    }
+
+    // Make RND reproducible, it is seeded randomly otherwise.
+    pub fn seed_rng(&mut self, seed: u64)
+    {
+        random::seed_rng(&mut self.state, seed);
+    }
+
+    pub fn load_program(&mut self, program: Vec<u8>) -> Result<(), Chip8Fault>
+    {
+        execution::load_program(&mut self.state, program)
+    }
+
+    // Advance the machine by the given amount of wall-clock time.
+    // On fault PC and registers are left at the faulting instruction,
+    // the timers and frame clock have already moved on by the whole step.
+    pub fn execute_step(&mut self, delta_time_ms: u32) -> Result<(), ExecutionError>
+    {
+        execution::execute_step(&mut self.state, delta_time_ms)
+    }
+
+    // Same as execute_step with a finer clock, see execution::execute_duration_until.
+    pub fn execute_duration(&mut self, elapsed: Duration) -> Result<(), ExecutionError>
+    {
+        execution::execute_duration_until(&mut self.state, elapsed, &mut |_| false).map(|_| ())
+    }
+
+    // Execute a single raw instruction, regardless of what PC points to.
+    pub fn execute_instruction(&mut self, instruction: u16) -> Result<(), ExecutionError>
+    {
+        execution::execute_instruction(&mut self.state, instruction)
+    }
+
+    // Hosts should play a tone while this is true, see chip8::ToneGenerator.
+    pub fn is_sound_playing(&self) -> bool
+    {
+        audio::is_sound_playing(&self.state)
+    }
+
+    // Keys past keyboard::KEY_ID_COUNT don't exist and are never pressed.
+    pub fn is_key_pressed(&self, key: KeyID) -> bool
+    {
+        key < keyboard::KEY_ID_COUNT && keyboard::is_key_pressed(&self.state, key)
+    }
+
+    // Keys past keyboard::KEY_ID_COUNT don't exist and are ignored.
+    pub fn set_key_pressed(&mut self, key: KeyID, pressed_state: bool)
+    {
+        if key < keyboard::KEY_ID_COUNT {
+            keyboard::set_key_pressed(&mut self.state, key, pressed_state);
+        }
+    }
+
+    // The resolution can change at runtime on SUPER-CHIP.
+    pub fn screen_width(&self) -> usize
+    {
+        display::screen_width(&self.state)
+    }
+
+    pub fn screen_height(&self) -> usize
+    {
+        display::screen_height(&self.state)
+    }
+
+    // True if the pixel is lit on any plane.
+    pub fn read_screen_pixel(&self, x: usize, y: usize) -> bool
+    {
+        display::read_screen_pixel(&self.state, x, y)
+    }
+
+    // Bit N is set if the pixel is lit on plane N, use it to index a Palette.
+    pub fn read_screen_pixel_color_index(&self, x: usize, y: usize) -> u8
+    {
+        display::read_screen_pixel_color_index(&self.state, x, y)
+    }
+
+    // Packed scanlines, 8 pixels per byte with the leftmost pixel in the lowest bit.
+    // Only XO-CHIP draws on the second plane.
+    pub fn screen_plane(&self, plane: usize) -> &[Vec<u8>]
+    {
+        &self.state.screen[plane]
+    }
+
+    // Versioned binary snapshot of the whole machine, tied to the loaded ROM and platform.
+    pub fn save_state(&self) -> Vec<u8>
+    {
+        savestate::save_state(&self.state)
+    }
+
+    // Snapshots made with another ROM or platform are rejected and the machine is left as is.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), SaveStateError>
+    {
+        savestate::load_state(&mut self.state, data)
+    }
+
+    pub fn state(&self) -> &CPUState
+    {
+        &self.state
+    }
+
+    pub fn state_mut(&mut self) -> &mut CPUState
+    {
+        &mut self.state
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn emulator() {
+        let mut emulator = Emulator::new();
+
+        // LD V0, 0x0A / LD F, V0 / DRW V1, V1, 5
+        emulator.load_program(vec![0x60, 0x0A, 0xF0, 0x29, 0xD1, 0x15]).unwrap();
+
+        for _ in 0..3 {
+            emulator.execute_step(cpu::INSTRUCTION_EXECUTION_PERIOD_MS).unwrap();
+        }
+
+        assert_eq!(emulator.state().pc, cpu::MIN_PROGRAM_ADDRESS as u16 + 6);
+
+        // Top row of the 'A' glyph is 0xF0.
+        assert!(emulator.read_screen_pixel(0, 0));
+        assert!(emulator.read_screen_pixel(3, 0));
+        assert!(!emulator.read_screen_pixel(4, 0));
+
+        emulator.set_key_pressed(0xB, true);
+
+        assert!(emulator.is_key_pressed(0xB));
+        assert!(!emulator.is_key_pressed(0xA));
+
+        // Invalid keys are ignored
+        emulator.set_key_pressed(keyboard::KEY_ID_COUNT, true);
+
+        assert!(!emulator.is_key_pressed(keyboard::KEY_ID_COUNT));
+        assert_eq!(emulator.state().key_state, 1 << 0xB);
+    }
+}
@@ -0,0 +1,324 @@
+use crate::{
+    chip8::{
+        cpu,
+        execution,
+        fault::ExecutionError,
+        hash,
+        keyboard::{KeyID, KEY_ID_COUNT},
+        movie::{MovieError, MoviePlayer},
+    },
+    Emulator,
+};
+
+use std::io::{self, Write};
+
+// How long a headless run lasts on the synthetic clock.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RunLimit
+{
+    Frames(u32),
+    // Counts the instructions executed, whatever the scheduler or timing runs per step.
+    // Nothing runs while waiting for the display, waiting for a key retries Fx0A every time.
+    Instructions(u32),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KeyEvent
+{
+    pub frame: u32,
+    pub key: KeyID,
+    pub pressed: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HeadlessConfig
+{
+    pub limit: RunLimit,
+    pub key_script: Vec<KeyEvent>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HeadlessReport
+{
+    pub frames: u32,
+    pub instructions: u32,
+    pub fault: Option<ExecutionError>,
+    pub desync_frame: Option<u32>,
+}
+
+// One event per line: '<frame> <key> <down|up>', with the key in hex.
+// Blank lines and lines starting with '#' are ignored.
+pub fn parse_key_script(script: &str) -> Result<Vec<KeyEvent>, String>
+{
+    let mut events = Vec::new();
+
+    for (line_index, line) in script.lines().enumerate() {
+        let line = line.trim();
+
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let error = |what: &str| format!("line {}: {} in '{}'", line_index + 1, what, line);
+        let fields: Vec<&str> = line.split_whitespace().collect();
+
+        if fields.len() != 3 {
+            return Err(error("expected '<frame> <key> <down|up>'"));
+        }
+
+        let frame = fields[0].parse::<u32>().map_err(|_| error("invalid frame"))?;
+        let key = u8::from_str_radix(fields[1], 16).ok()
+            .filter(|&key| key < KEY_ID_COUNT)
+            .ok_or_else(|| error("invalid key"))?;
+        let pressed = match fields[2] {
+            "down" => true,
+            "up" => false,
+            _ => return Err(error("invalid key state")),
+        };
+
+        events.push(KeyEvent { frame, key, pressed });
+    }
+
+    // Events of the same frame keep their order
+    events.sort_by_key(|event| event.frame);
+
+    Ok(events)
+}
+
+// Drive the emulator with a synthetic clock, one execution slot at a time.
+// Key events are applied at the start of their frame. The run stops early on fault or halt.
+pub fn run(emulator: &mut Emulator, config: &HeadlessConfig) -> HeadlessReport
+{
+    let first_frame = emulator.state().frame_count;
+    let first_instruction = emulator.state().instruction_count;
+    let mut next_event = 0;
+    let mut fault = None;
+
+    // A step can run any number of instructions, the one reaching the limit stops right there
+    let mut is_instruction_limit_reached = |state: &cpu::CPUState| match config.limit {
+        RunLimit::Frames(_) => false,
+        RunLimit::Instructions(instruction_count) => state.instruction_count.wrapping_sub(first_instruction) >= instruction_count,
+    };
+
+    loop {
+        // Counted by the machine, whose frames don't last a whole number of slots with the frame scheduler
+        let frame = emulator.state().frame_count.wrapping_sub(first_frame);
+
+        let is_done = match config.limit {
+            RunLimit::Frames(frame_count) => frame >= frame_count,
+            RunLimit::Instructions(_) => is_instruction_limit_reached(emulator.state()),
+        };
+
+        if is_done || emulator.state().is_halted {
+            break;
+        }
+
+        while next_event < config.key_script.len() && config.key_script[next_event].frame <= frame {
+            let event = config.key_script[next_event];
+
+            emulator.set_key_pressed(event.key, event.pressed);
+            next_event += 1;
+        }
+
+        if let Err(error) = execution::execute_step_until(emulator.state_mut(), cpu::INSTRUCTION_EXECUTION_PERIOD_MS, &mut is_instruction_limit_reached) {
+            fault = Some(error);
+            break;
+        }
+    }
+
+    HeadlessReport {
+        frames: emulator.state().frame_count.wrapping_sub(first_frame),
+        instructions: emulator.state().instruction_count.wrapping_sub(first_instruction),
+        fault,
+        desync_frame: None,
+    }
+}
+
+// Replay a movie with its recorded clock, stopping on the first fault or desync.
+pub fn play_movie(emulator: &mut Emulator, player: &mut MoviePlayer) -> HeadlessReport
+{
+    let first_instruction = emulator.state().instruction_count;
+    let mut fault = None;
+    let mut desync_frame = None;
+
+    loop {
+        match player.play_frame(emulator.state_mut()) {
+            Ok(true) => {},
+            Ok(false) => break,
+            Err(MovieError::Fault(error)) => {
+                fault = Some(error);
+                break;
+            },
+            Err(MovieError::Desync { frame }) => {
+                desync_frame = Some(frame);
+                break;
+            },
+            Err(error) => unreachable!("{}", error),
+        }
+    }
+
+    HeadlessReport {
+        frames: player.frame_count(),
+        instructions: emulator.state().instruction_count.wrapping_sub(first_instruction),
+        fault,
+        desync_frame,
+    }
+}
+
+// Plain text so that CI can diff it against a known good run.
+// Pixels are '.' when unlit, '#' on the first plane only, and the color index otherwise.
+pub fn write_report(emulator: &Emulator, report: &HeadlessReport, out: &mut dyn Write) -> io::Result<()>
+{
+    let state = emulator.state();
+
+    writeln!(out, "frames: {}", report.frames)?;
+    writeln!(out, "instructions: {}", report.instructions)?;
+
+    match (&report.fault, report.desync_frame) {
+        (Some(error), _) => writeln!(out, "status: {}", error)?,
+        (None, Some(frame)) => writeln!(out, "status: desync at frame {}", frame)?,
+        (None, None) if state.is_halted => writeln!(out, "status: halted")?,
+        (None, None) => writeln!(out, "status: ok")?,
+    }
+
+    writeln!(out, "pc: {:#06x}", state.pc)?;
+    writeln!(out, "i: {:#06x}", state.i)?;
+    writeln!(out, "sp: {}", state.sp)?;
+
+    // Slot 0 is never used, the innermost return address is at sp
+    let stack: Vec<String> = state.stack[1..=state.sp as usize].iter().map(|addr| format!("{:04x}", addr)).collect();
+    writeln!(out, "stack: [{}]", stack.join(" "))?;
+
+    let registers: Vec<String> = state.v_registers.iter().map(|value| format!("{:02x}", value)).collect();
+    writeln!(out, "v: [{}]", registers.join(" "))?;
+
+    writeln!(out, "delay_timer: {}", state.delay_timer)?;
+    writeln!(out, "sound_timer: {}", state.sound_timer)?;
+    writeln!(out, "memory_hash: {:016x}", hash::fnv1a_64(&state.memory))?;
+    writeln!(out, "screen: {}x{}", emulator.screen_width(), emulator.screen_height())?;
+
+    write_screen(emulator, out)
+}
+
+// One line per scanline, '.' for unlit pixels, '#' for the first plane and the color index otherwise.
+pub fn write_screen(emulator: &Emulator, out: &mut dyn Write) -> io::Result<()>
+{
+    for y in 0..emulator.screen_height() {
+        let line: String = (0..emulator.screen_width())
+            .map(|x| match emulator.read_screen_pixel_color_index(x, y) {
+                0 => '.',
+                1 => '#',
+                color_index => (b'0' + color_index) as char,
+            })
+            .collect();
+
+        writeln!(out, "{}", line)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::config::TimingMode;
+
+    #[test]
+    fn headless() {
+        let mut emulator = Emulator::new();
+
+        // LD V0, K / LD F, V0 / DRW V1, V1, 5 / LD V2, 0 / JP 0x206
+        emulator.load_program(vec![0xF0, 0x0A, 0xF0, 0x29, 0xD1, 0x15, 0x62, 0x00, 0x12, 0x06]).unwrap();
+
+        let config = HeadlessConfig {
+            limit: RunLimit::Frames(10),
+            key_script: parse_key_script("# Press 'A' for one frame\n4 a down\n5 a up\n").unwrap(),
+        };
+
+        let report = run(&mut emulator, &config);
+
+        assert_eq!(report.frames, 10);
+        assert_eq!(report.instructions, 10 * cpu::DELAY_TIMER_PERIOD_MS / cpu::INSTRUCTION_EXECUTION_PERIOD_MS);
+        assert_eq!(report.fault, None);
+        assert_eq!(emulator.state().v_registers[0], 0xA);
+        assert!(emulator.state().pc >= cpu::MIN_PROGRAM_ADDRESS as u16 + 6);
+
+        let mut output = Vec::new();
+
+        write_report(&emulator, &report, &mut output).unwrap();
+
+        let output = String::from_utf8(output).unwrap();
+
+        assert!(output.contains("status: ok\n"));
+        assert!(output.contains("v: [0a 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00]\n"));
+        assert!(output.contains(&format!("memory_hash: {:016x}\n", hash::fnv1a_64(&emulator.state().memory))));
+
+        // Top row of the 'A' glyph is 0xF0.
+        assert!(output.contains(&format!("\n####{}\n", ".".repeat(60))));
+
+        // SUBCASE: call stack
+        {
+            let mut emulator = Emulator::new();
+
+            // CALL 0x204 / (unused) / JP 0x206 / JP 0x204
+            emulator.load_program(vec![0x22, 0x04, 0x00, 0x00, 0x12, 0x06, 0x12, 0x04]).unwrap();
+
+            let report = run(&mut emulator, &HeadlessConfig { limit: RunLimit::Frames(1), key_script: Vec::new() });
+            let mut output = Vec::new();
+
+            write_report(&emulator, &report, &mut output).unwrap();
+
+            let output = String::from_utf8(output).unwrap();
+
+            assert!(output.contains("sp: 1\nstack: [0200]\n"));
+        }
+
+        // SUBCASE: instruction limit with the frame scheduler and cycle timing
+        {
+            let mut emulator = Emulator::new();
+
+            // ADD V0, 1 / JP 0x204 / JP 0x200
+            emulator.load_program(vec![0x70, 0x01, 0x12, 0x04, 0x12, 0x00]).unwrap();
+            emulator.state_mut().quirks.instructions_per_frame = Some(20);
+
+            let report = run(&mut emulator, &HeadlessConfig { limit: RunLimit::Instructions(50), key_script: Vec::new() });
+
+            // Two whole frames and half of the third
+            assert_eq!(report.instructions, 50);
+            assert_eq!(report.frames, 3);
+            assert_eq!(emulator.state().v_registers[0], 17);
+
+            // COSMAC VIP timing runs as many as each frame's cycles pay for
+            let mut emulator = Emulator::new();
+
+            emulator.load_program(vec![0x70, 0x01, 0x12, 0x04, 0x12, 0x00]).unwrap();
+            emulator.state_mut().quirks.timing = TimingMode::CosmacVip;
+
+            let report = run(&mut emulator, &HeadlessConfig { limit: RunLimit::Instructions(50), key_script: Vec::new() });
+
+            assert_eq!(report.instructions, 50);
+            assert_eq!(report.frames, 3);
+            assert_eq!(emulator.state().v_registers[0], 17);
+        }
+
+        // SUBCASE: stop on the first fault
+        {
+            let mut emulator = Emulator::new();
+
+            emulator.load_program(vec![0xFF, 0xFF]).unwrap();
+
+            let report = run(&mut emulator, &HeadlessConfig { limit: RunLimit::Instructions(100), key_script: Vec::new() });
+
+            assert_eq!(report.instructions, 1);
+            assert!(report.fault.is_some());
+        }
+
+        // SUBCASE: malformed scripts
+        {
+            assert!(parse_key_script("1 g down").is_err());
+            assert!(parse_key_script("1 10 down").is_err());
+            assert!(parse_key_script("1 a pressed").is_err());
+            assert!(parse_key_script("a down").is_err());
+        }
+    }
+}
@@ -0,0 +1,410 @@
+use crate::{
+    chip8::{
+        config::EmuConfig,
+        debugger::{Debugger, DebuggerAction},
+        gdbstub::{GdbSession, GdbStub},
+        keyboard::KEY_ID_COUNT,
+        movie::{MoviePlayer, MovieRecorder},
+        rewind::RewindBuffer,
+    },
+    screenshot,
+    Emulator,
+};
+
+// Requests a host can make on top of the CHIP-8 keys.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HostAction
+{
+    SaveState { slot: usize },
+    LoadState { slot: usize },
+    BreakIntoDebugger,
+    Screenshot,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct HostInput
+{
+    pub key_state: u16, // Bit N is set while key N is held
+    pub is_rewinding: bool, // Held down to play the history backwards
+    pub actions: Vec<HostAction>,
+}
+
+// What the main loop needs from a platform: input, a clock, a screen and a buzzer.
+pub trait Host
+{
+    // Called once at the start of every frame.
+    fn poll_input(&mut self) -> HostInput;
+
+    fn is_quit_requested(&self) -> bool;
+
+    // Milliseconds since an arbitrary origin.
+    fn ticks_ms(&mut self) -> u32;
+
+    // Expected to block until the frame is shown, that paces the loop.
+    fn present_screen(&mut self, emulator: &Emulator) -> Result<(), String>;
+
+    fn play_sound(&mut self, is_playing: bool);
+
+    // Shows a problem to the user, None once it is gone.
+    fn show_status(&mut self, status: Option<&str>) -> Result<(), String>;
+}
+
+// Movies need the run to stay untouched from the first frame.
+pub enum MovieMode
+{
+    Off,
+    Record { recorder: MovieRecorder, path: String },
+    Play(MoviePlayer),
+}
+
+// Who drives execution when debugging.
+pub enum DebugMode
+{
+    Off,
+    Repl(Debugger),
+    Gdb(GdbStub),
+}
+
+// Everything the main loop runs besides the emulator.
+pub struct Session
+{
+    pub rom_path: String,
+    pub movie_mode: MovieMode,
+    pub debug_mode: DebugMode,
+}
+
+fn write_movie(recorder: &MovieRecorder, path: &str)
+{
+    match std::fs::write(path, recorder.movie().to_text()) {
+        Ok(()) => println!("Recorded {} frames to '{}'", recorder.movie().frames.len(), path),
+        Err(error) => eprintln!("warning: unable to write '{}': {}", path, error),
+    }
+}
+
+// Slots are stored next to the ROM.
+fn save_state_path(rom_path: &str, slot: usize) -> String
+{
+    format!("{}.state{}", rom_path, slot)
+}
+
+fn save_state_to_slot(emulator: &Emulator, rom_path: &str, slot: usize)
+{
+    let path = save_state_path(rom_path, slot);
+
+    match std::fs::write(&path, emulator.save_state()) {
+        Ok(()) => println!("Saved state to slot {}", slot),
+        Err(error) => eprintln!("warning: unable to write '{}': {}", path, error),
+    }
+}
+
+// Numbered next to the ROM, the first free number is taken so earlier screenshots are kept.
+fn take_screenshot(emulator: &Emulator, config: &EmuConfig, rom_path: &str)
+{
+    let path = (1..)
+        .map(|number| format!("{}.screenshot{}.png", rom_path, number))
+        .find(|path| !std::path::Path::new(path).exists())
+        .unwrap();
+
+    match screenshot::save_screen_png(emulator, &config.palette, config.screenshot_scale, path.as_ref()) {
+        Ok(()) => println!("Saved screenshot to '{}'", path),
+        Err(error) => eprintln!("warning: unable to write '{}': {}", path, error),
+    }
+}
+
+fn load_state_from_slot(emulator: &mut Emulator, rom_path: &str, slot: usize) -> bool
+{
+    let path = save_state_path(rom_path, slot);
+
+    let result = std::fs::read(&path)
+        .map_err(|error| error.to_string())
+        .and_then(|data| emulator.load_state(&data).map_err(|error| error.to_string()));
+
+    match result {
+        Ok(()) => {
+            println!("Loaded state from slot {}", slot);
+            true
+        },
+        Err(error) => {
+            eprintln!("warning: unable to load '{}': {}", path, error);
+            false
+        },
+    }
+}
+
+// Runs the emulator on the host until it asks to quit, one iteration per presented frame.
+pub fn run_main_loop(emulator: &mut Emulator, host: &mut dyn Host, config: &EmuConfig, session: &mut Session) -> Result<(), String>
+{
+    let mut previous_time_ms: u32 = host.ticks_ms();
+
+    // Execution stays paused once the ROM faults, the last frame is kept on screen.
+    let mut is_faulted = false;
+
+    let mut rewind_buffer = RewindBuffer::new(config.rewind);
+    let is_rewind_enabled = config.rewind.buffer_size_in_bytes > 0;
+
+    loop {
+        let input = host.poll_input();
+
+        if host.is_quit_requested() {
+            break;
+        }
+
+        for action in input.actions.iter() {
+            match *action {
+                HostAction::SaveState{slot} => save_state_to_slot(emulator, &session.rom_path, slot),
+                HostAction::LoadState{slot} => {
+                    if !matches!(session.movie_mode, MovieMode::Off) {
+                        eprintln!("warning: save states can't be loaded during a movie");
+                    } else if load_state_from_slot(emulator, &session.rom_path, slot) && is_faulted {
+                        // A good state gets a faulted ROM going again
+                        host.show_status(None)?;
+                        is_faulted = false;
+                    }
+                },
+                HostAction::Screenshot => take_screenshot(emulator, config, &session.rom_path),
+                HostAction::BreakIntoDebugger => {
+                    // Paused frames would be missing from a movie
+                    if !matches!(session.movie_mode, MovieMode::Off) {
+                        eprintln!("warning: the debugger can't be used during a movie");
+                    } else if let DebugMode::Gdb(_) = session.debug_mode {
+                        eprintln!("warning: the debugger can't be used along with GDB");
+                    } else {
+                        if let DebugMode::Off = session.debug_mode {
+                            session.debug_mode = DebugMode::Repl(Debugger::new());
+                        }
+
+                        if let DebugMode::Repl(debugger) = &mut session.debug_mode {
+                            debugger.pause();
+                        }
+                    }
+                },
+            }
+        }
+
+        for key in 0..KEY_ID_COUNT {
+            emulator.set_key_pressed(key, input.key_state & (1 << key) != 0);
+        }
+
+        let is_movie_active = !matches!(session.movie_mode, MovieMode::Off);
+        let is_rewinding = is_rewind_enabled && !is_movie_active && input.is_rewinding;
+
+        let current_time_ms: u32 = host.ticks_ms();
+        let delta_time_ms: u32 = current_time_ms.wrapping_sub(previous_time_ms);
+
+        // Time spent stopped in GDB is not emulated
+        let mut blocked_time_ms = 0;
+
+        if is_rewinding {
+            // Going back in time also gets a faulted ROM going again
+            if rewind_buffer.rewind_frame(emulator.state_mut()) && is_faulted {
+                host.show_status(None)?;
+                is_faulted = false;
+            }
+        } else if let MovieMode::Play(player) = &mut session.movie_mode {
+            // The movie provides both the keys and the frame time
+            match player.play_frame(emulator.state_mut()) {
+                Ok(true) => {},
+                Ok(false) => {
+                    println!("Movie finished after {} frames", player.frame_count());
+                    session.movie_mode = MovieMode::Off;
+                },
+                Err(error) => {
+                    eprintln!("error: {}", error);
+                    host.show_status(Some(&error.to_string()))?;
+                    is_faulted = true;
+                    session.movie_mode = MovieMode::Off;
+                },
+            }
+        } else if !is_faulted {
+            let result = match &mut session.debug_mode {
+                DebugMode::Off => emulator.execute_step(delta_time_ms),
+                DebugMode::Repl(debugger) => debugger.execute_step(emulator.state_mut(), delta_time_ms),
+                // GDB gets the faults, the stub blocks while the client has the machine stopped
+                DebugMode::Gdb(stub) => {
+                    let gdb_session = stub.execute_step(emulator.state_mut(), delta_time_ms).map_err(|error| error.to_string())?;
+
+                    blocked_time_ms = host.ticks_ms().wrapping_sub(current_time_ms);
+
+                    match gdb_session {
+                        GdbSession::Attached => {},
+                        GdbSession::Detached => {
+                            println!("GDB detached");
+                            session.debug_mode = DebugMode::Off;
+                        },
+                        GdbSession::Killed => break,
+                    }
+
+                    Ok(())
+                },
+            };
+
+            if let Err(error) = result {
+                eprintln!("error: {}", error);
+                host.show_status(Some(&error.to_string()))?;
+                is_faulted = true;
+            }
+
+            // Faulting frames are kept so that playback reproduces them
+            if let MovieMode::Record { recorder, .. } = &mut session.movie_mode {
+                recorder.record_frame(emulator.state(), delta_time_ms);
+            }
+
+            if is_rewind_enabled {
+                rewind_buffer.record_frame(emulator.state());
+            }
+        }
+
+        host.play_sound(emulator.is_sound_playing());
+        host.present_screen(emulator)?;
+
+        previous_time_ms = current_time_ms.wrapping_add(blocked_time_ms);
+
+        if config.debug_mode {
+            println!("Frame time = {} ms", delta_time_ms);
+        }
+
+        // The REPL blocks the loop, the time spent in it is not emulated
+        if let DebugMode::Repl(debugger) = &mut session.debug_mode {
+            if debugger.is_paused() {
+                let stdin = std::io::stdin();
+
+                match debugger.run_repl(emulator.state_mut(), &mut stdin.lock(), &mut std::io::stdout()) {
+                    Ok(DebuggerAction::Resume) => previous_time_ms = host.ticks_ms(),
+                    Ok(DebuggerAction::Quit) => break,
+                    Err(error) => return Err(error.to_string()),
+                }
+            }
+        }
+    }
+
+    if let MovieMode::Record { recorder, path } = &session.movie_mode {
+        write_movie(recorder, path);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::{
+        cpu,
+        rewind::RewindConfig,
+    };
+
+    // Quits after a fixed number of frames, each one exactly a 60 Hz period long
+    struct MockHost
+    {
+        inputs: Vec<HostInput>, // One per frame, the last one is repeated
+        frame_count: usize,
+        frame_limit: usize,
+        time_ms: u32,
+        presented_v0: Vec<u8>,
+        sound_frames: usize,
+        statuses: Vec<Option<String>>,
+    }
+
+    impl MockHost
+    {
+        fn new(inputs: Vec<HostInput>, frame_limit: usize) -> MockHost
+        {
+            MockHost { inputs, frame_count: 0, frame_limit, time_ms: 0, presented_v0: Vec::new(), sound_frames: 0, statuses: Vec::new() }
+        }
+    }
+
+    impl Host for MockHost
+    {
+        fn poll_input(&mut self) -> HostInput
+        {
+            let input = self.inputs[self.frame_count.min(self.inputs.len() - 1)].clone();
+
+            self.frame_count += 1;
+
+            input
+        }
+
+        fn is_quit_requested(&self) -> bool
+        {
+            self.frame_count > self.frame_limit
+        }
+
+        fn ticks_ms(&mut self) -> u32
+        {
+            self.time_ms = self.frame_count as u32 * cpu::DELAY_TIMER_PERIOD_MS;
+            self.time_ms
+        }
+
+        fn present_screen(&mut self, emulator: &Emulator) -> Result<(), String>
+        {
+            self.presented_v0.push(emulator.state().v_registers[0]);
+            Ok(())
+        }
+
+        fn play_sound(&mut self, is_playing: bool)
+        {
+            self.sound_frames += is_playing as usize;
+        }
+
+        fn show_status(&mut self, status: Option<&str>) -> Result<(), String>
+        {
+            self.statuses.push(status.map(str::to_string));
+            Ok(())
+        }
+    }
+
+    fn new_session() -> Session
+    {
+        Session { rom_path: String::new(), movie_mode: MovieMode::Off, debug_mode: DebugMode::Off }
+    }
+
+    #[test]
+    fn main_loop() {
+        let mut config = EmuConfig::default();
+        let mut emulator = Emulator::with_config(&config);
+
+        // Waits for key 5, then counts frames in V0 and beeps: LD V1, K / loop: ADD V0, 1 / LD ST, V2 / LD V2, 1 / wait: LD V3, DT / SE V3, 0 / JP wait / LD DT, V2 / JP loop
+        emulator.load_program(vec![0xF1, 0x0A, 0x70, 0x01, 0xF2, 0x18, 0x62, 0x01, 0xF3, 0x07, 0x33, 0x00, 0x12, 0x08, 0xF2, 0x15, 0x12, 0x02]).unwrap();
+
+        let released = HostInput::default();
+        let pressed = HostInput { key_state: 1 << 5, ..HostInput::default() };
+        let mut host = MockHost::new(vec![released.clone(), released, pressed.clone(), pressed, HostInput::default()], 10);
+
+        run_main_loop(&mut emulator, &mut host, &config, &mut new_session()).unwrap();
+
+        // One presentation per frame, nothing runs before the key is pressed
+        assert_eq!(host.presented_v0.len(), 10);
+        assert_eq!(host.presented_v0[..3], [0, 0, 1]);
+        assert!(host.presented_v0[9] > 1);
+        assert!(host.sound_frames > 0);
+        assert!(host.statuses.is_empty());
+
+        // SUBCASE: faults pause execution, rewinding resumes it
+        {
+            config.rewind = RewindConfig { buffer_size_in_bytes: 1024 * 1024, snapshot_interval: 1 };
+
+            let mut emulator = Emulator::with_config(&config);
+
+            // ADD V0, 1 / SE V0, 20 / JP 0x200 / RET
+            emulator.load_program(vec![0x70, 0x01, 0x30, 0x14, 0x12, 0x00, 0x00, 0xEE]).unwrap();
+
+            let rewinding = HostInput { is_rewinding: true, ..HostInput::default() };
+            let mut inputs = vec![HostInput::default(); 8];
+            inputs.push(rewinding);
+            inputs.push(HostInput::default());
+
+            let mut host = MockHost::new(inputs, 10);
+
+            run_main_loop(&mut emulator, &mut host, &config, &mut new_session()).unwrap();
+
+            assert_eq!(host.statuses.len(), 3);
+            assert!(host.statuses[0].as_ref().unwrap().starts_with("stack underflow"));
+            assert_eq!(host.statuses[1], None);
+            assert_eq!(host.statuses[2], host.statuses[0]);
+
+            // Stuck on the fault, then back one frame and running into it again
+            assert_eq!(host.presented_v0[7], 20);
+            assert!(host.presented_v0[8] < 20);
+            assert_eq!(host.presented_v0[9], 20);
+        }
+    }
+}
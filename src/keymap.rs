@@ -0,0 +1,549 @@
+use crate::{
+    chip8::keyboard::{KeyID, KEY_ID_COUNT},
+    host::{HostAction, HostInput},
+};
+
+use std::{
+    error,
+    fmt,
+};
+
+pub const STATE_SLOT_COUNT: usize = 4;
+
+// Analog sticks and triggers range over -32768..=32767, halfway counts as pressed by default
+pub const DEFAULT_AXIS_THRESHOLD: i16 = 16384;
+
+// What a physical key does.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Binding
+{
+    Key(KeyID),
+    Quit,
+    Rewind,
+    Debugger,
+    Screenshot,
+    SaveState { slot: usize }, // From 1
+    LoadState { slot: usize },
+}
+
+impl Binding
+{
+    pub fn from_name(name: &str) -> Option<Binding>
+    {
+        let slot = |prefix: &str| name.strip_prefix(prefix)
+            .and_then(|slot| slot.parse::<usize>().ok())
+            .filter(|slot| (1..=STATE_SLOT_COUNT).contains(slot));
+
+        match name {
+            "quit" => Some(Binding::Quit),
+            "rewind" => Some(Binding::Rewind),
+            "debugger" => Some(Binding::Debugger),
+            "screenshot" => Some(Binding::Screenshot),
+            _ if name.len() == 1 => u8::from_str_radix(name, 16).ok().map(Binding::Key),
+            _ => slot("save").map(|slot| Binding::SaveState { slot })
+                .or_else(|| slot("load").map(|slot| Binding::LoadState { slot })),
+        }
+    }
+
+    // Actions happen once per press, keys and rewind are held instead and quitting is up to the host.
+    pub fn action(self) -> Option<HostAction>
+    {
+        match self {
+            Binding::SaveState { slot } => Some(HostAction::SaveState { slot }),
+            Binding::LoadState { slot } => Some(HostAction::LoadState { slot }),
+            Binding::Debugger => Some(HostAction::BreakIntoDebugger),
+            Binding::Screenshot => Some(HostAction::Screenshot),
+            Binding::Key(_) | Binding::Quit | Binding::Rewind => None,
+        }
+    }
+}
+
+// Physical keys and controller inputs by their SDL name, the host resolves them.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum InputName
+{
+    Scancode(String), // Position on the keyboard, whatever the layout
+    Keycode(String), // Symbol printed on the key, follows the layout
+    Button(String), // Game controller button, as in 'a' or 'dpup'
+    Axis { name: String, is_positive: bool }, // Game controller stick or trigger pushed past the threshold
+}
+
+impl InputName
+{
+    // 'key:<name>' for keycodes, '<name>' or 'scancode:<name>' for scancodes,
+    // 'pad:<button>' for controller buttons and 'pad:<axis>+' or 'pad:<axis>-' for controller axes.
+    pub fn parse(text: &str) -> Option<InputName>
+    {
+        let text = text.trim();
+
+        let input = if let Some(name) = text.strip_prefix("key:") {
+            InputName::Keycode(name.trim().to_string())
+        } else if let Some(name) = text.strip_prefix("pad:") {
+            let name = name.trim();
+
+            if let Some(axis) = name.strip_suffix('+') {
+                InputName::Axis { name: axis.to_string(), is_positive: true }
+            } else if let Some(axis) = name.strip_suffix('-') {
+                InputName::Axis { name: axis.to_string(), is_positive: false }
+            } else {
+                InputName::Button(name.to_string())
+            }
+        } else {
+            InputName::Scancode(text.strip_prefix("scancode:").unwrap_or(text).trim().to_string())
+        };
+
+        match &input {
+            InputName::Scancode(name) | InputName::Keycode(name) | InputName::Button(name)
+            | InputName::Axis { name, .. } if name.is_empty() => None,
+            _ => Some(input),
+        }
+    }
+}
+
+// Whether an axis position counts as pressed in the direction of the binding.
+pub fn is_axis_pressed(value: i16, is_positive: bool, threshold: i16) -> bool
+{
+    if is_positive {
+        value >= threshold
+    } else {
+        i32::from(value) <= -i32::from(threshold)
+    }
+}
+
+// What a bound input reads on the current frame.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InputReading
+{
+    Keyboard(bool), // Its actions come from key down events, see BindingState::press
+    Controller(bool), // Its actions happen on the frame it gets pressed, sticks have no press events
+}
+
+// Merges the keyboard and the controllers into the CHIP-8 keys and the host actions, frame after frame.
+#[derive(Clone, Debug, Default)]
+pub struct BindingState
+{
+    controller_pressed: Vec<bool>, // Per bound input, on the previous frame
+    pub is_quit_requested: bool,
+}
+
+impl BindingState
+{
+    // For key down events, repeats excluded.
+    pub fn press(&mut self, binding: Binding, input: &mut HostInput)
+    {
+        if binding == Binding::Quit {
+            self.is_quit_requested = true;
+        }
+
+        input.actions.extend(binding.action());
+    }
+
+    // 'readings' lists every bound input, in the same order on every frame.
+    pub fn update(&mut self, readings: &[(Binding, InputReading)], input: &mut HostInput)
+    {
+        self.controller_pressed.resize(readings.len(), false);
+
+        for (index, &(binding, reading)) in readings.iter().enumerate() {
+            let is_pressed = match reading {
+                InputReading::Keyboard(is_pressed) => is_pressed,
+                InputReading::Controller(is_pressed) => {
+                    if is_pressed && !self.controller_pressed[index] {
+                        self.press(binding, input);
+                    }
+
+                    is_pressed
+                },
+            };
+
+            self.controller_pressed[index] = is_pressed && matches!(reading, InputReading::Controller(_));
+
+            if is_pressed {
+                match binding {
+                    Binding::Key(key) => input.key_state |= 1 << key,
+                    Binding::Rewind => input.is_rewinding = true,
+                    _ => {},
+                }
+            }
+        }
+    }
+}
+
+// Game controllers plugged in, by SDL instance id.
+#[derive(Debug)]
+pub struct ControllerSet<C>
+{
+    controllers: Vec<(i32, C)>,
+}
+
+impl<C> Default for ControllerSet<C>
+{
+    fn default() -> Self
+    {
+        ControllerSet { controllers: Vec::new() }
+    }
+}
+
+impl<C> ControllerSet<C>
+{
+    // Controllers plugged in at startup can be reported twice, the first one is kept.
+    pub fn add(&mut self, id: i32, controller: C)
+    {
+        if self.controllers.iter().all(|(other_id, _)| *other_id != id) {
+            self.controllers.push((id, controller));
+        }
+    }
+
+    pub fn remove(&mut self, id: i32)
+    {
+        self.controllers.retain(|(other_id, _)| *other_id != id);
+    }
+
+    pub fn len(&self) -> usize
+    {
+        self.controllers.len()
+    }
+
+    pub fn is_empty(&self) -> bool
+    {
+        self.controllers.is_empty()
+    }
+
+    // All controllers drive the same keys.
+    pub fn is_any_pressed(&self, is_pressed: impl Fn(&C) -> bool) -> bool
+    {
+        self.controllers.iter().any(|(_, controller)| is_pressed(controller))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KeymapError
+{
+    pub line: usize, // 0 outside of a file
+    pub message: String,
+}
+
+impl fmt::Display for KeymapError
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        if self.line == 0 {
+            write!(f, "{}", self.message)
+        } else {
+            write!(f, "line {}: {}", self.line, self.message)
+        }
+    }
+}
+
+impl error::Error for KeymapError {}
+
+// Physical keys of every binding, several keys can share a binding.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Keymap
+{
+    pub bindings: Vec<(Binding, Vec<InputName>)>,
+    pub axis_threshold: i16,
+}
+
+fn scancodes(names: &[&str]) -> Vec<InputName>
+{
+    names.iter().map(|name| InputName::Scancode(name.to_string())).collect()
+}
+
+fn controller_inputs(key: KeyID) -> Vec<InputName>
+{
+    let names: &[&str] = match key {
+        0x2 => &["dpup", "lefty-"],
+        0x4 => &["dpleft", "leftx-"],
+        0x6 => &["dpright", "leftx+"],
+        0x8 => &["dpdown", "lefty+"],
+        0x5 => &["a"],
+        0x0 => &["b"],
+        0x1 => &["leftshoulder"],
+        0x3 => &["rightshoulder"],
+        _ => &[],
+    };
+
+    names.iter()
+        .map(|name| InputName::parse(&format!("pad:{}", name)).unwrap())
+        .collect()
+}
+
+impl Default for Keymap
+{
+    // The keypad on the left of a QWERTY keyboard, host hotkeys on Escape, Backspace and the function keys.
+    // Controllers move with 2/4/6/8, which most games use as directions, and act with 5.
+    fn default() -> Self
+    {
+        const KEY_SCANCODES: [&str; KEY_ID_COUNT as usize] = [
+            "X", "1", "2", "3", "Q", "W", "E", "A", "S", "D", "Z", "C", "4", "R", "F", "V",
+        ];
+
+        let mut bindings: Vec<(Binding, Vec<InputName>)> = KEY_SCANCODES.iter().enumerate()
+            .map(|(key, name)| {
+                let mut inputs = scancodes(&[name]);
+                inputs.extend(controller_inputs(key as KeyID));
+                (Binding::Key(key as KeyID), inputs)
+            })
+            .collect();
+
+        bindings.push((Binding::Quit, vec![InputName::Keycode("Escape".to_string())]));
+        bindings.push((Binding::Rewind, scancodes(&["Backspace"])));
+        bindings.push((Binding::Debugger, scancodes(&["F10"])));
+        bindings.push((Binding::Screenshot, scancodes(&["F12"])));
+
+        for slot in 1..=STATE_SLOT_COUNT {
+            bindings.push((Binding::SaveState { slot }, scancodes(&[&format!("F{}", slot)])));
+            bindings.push((Binding::LoadState { slot }, scancodes(&[&format!("F{}", slot + STATE_SLOT_COUNT)])));
+        }
+
+        Keymap { bindings, axis_threshold: DEFAULT_AXIS_THRESHOLD }
+    }
+}
+
+impl Keymap
+{
+    pub fn inputs(&self, binding: Binding) -> &[InputName]
+    {
+        self.bindings.iter()
+            .find(|(other, _)| *other == binding)
+            .map_or(&[], |(_, inputs)| &inputs[..])
+    }
+
+    // Parses '<binding> = <input>, <input>...', replacing the keys of the binding.
+    // An empty list of inputs unbinds it. 'axis_threshold = <value>' sets the threshold instead.
+    pub fn apply(&mut self, line: &str) -> Result<(), String>
+    {
+        let mut sides = line.splitn(2, '=');
+        let name = sides.next().unwrap_or("").trim();
+        let inputs = sides.next().ok_or_else(|| "expected '<binding> = <keys>'".to_string())?;
+
+        if name == "axis_threshold" {
+            self.axis_threshold = inputs.trim().parse::<i16>().ok()
+                .filter(|threshold| *threshold > 0)
+                .ok_or_else(|| format!("invalid axis threshold '{}'", inputs.trim()))?;
+
+            return Ok(());
+        }
+
+        let binding = Binding::from_name(&name.to_ascii_lowercase()).ok_or_else(|| format!("unknown binding '{}'", name))?;
+        let inputs = inputs.split(',')
+            .filter(|input| !input.trim().is_empty())
+            .map(|input| InputName::parse(input).ok_or_else(|| format!("invalid key '{}'", input.trim())))
+            .collect::<Result<Vec<InputName>, String>>()?;
+
+        match self.bindings.iter_mut().find(|(other, _)| *other == binding) {
+            Some((_, current_inputs)) => *current_inputs = inputs,
+            None => self.bindings.push((binding, inputs)),
+        }
+
+        Ok(())
+    }
+
+    // Lines before any section apply to every ROM, '[rom <file name>]' sections only to that ROM, after the others.
+    // '#' starts a comment.
+    pub fn parse(text: &str, rom_name: Option<&str>) -> Result<Keymap, KeymapError>
+    {
+        let mut keymap = Keymap::default();
+        let mut rom_lines = Vec::new();
+        let mut section: Option<String> = None;
+
+        for (line_index, line) in text.lines().enumerate() {
+            let line_number = line_index + 1;
+            let line = line.split('#').next().unwrap_or("").trim();
+
+            if line.is_empty() {
+                continue;
+            }
+
+            if let Some(header) = line.strip_prefix('[').and_then(|header| header.strip_suffix(']')) {
+                let name = header.trim().strip_prefix("rom ")
+                    .ok_or_else(|| KeymapError { line: line_number, message: format!("unknown section '{}'", header) })?;
+
+                section = Some(name.trim().to_string());
+                continue;
+            }
+
+            match &section {
+                None => keymap.apply(line).map_err(|message| KeymapError { line: line_number, message })?,
+                Some(name) => {
+                    // Errors are reported even in the sections of other ROMs
+                    keymap.clone().apply(line).map_err(|message| KeymapError { line: line_number, message })?;
+
+                    if Some(name.as_str()) == rom_name {
+                        rom_lines.push(line);
+                    }
+                },
+            }
+        }
+
+        for line in rom_lines {
+            keymap.apply(line).expect("keymap line checked while parsing");
+        }
+
+        Ok(keymap)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scancode(name: &str) -> InputName
+    {
+        InputName::Scancode(name.to_string())
+    }
+
+    #[test]
+    fn keymap() {
+        let keymap = Keymap::default();
+
+        assert_eq!(keymap.inputs(Binding::Key(0x0)), [scancode("X"), InputName::Button("b".to_string())]);
+        assert_eq!(keymap.inputs(Binding::Key(0xF)), [scancode("V")]);
+        assert_eq!(keymap.inputs(Binding::Key(0x4))[2], InputName::Axis { name: "leftx".to_string(), is_positive: false });
+        assert_eq!(keymap.inputs(Binding::Quit), [InputName::Keycode("Escape".to_string())]);
+        assert_eq!(keymap.inputs(Binding::LoadState { slot: 4 }), [scancode("F8")]);
+        assert_eq!(keymap.inputs(Binding::Screenshot), [scancode("F12")]);
+
+        let text = "
+            # AZERTY
+            4 = A
+            5 = Z, key:Up
+            7 = Q # Comment
+            a = scancode:W, Left Shift, pad:x, pad:righttrigger+
+            quit =
+            axis_threshold = 8000
+
+            [rom pong.ch8]
+            1 = Keypad 7
+            save1 = F12
+
+            [rom other.ch8]
+            1 = F11
+        ";
+
+        let keymap = Keymap::parse(text, Some("pong.ch8")).unwrap();
+
+        assert_eq!(keymap.inputs(Binding::Key(0x4)), [scancode("A")]);
+        assert_eq!(keymap.inputs(Binding::Key(0x5)), [scancode("Z"), InputName::Keycode("Up".to_string())]);
+        assert_eq!(keymap.inputs(Binding::Key(0x7)), [scancode("Q")]);
+        assert_eq!(keymap.inputs(Binding::Key(0xA)), [
+            scancode("W"),
+            scancode("Left Shift"),
+            InputName::Button("x".to_string()),
+            InputName::Axis { name: "righttrigger".to_string(), is_positive: true },
+        ]);
+        assert_eq!(keymap.axis_threshold, 8000);
+        assert!(keymap.inputs(Binding::Quit).is_empty());
+        assert_eq!(keymap.inputs(Binding::Key(0x1)), [scancode("Keypad 7")]);
+        assert_eq!(keymap.inputs(Binding::SaveState { slot: 1 }), [scancode("F12")]);
+        assert_eq!(keymap.inputs(Binding::Key(0x2)).len(), 3);
+
+        // Other ROMs only get the shared lines
+        let keymap = Keymap::parse(text, Some("tetris.ch8")).unwrap();
+
+        assert_eq!(keymap.inputs(Binding::Key(0x1))[0], scancode("1"));
+        assert_eq!(keymap.inputs(Binding::Key(0x4)), [scancode("A")]);
+
+        // SUBCASE: errors
+        {
+            let error = |text: &str| Keymap::parse(text, None).unwrap_err().to_string();
+
+            assert_eq!(error("\n10 = A"), "line 2: unknown binding '10'");
+            assert_eq!(error("save5 = A"), "line 1: unknown binding 'save5'");
+            assert_eq!(error("5 A"), "line 1: expected '<binding> = <keys>'");
+            assert_eq!(error("5 = key:"), "line 1: invalid key 'key:'");
+            assert_eq!(error("[roms]"), "line 1: unknown section 'roms'");
+            assert_eq!(error("5 = pad:"), "line 1: invalid key 'pad:'");
+            assert_eq!(error("axis_threshold = -5"), "line 1: invalid axis threshold '-5'");
+            assert_eq!(error("[rom x]\n5 = key:"), "line 2: invalid key 'key:'");
+        }
+
+        // SUBCASE: axis threshold
+        {
+            assert!(is_axis_pressed(16384, true, DEFAULT_AXIS_THRESHOLD));
+            assert!(!is_axis_pressed(16383, true, DEFAULT_AXIS_THRESHOLD));
+            assert!(!is_axis_pressed(-32768, true, DEFAULT_AXIS_THRESHOLD));
+            assert!(is_axis_pressed(-32768, false, DEFAULT_AXIS_THRESHOLD));
+            assert!(is_axis_pressed(-16384, false, DEFAULT_AXIS_THRESHOLD));
+            assert!(!is_axis_pressed(-16383, false, DEFAULT_AXIS_THRESHOLD));
+            assert!(!is_axis_pressed(0, false, 1));
+        }
+
+        // SUBCASE: controllers
+        {
+            // Buttons held, bit N for button N
+            let mut controllers: ControllerSet<u32> = ControllerSet::default();
+            let mut state = BindingState::default();
+
+            let bindings = [
+                (Binding::Key(0x5), None),
+                (Binding::Key(0x5), Some(0)),
+                (Binding::SaveState { slot: 1 }, Some(1)),
+                (Binding::Rewind, Some(2)),
+                (Binding::Quit, Some(3)),
+            ];
+
+            let frame = |controllers: &ControllerSet<u32>, state: &mut BindingState, is_key_down: bool| {
+                let readings: Vec<(Binding, InputReading)> = bindings.iter()
+                    .map(|&(binding, button)| (binding, match button {
+                        None => InputReading::Keyboard(is_key_down),
+                        Some(button) => InputReading::Controller(controllers.is_any_pressed(|buttons| buttons & (1 << button) != 0)),
+                    }))
+                    .collect();
+                let mut input = HostInput::default();
+
+                state.update(&readings, &mut input);
+                input
+            };
+
+            assert_eq!(frame(&controllers, &mut state, false), HostInput::default());
+
+            // Plugged in with button 0 and 1 held, the same one reported twice
+            controllers.add(7, 0b0011);
+            controllers.add(7, 0b0000);
+
+            assert_eq!(controllers.len(), 1);
+
+            let input = frame(&controllers, &mut state, false);
+
+            assert_eq!(input.key_state, 1 << 5);
+            assert_eq!(input.actions, [HostAction::SaveState { slot: 1 }]);
+
+            // Held buttons don't repeat their action, the keyboard merges into the same keys
+            let input = frame(&controllers, &mut state, true);
+
+            assert_eq!(input.key_state, 1 << 5);
+            assert!(input.actions.is_empty());
+
+            // A second controller pressing the same button doesn't trigger it again
+            controllers.add(9, 0b0110);
+
+            let input = frame(&controllers, &mut state, false);
+
+            assert!(input.actions.is_empty());
+            assert!(input.is_rewinding);
+
+            // Unplugged, its buttons are released and can be pressed again
+            controllers.remove(7);
+            controllers.remove(9);
+
+            assert!(controllers.is_empty());
+            assert_eq!(frame(&controllers, &mut state, false), HostInput::default());
+
+            controllers.add(9, 0b1010);
+
+            let input = frame(&controllers, &mut state, false);
+
+            assert_eq!(input.actions, [HostAction::SaveState { slot: 1 }]);
+            assert!(state.is_quit_requested);
+
+            // Keyboard actions come from key down events
+            let mut input = HostInput::default();
+
+            state.press(Binding::Screenshot, &mut input);
+            state.press(Binding::Key(0x1), &mut input);
+
+            assert_eq!(input.actions, [HostAction::Screenshot]);
+        }
+    }
+}
@@ -0,0 +1,14 @@
+// Opcode mnemonics and register names intentionally mirror the CHIP-8 documentation.
+#![allow(clippy::upper_case_acronyms)]
+#![allow(clippy::field_reassign_with_default)]
+
+pub mod chip8;
+pub mod headless;
+pub mod host;
+pub mod keymap;
+pub mod screenshot;
+pub mod tty;
+
+mod emulator;
+
+pub use self::emulator::*;
@@ -1,13 +1,52 @@
-mod chip8;
+#[cfg(feature = "sdl")]
 mod sdl2;
 
+#[cfg(feature = "sdl")]
+use chip8emu::keymap::Keymap;
+use chip8emu::{chip8, headless, host, screenshot, tty, Emulator};
+
 #[macro_use]
 extern crate clap;
-use clap::{Arg, App};
+use clap::{Arg, App, AppSettings, SubCommand};
+
+// The first one is the default
+const FRONTEND_NAMES: &[&str] = &[
+    #[cfg(feature = "sdl")]
+    "sdl",
+    "tty",
+];
 
 fn main() {
     // Argument parsing
     let matches = App::new("CHIP-8 Emulator")
+        .setting(AppSettings::SubcommandsNegateReqs)
+        .subcommand(SubCommand::with_name("disasm")
+             .about("print the disassembly of a ROM")
+             .arg(Arg::with_name("rom_path")
+                  .help("path of the CHIP-8 ROM to disassemble")
+                  .required(true)
+                  .index(1))
+             .arg(Arg::with_name("platform")
+                  .short("p")
+                  .long("platform")
+                  .takes_value(true)
+                  .possible_values(&chip8::PLATFORM_NAMES)
+                  .help("platform whose instruction set is used"))
+             .arg(Arg::with_name("source")
+                  .long("source")
+                  .help("print source the assembler can read back instead of a listing")))
+        .subcommand(SubCommand::with_name("asm")
+             .about("assemble a source file into a ROM")
+             .arg(Arg::with_name("source_path")
+                  .help("path of the assembly source")
+                  .required(true)
+                  .index(1))
+             .arg(Arg::with_name("output")
+                  .short("o")
+                  .long("output")
+                  .takes_value(true)
+                  .required(true)
+                  .help("path of the ROM to write")))
         .arg(Arg::with_name("rom_path")
              .help("path of the CHIP-8 ROM to load")
              .required(true)
@@ -19,24 +58,442 @@ fn main() {
              .short("s")
              .takes_value(true)
              .help("screen upscale factor"))
+        .arg(Arg::with_name("screenshot_scale")
+             .long("screenshot-scale")
+             .takes_value(true)
+             .help("screenshot upscale factor, F12 takes a screenshot (default: 1)"))
+        .arg(Arg::with_name("platform")
+             .short("p")
+             .long("platform")
+             .takes_value(true)
+             .possible_values(&chip8::PLATFORM_NAMES)
+             .help("platform to emulate"))
+        .arg(Arg::with_name("quirks")
+             .short("q")
+             .long("quirks")
+             .takes_value(true)
+             .possible_values(&chip8::QUIRKS_PROFILE_NAMES)
+             .help("interpreter quirks profile to emulate"))
+        .arg(Arg::with_name("random")
+             .long("random")
+             .takes_value(true)
+             .possible_values(&chip8::RANDOM_MODE_NAMES)
+             .help("random number generator, overrides the quirks profile one"))
+        .arg(Arg::with_name("display_wait")
+             .long("display-wait")
+             .takes_value(true)
+             .possible_values(&["on", "off"])
+             .help("whether DXYN waits for the next 60 Hz frame, overrides the quirks profile"))
+        .arg(Arg::with_name("timing")
+             .long("timing")
+             .takes_value(true)
+             .possible_values(&chip8::TIMING_MODE_NAMES)
+             .help("instruction timing, 'vip' charges each instruction its COSMAC VIP cycles (default: fixed)"))
+        .arg(Arg::with_name("ipf")
+             .long("ipf")
+             .takes_value(true)
+             .help("instructions per 60 Hz frame, runs whole frames instead of one instruction every 2 ms"))
+        .arg(Arg::with_name("tone_frequency")
+             .long("tone-frequency")
+             .takes_value(true)
+             .help("buzzer frequency in Hz"))
+        .arg(Arg::with_name("volume")
+             .long("volume")
+             .takes_value(true)
+             .help("buzzer volume, from 0 to 1"))
+        .arg(Arg::with_name("waveform")
+             .long("waveform")
+             .takes_value(true)
+             .possible_values(&chip8::WAVEFORM_NAMES)
+             .help("buzzer waveform"))
+        .arg(Arg::with_name("rewind_buffer")
+             .long("rewind-buffer")
+             .takes_value(true)
+             .help("memory kept for rewinding in MiB, 0 disables it (default: 4)"))
+        .arg(Arg::with_name("rewind_interval")
+             .long("rewind-interval")
+             .takes_value(true)
+             .help("frames between rewind snapshots (default: 1)"))
+        .arg(Arg::with_name("seed")
+             .long("seed")
+             .takes_value(true)
+             .help("seed of the random number generator"))
+        .arg(Arg::with_name("record_movie")
+             .long("record-movie")
+             .takes_value(true)
+             .conflicts_with_all(&["play_movie", "headless"])
+             .help("record the inputs to a movie file"))
+        .arg(Arg::with_name("play_movie")
+             .long("play-movie")
+             .takes_value(true)
+             .help("replay the inputs of a movie file"))
+        .arg(Arg::with_name("debugger")
+             .long("debugger")
+             .conflicts_with_all(&["record_movie", "play_movie", "headless"])
+             .help("start paused in the debugger, F10 breaks in at any time"))
+        .arg(Arg::with_name("gdb")
+             .long("gdb")
+             .takes_value(true)
+             .value_name("port")
+             .conflicts_with_all(&["record_movie", "play_movie", "headless", "debugger"])
+             .help("wait for a GDB client on the given localhost port before running"))
+        .arg(Arg::with_name("trace")
+             .long("trace")
+             .takes_value(true)
+             .help("write every executed instruction to a file"))
+        .arg(Arg::with_name("trace_addresses")
+             .long("trace-addresses")
+             .takes_value(true)
+             .requires("trace")
+             .help("only trace instructions in the address range, as in '200-2FF'"))
+        .arg(Arg::with_name("trace_frames")
+             .long("trace-frames")
+             .takes_value(true)
+             .requires("trace")
+             .help("only trace instructions in the frame range, as in '60-120'"))
+        .arg(Arg::with_name("trace_calls")
+             .long("trace-calls")
+             .requires("trace")
+             .help("only trace CALL and RET"))
+        .arg(Arg::with_name("trace_ring")
+             .long("trace-ring")
+             .takes_value(true)
+             .requires("trace")
+             .help("only write the last instructions before a fault"))
+        .arg(Arg::with_name("frontend")
+             .long("frontend")
+             .takes_value(true)
+             .possible_values(FRONTEND_NAMES)
+             .help("window or terminal display (default: sdl when built with it, tty otherwise)"))
+        .arg(Arg::with_name("tty_mode")
+             .long("tty-mode")
+             .takes_value(true)
+             .possible_values(&tty::TTY_RENDER_MODE_NAMES)
+             .help("terminal characters used for the pixels (default: blocks)"))
+        .arg(Arg::with_name("keymap")
+             .long("keymap")
+             .takes_value(true)
+             .help("key bindings file, one '<key|action> = <keys>' per line, '[rom <file name>]' sections override them"))
+        .arg(Arg::with_name("map")
+             .long("map")
+             .takes_value(true)
+             .multiple(true)
+             .number_of_values(1)
+             .help("binds keys after the keymap file, as in '5=key:Space', '2=pad:dpup, pad:lefty-' or 'quit=F12'"))
+        .arg(Arg::with_name("headless")
+             .long("headless")
+             .help("run without a window on a synthetic clock, then print a report"))
+        .arg(Arg::with_name("frames")
+             .long("frames")
+             .takes_value(true)
+             .requires("headless")
+             .help("headless run length in 60 Hz frames (default: 600)"))
+        .arg(Arg::with_name("instructions")
+             .long("instructions")
+             .takes_value(true)
+             .requires("headless")
+             .conflicts_with("frames")
+             .help("headless run length in instructions"))
+        .arg(Arg::with_name("keys")
+             .long("keys")
+             .takes_value(true)
+             .requires("headless")
+             .help("headless key script, one '<frame> <key> <down|up>' per line"))
+        .arg(Arg::with_name("output")
+             .short("o")
+             .long("output")
+             .takes_value(true)
+             .requires("headless")
+             .help("write the headless report to a file instead of stdout"))
+        .arg(Arg::with_name("screenshot")
+             .long("screenshot")
+             .takes_value(true)
+             .requires("headless")
+             .help("write the final headless frame to a PNG file"))
         .get_matches();
 
+    if let Some(matches) = matches.subcommand_matches("disasm") {
+        run_disassembler(matches);
+        return;
+    }
+
+    if let Some(matches) = matches.subcommand_matches("asm") {
+        run_assembler(matches);
+        return;
+    }
+
     let rom_path = matches.value_of("rom_path").unwrap();
 
-    let config = chip8::EmuConfig {
+    let platform = matches.value_of("platform")
+        .and_then(chip8::Platform::from_name)
+        .unwrap_or_default();
+
+    let mut config = chip8::EmuConfig {
         debug_mode: matches.is_present("debug"),
         palette: chip8::Palette {
             primary: chip8::Color { r: 1.0, g: 1.0, b: 1.0 },
-            secondary: chip8::Color { r: 0.14, g: 0.14, b: 0.14 }
+            secondary: chip8::Color { r: 0.14, g: 0.14, b: 0.14 },
+            tertiary: chip8::Color { r: 0.55, g: 0.55, b: 0.55 },
+            quaternary: chip8::Color { r: 0.78, g: 0.78, b: 0.78 },
         },
         screen_scale: value_t!(matches, "scale", u32).unwrap_or(16),
+        screenshot_scale: value_t!(matches, "screenshot_scale", u32).unwrap_or(1),
+        audio: chip8::AudioConfig {
+            frequency: value_t!(matches, "tone_frequency", f32).unwrap_or(440.0),
+            volume: value_t!(matches, "volume", f32).unwrap_or(0.25).clamp(0.0, 1.0),
+            waveform: matches.value_of("waveform")
+                .and_then(chip8::Waveform::from_name)
+                .unwrap_or_default(),
+        },
+        rewind: chip8::RewindConfig {
+            buffer_size_in_bytes: value_t!(matches, "rewind_buffer", usize).unwrap_or(4) * 1024 * 1024,
+            snapshot_interval: value_t!(matches, "rewind_interval", u32).unwrap_or(1).max(1),
+        },
+        platform,
+        quirks: matches.value_of("quirks")
+            .and_then(chip8::QuirksProfile::from_name)
+            .map(chip8::Quirks::from_profile)
+            .unwrap_or_else(|| chip8::Quirks::for_platform(platform)),
+    };
+
+    if let Some(random_mode) = matches.value_of("random").and_then(chip8::RandomMode::from_name) {
+        config.quirks.random_mode = random_mode;
+    }
+
+    if let Some(display_wait) = matches.value_of("display_wait") {
+        config.quirks.display_wait = display_wait == "on";
+    }
+
+    if let Some(timing) = matches.value_of("timing").and_then(chip8::TimingMode::from_name) {
+        config.quirks.timing = timing;
+    }
+
+    if let Ok(instructions_per_frame) = value_t!(matches, "ipf", u32) {
+        config.quirks.instructions_per_frame = Some(instructions_per_frame).filter(|&ipf| ipf > 0);
+    }
+
+    let mut emulator = Emulator::with_config(&config);
+
+    let rom_content = std::fs::read(rom_path).expect("Unable to read file");
+
+    if let Err(fault) = emulator.load_program(rom_content) {
+        eprintln!("error: unable to load '{}': {}", rom_path, fault);
+        std::process::exit(1);
+    }
+
+    if let Ok(seed) = value_t!(matches, "seed", u64) {
+        emulator.seed_rng(seed);
+    }
+
+    if let Some(path) = matches.value_of("trace") {
+        start_trace(&mut emulator, &matches, path);
+    }
+
+    let movie_player = matches.value_of("play_movie").map(|path| {
+        let text = std::fs::read_to_string(path).expect("Unable to read movie");
+
+        chip8::Movie::parse(&text)
+            .and_then(|movie| chip8::MoviePlayer::new(movie, emulator.state_mut()))
+            .unwrap_or_else(|error| {
+                eprintln!("error: unable to play '{}': {}", path, error);
+                std::process::exit(1);
+            })
+    });
+
+    if matches.is_present("headless") {
+        run_headless(&mut emulator, &config, &matches, movie_player);
+    } else {
+        let movie_mode = match (movie_player, matches.value_of("record_movie")) {
+            (Some(player), _) => host::MovieMode::Play(player),
+            (None, Some(path)) => {
+                let seed = value_t!(matches, "seed", u64).unwrap_or_else(|_| rand::random());
+
+                host::MovieMode::Record {
+                    recorder: chip8::MovieRecorder::new(emulator.state_mut(), seed, 60),
+                    path: path.to_string(),
+                }
+            },
+            (None, None) => host::MovieMode::Off,
+        };
+
+        let debug_mode = if let Ok(port) = value_t!(matches, "gdb", u16) {
+            let listener = std::net::TcpListener::bind(("127.0.0.1", port)).expect("Unable to listen for GDB");
+
+            println!("Waiting for GDB on port {}", port);
+
+            host::DebugMode::Gdb(chip8::gdbstub::GdbStub::accept(&listener).expect("Unable to accept GDB"))
+        } else if matches.is_present("debugger") {
+            let mut debugger = chip8::debugger::Debugger::new();
+            debugger.pause();
+            host::DebugMode::Repl(debugger)
+        } else {
+            host::DebugMode::Off
+        };
+
+        let mut session = host::Session { rom_path: rom_path.to_string(), movie_mode, debug_mode };
+        let mut frontend: Box<dyn host::Host> = match matches.value_of("frontend").unwrap_or(FRONTEND_NAMES[0]) {
+            #[cfg(feature = "sdl")]
+            "sdl" => {
+                let keymap = load_keymap(&matches, rom_path);
+
+                Box::new(sdl2::SdlHost::new(&emulator, &config, &keymap).unwrap())
+            },
+            _ => {
+                let mode = matches.value_of("tty_mode")
+                    .and_then(tty::TtyRenderMode::from_name)
+                    .unwrap_or(tty::TtyRenderMode::HalfBlocks);
+
+                Box::new(tty::TtyHost::new(mode, &config.palette).unwrap())
+            },
+        };
+
+        let result = host::run_main_loop(&mut emulator, frontend.as_mut(), &config, &mut session);
+
+        // The terminal has to be restored before reporting anything
+        drop(frontend);
+        result.unwrap();
+    }
+}
+
+#[cfg(feature = "sdl")]
+fn load_keymap(matches: &clap::ArgMatches, rom_path: &str) -> Keymap
+{
+    let rom_name = std::path::Path::new(rom_path).file_name().and_then(|name| name.to_str());
+
+    let mut keymap = match matches.value_of("keymap") {
+        Some(path) => {
+            let text = std::fs::read_to_string(path).expect("Unable to read keymap");
+
+            Keymap::parse(&text, rom_name).unwrap_or_else(|error| {
+                eprintln!("error: invalid keymap '{}': {}", path, error);
+                std::process::exit(1);
+            })
+        },
+        None => Keymap::default(),
+    };
+
+    for binding in matches.values_of("map").into_iter().flatten() {
+        if let Err(error) = keymap.apply(binding) {
+            eprintln!("error: invalid key binding '{}': {}", binding, error);
+            std::process::exit(1);
+        }
+    }
+
+    keymap
+}
+
+fn run_disassembler(matches: &clap::ArgMatches)
+{
+    let rom_path = matches.value_of("rom_path").unwrap();
+    let platform = matches.value_of("platform")
+        .and_then(chip8::Platform::from_name)
+        .unwrap_or_default();
+
+    let rom_content = std::fs::read(rom_path).expect("Unable to read file");
+    let lines = chip8::disassembler::disassemble(&rom_content, platform);
+
+    if matches.is_present("source") {
+        print!("{}", chip8::disassembler::format_source(&lines));
+    } else {
+        print!("{}", chip8::disassembler::format_listing(&lines));
+    }
+}
+
+fn run_assembler(matches: &clap::ArgMatches)
+{
+    let source_path = matches.value_of("source_path").unwrap();
+    let output_path = matches.value_of("output").unwrap();
+
+    let source = std::fs::read_to_string(source_path).expect("Unable to read file");
+
+    let program = chip8::assembler::assemble(&source).unwrap_or_else(|error| {
+        eprintln!("{}:{}", source_path, error);
+        std::process::exit(1);
+    });
+
+    std::fs::write(output_path, program).expect("Unable to write ROM");
+}
+
+// Parses '<first>-<last>', inclusive.
+fn parse_range(text: &str, radix: u32) -> Option<std::ops::RangeInclusive<u32>>
+{
+    let mut bounds = text.splitn(2, '-');
+    let first = u32::from_str_radix(bounds.next()?.trim(), radix).ok()?;
+    let last = u32::from_str_radix(bounds.next()?.trim(), radix).ok()?;
+
+    Some(first..=last)
+}
+
+fn start_trace(emulator: &mut Emulator, matches: &clap::ArgMatches, path: &str)
+{
+    let range_argument = |name: &str, radix: u32| matches.value_of(name).map(|text| {
+        parse_range(text, radix).unwrap_or_else(|| {
+            eprintln!("error: invalid range '{}'", text);
+            std::process::exit(1);
+        })
+    });
+
+    let config = chip8::trace::TraceConfig {
+        filter: chip8::trace::TraceFilter {
+            addresses: range_argument("trace_addresses", 16).map(|range| (*range.start() as u16)..=(*range.end() as u16)),
+            frames: range_argument("trace_frames", 10),
+            calls_only: matches.is_present("trace_calls"),
+        },
+        ring_buffer_size: value_t!(matches, "trace_ring", usize).unwrap_or(0),
+    };
+
+    let file = std::fs::File::create(path).expect("Unable to create trace");
+    let writer = Box::new(std::io::BufWriter::new(file));
+
+    emulator.state_mut().tracer = Some(Box::new(chip8::trace::Tracer::new(config, writer)));
+}
+
+fn run_headless(emulator: &mut Emulator, config: &chip8::EmuConfig, matches: &clap::ArgMatches, movie_player: Option<chip8::MoviePlayer>)
+{
+    let limit = match value_t!(matches, "instructions", u32) {
+        Ok(instruction_count) => headless::RunLimit::Instructions(instruction_count),
+        Err(_) => headless::RunLimit::Frames(value_t!(matches, "frames", u32).unwrap_or(600)),
+    };
+
+    let key_script = match matches.value_of("keys") {
+        Some(path) => {
+            let script = std::fs::read_to_string(path).expect("Unable to read key script");
+
+            headless::parse_key_script(&script).unwrap_or_else(|error| {
+                eprintln!("error: invalid key script '{}': {}", path, error);
+                std::process::exit(1);
+            })
+        }
+        None => Vec::new(),
+    };
+
+    let report = match movie_player {
+        Some(mut player) => headless::play_movie(emulator, &mut player),
+        None => headless::run(emulator, &headless::HeadlessConfig { limit, key_script }),
+    };
+
+    let write_result = match matches.value_of("output") {
+        Some(path) => std::fs::File::create(path)
+            .and_then(|mut file| headless::write_report(emulator, &report, &mut file)),
+        None => headless::write_report(emulator, &report, &mut std::io::stdout()),
     };
 
-    let mut state: chip8::CPUState = chip8::create_chip8_state();
+    write_result.expect("Unable to write report");
 
-    let rom_content = std::fs::read(&rom_path).expect("Unable to read file");
+    if let Some(path) = matches.value_of("screenshot") {
+        screenshot::save_screen_png(emulator, &config.palette, config.screenshot_scale, path.as_ref())
+            .expect("Unable to write screenshot");
+    }
 
-    chip8::load_program(&mut state, rom_content);
+    // Exiting skips the destructors, the trace has to be flushed first
+    if let Some(tracer) = emulator.state_mut().tracer.take() {
+        tracer.finish().expect("Unable to write trace");
+    }
 
-    sdl2::execute_main_loop(&mut state, &config).unwrap();
+    // Let CI tell faulting ROMs and broken movies apart
+    if report.fault.is_some() {
+        std::process::exit(2);
+    } else if report.desync_frame.is_some() {
+        std::process::exit(3);
+    }
 }
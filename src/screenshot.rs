@@ -0,0 +1,276 @@
+use crate::{
+    chip8::config::Palette,
+    Emulator,
+};
+
+use std::{
+    fs,
+    io,
+    path::Path,
+};
+
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n'];
+
+// Indexed colour, with 2 bits per pixel since a pixel is one of the 4 palette colours
+const PNG_COLOR_TYPE_INDEXED: u8 = 3;
+const PNG_BIT_DEPTH: u8 = 2;
+const PIXELS_PER_BYTE: usize = 8 / PNG_BIT_DEPTH as usize;
+
+// Largest deflate block that can be stored uncompressed
+const STORED_BLOCK_MAX_SIZE: usize = 0xFFFF;
+
+fn crc32(chunks: &[&[u8]]) -> u32
+{
+    let mut crc = 0xFFFF_FFFFu32;
+
+    for &byte in chunks.iter().flat_map(|chunk| chunk.iter()) {
+        crc ^= u32::from(byte);
+
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32
+{
+    const MODULO: u32 = 65521;
+
+    let (a, b) = data.iter().fold((1u32, 0u32), |(a, b), &byte| {
+        let a = (a + u32::from(byte)) % MODULO;
+        (a, (b + a) % MODULO)
+    });
+
+    (b << 16) | a
+}
+
+// Screens are tiny, storing the deflate blocks uncompressed keeps the encoder trivially exact.
+fn zlib_stored(data: &[u8]) -> Vec<u8>
+{
+    // Deflate with a 32K window and no preset dictionary
+    let mut stream = vec![0x78, 0x01];
+    let mut blocks = data.chunks(STORED_BLOCK_MAX_SIZE).peekable();
+
+    if blocks.peek().is_none() {
+        stream.extend_from_slice(&[0x01, 0x00, 0x00, 0xFF, 0xFF]);
+    }
+
+    while let Some(block) = blocks.next() {
+        let is_final = blocks.peek().is_none();
+        let size = block.len() as u16;
+
+        stream.push(is_final as u8);
+        stream.extend_from_slice(&size.to_le_bytes());
+        stream.extend_from_slice(&(!size).to_le_bytes());
+        stream.extend_from_slice(block);
+    }
+
+    stream.extend_from_slice(&adler32(data).to_be_bytes());
+    stream
+}
+
+fn write_chunk(png: &mut Vec<u8>, chunk_type: &[u8; 4], data: &[u8])
+{
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    png.extend_from_slice(chunk_type);
+    png.extend_from_slice(data);
+    png.extend_from_slice(&crc32(&[chunk_type, data]).to_be_bytes());
+}
+
+// Encodes an image of palette indices, one byte per pixel row by row, as an indexed PNG.
+// The palette holds at most 4 RGB colours.
+pub fn encode_png(width: usize, height: usize, palette: &[[u8; 3]], color_indices: &[u8]) -> Vec<u8>
+{
+    assert!(palette.len() <= 1 << PNG_BIT_DEPTH, "too many colours for the bit depth");
+    assert_eq!(color_indices.len(), width * height, "color_indices doesn't match the image size");
+
+    let mut header = Vec::new();
+
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&(height as u32).to_be_bytes());
+    // Bit depth, colour type, then default compression, filtering and no interlacing
+    header.extend_from_slice(&[PNG_BIT_DEPTH, PNG_COLOR_TYPE_INDEXED, 0, 0, 0]);
+
+    // Every row starts with its filter type, none here
+    let row_size = 1 + width.div_ceil(PIXELS_PER_BYTE);
+    let mut image_data = vec![0; row_size * height];
+
+    if width > 0 {
+        for (row, indices) in image_data.chunks_mut(row_size).zip(color_indices.chunks(width)) {
+            for (x, &color_index) in indices.iter().enumerate() {
+                let shift = 8 - PNG_BIT_DEPTH as usize * (x % PIXELS_PER_BYTE + 1);
+                row[1 + x / PIXELS_PER_BYTE] |= (color_index & 0x3) << shift;
+            }
+        }
+    }
+
+    let mut png = PNG_SIGNATURE.to_vec();
+
+    write_chunk(&mut png, b"IHDR", &header);
+    write_chunk(&mut png, b"PLTE", &palette.concat());
+    write_chunk(&mut png, b"IDAT", &zlib_stored(&image_data));
+    write_chunk(&mut png, b"IEND", &[]);
+
+    png
+}
+
+// The current screen in the palette's colours, every pixel grown to a square of scale by scale.
+pub fn encode_screen_png(emulator: &Emulator, palette: &Palette, scale: u32) -> Vec<u8>
+{
+    let scale = scale.max(1) as usize;
+    let (width, height) = (emulator.screen_width() * scale, emulator.screen_height() * scale);
+
+    let colors: Vec<[u8; 3]> = (0..4)
+        .map(|color_index| {
+            let color = palette.color(color_index);
+            [(255.0 * color.r) as u8, (255.0 * color.g) as u8, (255.0 * color.b) as u8]
+        })
+        .collect();
+
+    let color_indices: Vec<u8> = (0..height)
+        .flat_map(|y| (0..width).map(move |x| (x / scale, y / scale)))
+        .map(|(x, y)| emulator.read_screen_pixel_color_index(x, y))
+        .collect();
+
+    encode_png(width, height, &colors, &color_indices)
+}
+
+pub fn save_screen_png(emulator: &Emulator, palette: &Palette, scale: u32, path: &Path) -> io::Result<()>
+{
+    fs::write(path, encode_screen_png(emulator, palette, scale))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::config::Color;
+
+    struct DecodedPng
+    {
+        width: usize,
+        height: usize,
+        palette: Vec<u8>,
+        color_indices: Vec<u8>,
+    }
+
+    fn read_u32(data: &[u8]) -> u32
+    {
+        u32::from_be_bytes([data[0], data[1], data[2], data[3]])
+    }
+
+    // Only reads back what encode_png writes, checking every checksum on the way.
+    fn decode_png(png: &[u8]) -> DecodedPng
+    {
+        assert_eq!(png[..8], PNG_SIGNATURE);
+
+        let mut chunks = Vec::new();
+        let mut offset = 8;
+
+        while offset < png.len() {
+            let size = read_u32(&png[offset..]) as usize;
+            let chunk_type = &png[offset + 4..offset + 8];
+            let data = &png[offset + 8..offset + 8 + size];
+
+            assert_eq!(read_u32(&png[offset + 8 + size..]), crc32(&[chunk_type, data]));
+
+            chunks.push((chunk_type.to_vec(), data.to_vec()));
+            offset += 12 + size;
+        }
+
+        let chunk_types: Vec<&[u8]> = chunks.iter().map(|(chunk_type, _)| chunk_type.as_slice()).collect();
+
+        assert_eq!(chunk_types, [b"IHDR", b"PLTE", b"IDAT", b"IEND"]);
+
+        let header = &chunks[0].1;
+        let (width, height) = (read_u32(header) as usize, read_u32(&header[4..]) as usize);
+
+        assert_eq!(header[8..], [2, 3, 0, 0, 0]);
+
+        let stream = &chunks[2].1;
+        let mut image_data = Vec::new();
+        let mut offset = 2;
+
+        assert_eq!((u16::from(stream[0]) << 8 | u16::from(stream[1])) % 31, 0);
+
+        loop {
+            let is_final = stream[offset] & 1 != 0;
+            let size = usize::from(u16::from_le_bytes([stream[offset + 1], stream[offset + 2]]));
+
+            assert_eq!(stream[offset] >> 1, 0);
+            assert_eq!(u16::from_le_bytes([stream[offset + 3], stream[offset + 4]]), !(size as u16));
+
+            image_data.extend_from_slice(&stream[offset + 5..offset + 5 + size]);
+            offset += 5 + size;
+
+            if is_final {
+                break;
+            }
+        }
+
+        assert_eq!(read_u32(&stream[offset..]), adler32(&image_data));
+        assert_eq!(offset + 4, stream.len());
+
+        let row_size = 1 + width.div_ceil(4);
+
+        assert_eq!(image_data.len(), row_size * height);
+
+        let color_indices = image_data.chunks(row_size)
+            .flat_map(|row| {
+                assert_eq!(row[0], 0);
+                (0..width).map(move |x| (row[1 + x / 4] >> (6 - 2 * (x % 4))) & 0x3)
+            })
+            .collect();
+
+        DecodedPng { width, height, palette: chunks[1].1.clone(), color_indices }
+    }
+
+    #[test]
+    fn screenshot() {
+        assert_eq!(crc32(&[b"IEND"]), 0xAE42_6082);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+
+        let mut emulator = Emulator::new();
+
+        emulator.state_mut().screen[0][0][0] = 0x03;
+        emulator.state_mut().screen[0][31][7] = 0x80;
+
+        let palette = Palette {
+            primary: Color { r: 1.0, g: 0.5, b: 0.0 },
+            secondary: Color { r: 0.0, g: 0.0, b: 0.0 },
+            tertiary: Color { r: 0.0, g: 0.0, b: 1.0 },
+            quaternary: Color { r: 0.0, g: 1.0, b: 0.0 },
+        };
+
+        let png = decode_png(&encode_screen_png(&emulator, &palette, 1));
+
+        assert_eq!((png.width, png.height), (64, 32));
+        assert_eq!(png.palette, [0, 0, 0, 255, 127, 0, 0, 0, 255, 0, 255, 0]);
+
+        let lit: Vec<usize> = (0..png.color_indices.len()).filter(|&index| png.color_indices[index] != 0).collect();
+
+        assert_eq!(lit, [0, 1, 31 * 64 + 63]);
+
+        // SUBCASE: scaling
+        {
+            let png = decode_png(&encode_screen_png(&emulator, &palette, 3));
+
+            assert_eq!((png.width, png.height), (192, 96));
+            assert_eq!(png.color_indices[2 * 192 + 5], 1);
+            assert_eq!(png.color_indices[2 * 192 + 6], 0);
+            assert_eq!(png.color_indices[95 * 192 + 191], 1);
+        }
+
+        // SUBCASE: colour planes and several deflate blocks
+        {
+            let (width, height) = (1000, 300);
+            let color_indices: Vec<u8> = (0..width * height).map(|index| (index % 7 % 4) as u8).collect();
+
+            let png = decode_png(&encode_png(width, height, &[[0; 3], [1; 3], [2; 3], [3; 3]], &color_indices));
+
+            assert_eq!((png.width, png.height), (width, height));
+            assert_eq!(png.color_indices, color_indices);
+        }
+    }
+}
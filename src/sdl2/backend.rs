@@ -1,138 +1,322 @@
-use crate::chip8::{
-    config,
-    cpu,
-    cpu::CPUState,
-    execution,
-    keyboard,
+use chip8emu::{
+    chip8::{
+        audio,
+        config,
+    },
+    host::{Host, HostInput},
+    keymap::{self, Binding, BindingState, ControllerSet, InputName, InputReading, Keymap},
+    Emulator,
 };
 
 extern crate sdl2;
 
+use std::cmp::max;
+
 use sdl2::{
+    audio::{AudioCallback, AudioDevice, AudioSpecDesired},
+    controller::{Axis, Button, GameController},
     event::Event,
     keyboard::{Keycode, Scancode},
     pixels::PixelFormatEnum,
+    render::Canvas,
+    video::Window,
+    EventPump,
+    GameControllerSubsystem,
+    TimerSubsystem,
 };
 
-pub fn execute_main_loop(state: &mut CPUState, config: &config::EmuConfig) -> Result<(), String>
+const AUDIO_SAMPLE_RATE: i32 = 44100;
+
+const WINDOW_TITLE: &str = "CHIP-8 Emulator";
+
+struct Buzzer
 {
-    let scale = config.screen_scale as usize;
-    let framebuffer_width = cpu::SCREEN_WIDTH * scale;
-    let framebuffer_height = cpu::SCREEN_HEIGHT * scale;
+    generator: audio::ToneGenerator,
+    is_playing: bool,
+}
 
-    let sdl_context = sdl2::init()?;
-    let video_subsystem = sdl_context.video()?;
-    let mut timer_subsystem = sdl_context.timer()?;
+impl AudioCallback for Buzzer
+{
+    type Channel = f32;
 
-    let window = video_subsystem.window("CHIP-8 Emulator", framebuffer_width as u32, framebuffer_height as u32)
-        .position_centered()
-        .build()
-        .map_err(|e| e.to_string())?;
+    fn callback(&mut self, out: &mut [f32])
+    {
+        self.generator.generate(self.is_playing, out);
+    }
+}
 
-    let mut canvas = window.into_canvas()
-        .present_vsync()
-        .build()
-        .map_err(|e| e.to_string())?;
+pub struct SdlHost
+{
+    canvas: Canvas<Window>,
+    event_pump: EventPump,
+    timer_subsystem: TimerSubsystem,
+    audio_device: Option<AudioDevice<Buzzer>>,
+    palette_bgra: Vec<[u8; 4]>, // Indexed by the pixel color index
+    framebuffer_width: usize,
+    controller_subsystem: Option<GameControllerSubsystem>,
+    controllers: ControllerSet<GameController>, // Opened as they are plugged in
+    bindings: Vec<(Binding, InputSource)>,
+    axis_threshold: i16,
+    binding_state: BindingState,
+    is_quit_requested: bool,
+}
 
-    let texture_creator = canvas.texture_creator();
+#[derive(Clone, Copy, PartialEq)]
+enum InputSource
+{
+    Scancode(Scancode),
+    Button(Button),
+    Axis(Axis, bool),
+}
+
+// Keycodes are looked up in the current keyboard layout once, everything is scancodes afterwards.
+fn resolve_input(input: &InputName) -> Result<InputSource, String>
+{
+    match input {
+        InputName::Scancode(name) => Scancode::from_name(name)
+            .map(InputSource::Scancode)
+            .ok_or_else(|| format!("unknown scancode '{}'", name)),
+        InputName::Keycode(name) => Keycode::from_name(name)
+            .and_then(Scancode::from_keycode)
+            .map(InputSource::Scancode)
+            .ok_or_else(|| format!("unknown key '{}'", name)),
+        InputName::Button(name) => Button::from_string(name)
+            .map(InputSource::Button)
+            .ok_or_else(|| format!("unknown controller button '{}'", name)),
+        InputName::Axis { name, is_positive } => Axis::from_string(name)
+            .map(|axis| InputSource::Axis(axis, *is_positive))
+            .ok_or_else(|| format!("unknown controller axis '{}'", name)),
+    }
+}
+
+impl SdlHost
+{
+    pub fn new(emulator: &Emulator, config: &config::EmuConfig, keymap: &Keymap) -> Result<SdlHost, String>
+    {
+        let scale = config.screen_scale as usize;
+        let framebuffer_width = emulator.screen_width() * scale;
+        let framebuffer_height = emulator.screen_height() * scale;
 
-    let mut previous_time_ms: u32 = timer_subsystem.ticks();
+        let sdl_context = sdl2::init()?;
+        let video_subsystem = sdl_context.video()?;
+        let timer_subsystem = sdl_context.timer()?;
 
-    let mut event_pump = sdl_context.event_pump()?;
+        // Keep running without sound if no audio device is available
+        let audio_device = sdl_context.audio().and_then(|audio_subsystem| {
+            let desired_spec = AudioSpecDesired {
+                freq: Some(AUDIO_SAMPLE_RATE),
+                channels: Some(1),
+                samples: None,
+            };
+
+            audio_subsystem.open_playback(None, &desired_spec, |spec| {
+                Buzzer {
+                    generator: audio::ToneGenerator::new(config.audio, spec.freq as u32),
+                    is_playing: false,
+                }
+            })
+        });
+
+        let audio_device = match audio_device {
+            Ok(device) => {
+                device.resume();
+                Some(device)
+            },
+            Err(error) => {
+                eprintln!("warning: audio disabled: {}", error);
+                None
+            },
+        };
+
+        let window = video_subsystem.window(WINDOW_TITLE, framebuffer_width as u32, framebuffer_height as u32)
+            .position_centered()
+            .build()
+            .map_err(|e| e.to_string())?;
+
+        let canvas = window.into_canvas()
+            .present_vsync()
+            .build()
+            .map_err(|e| e.to_string())?;
+
+        let event_pump = sdl_context.event_pump()?;
+
+        // Controllers already plugged in are reported as added by the first events
+        let controller_subsystem = sdl_context.game_controller()
+            .map_err(|error| eprintln!("warning: game controllers disabled: {}", error))
+            .ok();
+
+        let mut bindings = Vec::new();
+
+        for (binding, inputs) in &keymap.bindings {
+            for input in inputs {
+                bindings.push((*binding, resolve_input(input)?));
+            }
+        }
+
+        let palette_bgra = (0..4).map(|color_index| {
+            let color = config.palette.color(color_index);
+            [
+                (255.0 * color.b) as u8,
+                (255.0 * color.g) as u8,
+                (255.0 * color.r) as u8,
+                255
+            ]
+        }).collect();
+
+        Ok(SdlHost {
+            canvas,
+            event_pump,
+            timer_subsystem,
+            audio_device,
+            palette_bgra,
+            framebuffer_width,
+            controller_subsystem,
+            controllers: ControllerSet::default(),
+            bindings,
+            axis_threshold: keymap.axis_threshold,
+            binding_state: BindingState::default(),
+            is_quit_requested: false,
+        })
+    }
+}
+
+impl SdlHost
+{
+    fn open_controller(&mut self, joystick_index: u32)
+    {
+        let controller = match &self.controller_subsystem {
+            Some(subsystem) => subsystem.open(joystick_index),
+            None => return,
+        };
+
+        match controller {
+            Ok(controller) => self.controllers.add(controller.instance_id(), controller),
+            Err(error) => eprintln!("warning: unable to open game controller: {}", error),
+        }
+    }
+
+    fn is_controller_input_pressed(&self, source: InputSource) -> bool
+    {
+        self.controllers.is_any_pressed(|controller| match source {
+            InputSource::Button(button) => controller.button(button),
+            InputSource::Axis(axis, is_positive) => keymap::is_axis_pressed(controller.axis(axis), is_positive, self.axis_threshold),
+            InputSource::Scancode(_) => false,
+        })
+    }
+}
+
+impl Host for SdlHost
+{
+    fn poll_input(&mut self) -> HostInput
+    {
+        let mut input = HostInput::default();
 
-    'mainloop: loop {
         // Poll events
-        for event in event_pump.poll_iter() {
+        let events: Vec<Event> = self.event_pump.poll_iter().collect();
+
+        for event in events {
             match event {
-                Event::Quit{..} | Event::KeyDown {keycode: Option::Some(Keycode::Escape), ..} =>
-                    break 'mainloop,
+                Event::Quit{..} => self.is_quit_requested = true,
+                Event::ControllerDeviceAdded {which, ..} => self.open_controller(which),
+                Event::ControllerDeviceRemoved {which, ..} => self.controllers.remove(which),
+                Event::KeyDown {scancode: Option::Some(scancode), repeat: false, ..} => {
+                    let bindings = self.bindings.iter()
+                        .filter(|(_, source)| *source == InputSource::Scancode(scancode))
+                        .map(|(binding, _)| *binding);
+
+                    for binding in bindings {
+                        self.binding_state.press(binding, &mut input);
+                    }
+                },
                 _ => {}
             }
         }
 
-        let keyboard_state = event_pump.keyboard_state();
-
-        // Get keyboard state
-        keyboard::set_key_pressed(state, 0x1, keyboard_state.is_scancode_pressed(Scancode::Num1));
-        keyboard::set_key_pressed(state, 0x2, keyboard_state.is_scancode_pressed(Scancode::Num2));
-        keyboard::set_key_pressed(state, 0x3, keyboard_state.is_scancode_pressed(Scancode::Num3));
-        keyboard::set_key_pressed(state, 0xC, keyboard_state.is_scancode_pressed(Scancode::Num4));
-        keyboard::set_key_pressed(state, 0x4, keyboard_state.is_scancode_pressed(Scancode::Q));
-        keyboard::set_key_pressed(state, 0x5, keyboard_state.is_scancode_pressed(Scancode::W));
-        keyboard::set_key_pressed(state, 0x6, keyboard_state.is_scancode_pressed(Scancode::E));
-        keyboard::set_key_pressed(state, 0xD, keyboard_state.is_scancode_pressed(Scancode::R));
-        keyboard::set_key_pressed(state, 0x7, keyboard_state.is_scancode_pressed(Scancode::A));
-        keyboard::set_key_pressed(state, 0x8, keyboard_state.is_scancode_pressed(Scancode::S));
-        keyboard::set_key_pressed(state, 0x9, keyboard_state.is_scancode_pressed(Scancode::D));
-        keyboard::set_key_pressed(state, 0xE, keyboard_state.is_scancode_pressed(Scancode::F));
-        keyboard::set_key_pressed(state, 0xA, keyboard_state.is_scancode_pressed(Scancode::Z));
-        keyboard::set_key_pressed(state, 0x0, keyboard_state.is_scancode_pressed(Scancode::X));
-        keyboard::set_key_pressed(state, 0xB, keyboard_state.is_scancode_pressed(Scancode::C));
-        keyboard::set_key_pressed(state, 0xF, keyboard_state.is_scancode_pressed(Scancode::V));
-
-        let current_time_ms: u32 = timer_subsystem.ticks();
-        let delta_time_ms: u32 = current_time_ms - previous_time_ms;
-
-        execution::execute_step(state, delta_time_ms);
-
-        // Draw
-        let mut texture = texture_creator.create_texture_streaming(PixelFormatEnum::BGRA32, framebuffer_width as u32, framebuffer_height as u32)
+        // Get keyboard and controller state, merged into the same keys
+        let keyboard_state = self.event_pump.keyboard_state();
+        let readings: Vec<(Binding, InputReading)> = self.bindings.iter()
+            .map(|&(binding, source)| (binding, match source {
+                InputSource::Scancode(scancode) => InputReading::Keyboard(keyboard_state.is_scancode_pressed(scancode)),
+                _ => InputReading::Controller(self.is_controller_input_pressed(source)),
+            }))
+            .collect();
+
+        self.binding_state.update(&readings, &mut input);
+
+        input
+    }
+
+    fn is_quit_requested(&self) -> bool
+    {
+        self.is_quit_requested || self.binding_state.is_quit_requested
+    }
+
+    fn ticks_ms(&mut self) -> u32
+    {
+        self.timer_subsystem.ticks()
+    }
+
+    fn present_screen(&mut self, emulator: &Emulator) -> Result<(), String>
+    {
+        // The emulated resolution can change at runtime, the texture is stretched to the window anyway
+        let upscale = max(1, self.framebuffer_width / emulator.screen_width());
+        let texture_width = emulator.screen_width() * upscale;
+        let texture_height = emulator.screen_height() * upscale;
+
+        let texture_creator = self.canvas.texture_creator();
+        let mut texture = texture_creator.create_texture_streaming(PixelFormatEnum::BGRA32, texture_width as u32, texture_height as u32)
             .map_err(|e| e.to_string())?;
 
+        let palette_bgra = &self.palette_bgra;
+
         // Copy texture data
         texture.with_lock(None, |mapped_buffer: &mut [u8], mapped_buffer_pitch: usize| {
-            let primary_color_bgra: [u8; 4] = [
-                (255.0 * config.palette.primary.b) as u8,
-                (255.0 * config.palette.primary.g) as u8,
-                (255.0 * config.palette.primary.r) as u8,
-                255
-            ];
-            let secondary_color_bgra: [u8; 4] = [
-                (255.0 * config.palette.secondary.b) as u8,
-                (255.0 * config.palette.secondary.g) as u8,
-                (255.0 * config.palette.secondary.r) as u8,
-                255
-            ];
-
             let mut scanlines = mapped_buffer.chunks_mut(mapped_buffer_pitch);
 
             // Convert and upscale screen image
-            for j in 0..cpu::SCREEN_HEIGHT {
+            for j in 0..emulator.screen_height() {
                 let current_scanline_slice = scanlines.next().unwrap();
                 let mut scanline_pixels = current_scanline_slice.chunks_mut(4);
 
-                for i in 0..cpu::SCREEN_LINE_SIZE_IN_BYTES {
-                    let pixel_byte: u8 = state.screen[j][i];
+                for i in 0..emulator.screen_width() {
+                    let color_index = emulator.read_screen_pixel_color_index(i, j);
+                    let color_slice = &palette_bgra[color_index as usize][..];
 
-                    for k in 0..8 {
-                        let pixel_state = ((pixel_byte >> k) & 0x1) != 0;
-                        let color_slice = if pixel_state { &primary_color_bgra[..] } else { &secondary_color_bgra[..] };
-
-                        // Copy to upscale
-                        for _ in 0..scale {
-                            let dst_pixel = scanline_pixels.next().unwrap();
-                            dst_pixel[..].clone_from_slice(color_slice);
-                        }
+                    // Copy to upscale
+                    for _ in 0..upscale {
+                        let dst_pixel = scanline_pixels.next().unwrap();
+                        dst_pixel[..].clone_from_slice(color_slice);
                     }
                 }
 
                 // Copy scanlines
-                for _ in 1..scale {
+                for _ in 1..upscale {
                     let dst_slice = scanlines.next().unwrap();
                     dst_slice[..].clone_from_slice(&current_scanline_slice[..]);
                 }
             }
         })?;
 
-        canvas.copy(&texture, None, None)?;
-        canvas.present();
+        self.canvas.copy(&texture, None, None)?;
+        self.canvas.present();
 
-        previous_time_ms = current_time_ms;
+        Ok(())
+    }
 
-        if config.debug_mode {
-            println!("Frame time = {} ms", delta_time_ms);
+    fn play_sound(&mut self, is_playing: bool)
+    {
+        if let Some(device) = &mut self.audio_device {
+            device.lock().is_playing = is_playing;
         }
     }
 
-    Ok(())
+    fn show_status(&mut self, status: Option<&str>) -> Result<(), String>
+    {
+        let title = match status {
+            Some(status) => format!("{} - {}", WINDOW_TITLE, status),
+            None => WINDOW_TITLE.to_string(),
+        };
+
+        self.canvas.window_mut().set_title(&title).map_err(|e| e.to_string())
+    }
 }
@@ -0,0 +1,492 @@
+use crate::{
+    chip8::config::Palette,
+    host::{Host, HostInput},
+    Emulator,
+};
+
+use std::{
+    io::{self, Read, Write},
+    process::Command,
+    sync::mpsc::{self, Receiver},
+    thread,
+    time::{Duration, Instant},
+};
+
+// Terminals only report key presses, along with the auto-repeat of held keys.
+// A key counts as released once no press came for a while. Before the first repeat that has to outlast the
+// initial repeat delay, usually 500 ms, once repeating it only has to outlast the repeat period.
+const KEY_INITIAL_RELEASE_TIMEOUT_MS: u32 = 750;
+const KEY_REPEAT_RELEASE_TIMEOUT_MS: u32 = 150;
+
+const FRAME_PERIOD_MS: u32 = 1000 / 60;
+
+const BYTE_CTRL_C: u8 = 0x03;
+const BYTE_ESCAPE: u8 = 0x1B;
+const BYTE_BACKSPACE: u8 = 0x7F;
+
+// CHIP-8 key of each character, same layout as the SDL frontend
+const KEY_CHARACTERS: [(u8, u8); 16] = [
+    (0x1, b'1'), (0x2, b'2'), (0x3, b'3'), (0xC, b'4'),
+    (0x4, b'q'), (0x5, b'w'), (0x6, b'e'), (0xD, b'r'),
+    (0x7, b'a'), (0x8, b's'), (0x9, b'd'), (0xE, b'f'),
+    (0xA, b'z'), (0x0, b'x'), (0xB, b'c'), (0xF, b'v'),
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TtyRenderMode
+{
+    HalfBlocks, // 1x2 pixels per character, in full color
+    Braille, // 2x4 pixels per character, one color per character
+}
+
+pub const TTY_RENDER_MODE_NAMES: [&str; 2] = ["blocks", "braille"];
+
+impl TtyRenderMode
+{
+    pub fn from_name(name: &str) -> Option<TtyRenderMode>
+    {
+        match name {
+            "blocks" => Some(TtyRenderMode::HalfBlocks),
+            "braille" => Some(TtyRenderMode::Braille),
+            _ => None,
+        }
+    }
+}
+
+fn foreground(rgb: [u8; 3]) -> String
+{
+    format!("\x1b[38;2;{};{};{}m", rgb[0], rgb[1], rgb[2])
+}
+
+fn background(rgb: [u8; 3]) -> String
+{
+    format!("\x1b[48;2;{};{};{}m", rgb[0], rgb[1], rgb[2])
+}
+
+// Turns the screen into rows of ANSI colored characters, only writing the rows that changed.
+pub struct TtyRenderer
+{
+    mode: TtyRenderMode,
+    palette_rgb: [[u8; 3]; 4], // Indexed by the pixel color index
+    previous_rows: Vec<String>,
+}
+
+impl TtyRenderer
+{
+    pub fn new(mode: TtyRenderMode, palette: &Palette) -> TtyRenderer
+    {
+        let mut palette_rgb = [[0; 3]; 4];
+
+        for (color_index, rgb) in palette_rgb.iter_mut().enumerate() {
+            let color = palette.color(color_index as u8);
+            *rgb = [(255.0 * color.r) as u8, (255.0 * color.g) as u8, (255.0 * color.b) as u8];
+        }
+
+        TtyRenderer {
+            mode,
+            palette_rgb,
+            previous_rows: Vec::new(),
+        }
+    }
+
+    pub fn row_count(&self, emulator: &Emulator) -> usize
+    {
+        match self.mode {
+            TtyRenderMode::HalfBlocks => emulator.screen_height() / 2,
+            TtyRenderMode::Braille => emulator.screen_height() / 4,
+        }
+    }
+
+    fn render_half_block_row(&self, emulator: &Emulator, row: usize) -> String
+    {
+        let mut text = String::new();
+        let mut current_colors = None;
+
+        for x in 0..emulator.screen_width() {
+            let top = emulator.read_screen_pixel_color_index(x, 2 * row);
+            let bottom = emulator.read_screen_pixel_color_index(x, 2 * row + 1);
+
+            // Colors are only set when they change along the row
+            if current_colors != Some((top, bottom)) {
+                text += &foreground(self.palette_rgb[top as usize]);
+                text += &background(self.palette_rgb[bottom as usize]);
+                current_colors = Some((top, bottom));
+            }
+
+            text.push('▀');
+        }
+
+        text
+    }
+
+    fn render_braille_row(&self, emulator: &Emulator, row: usize) -> String
+    {
+        // Bit of each dot, by column then line within the character
+        const DOT_BITS: [[u32; 4]; 2] = [[0x01, 0x02, 0x04, 0x40], [0x08, 0x10, 0x20, 0x80]];
+
+        let mut text = background(self.palette_rgb[0]);
+        let mut current_color = None;
+
+        for column in 0..emulator.screen_width() / 2 {
+            let mut dots = 0;
+            let mut color_counts = [0; 4];
+
+            for (dx, column_bits) in DOT_BITS.iter().enumerate() {
+                for (dy, bit) in column_bits.iter().enumerate() {
+                    let color_index = emulator.read_screen_pixel_color_index(2 * column + dx, 4 * row + dy);
+
+                    if color_index != 0 {
+                        dots |= bit;
+                        color_counts[color_index as usize] += 1;
+                    }
+                }
+            }
+
+            // The most common lit color wins
+            let color_index = (1..4).rev().max_by_key(|&color_index| color_counts[color_index]).unwrap_or(1);
+
+            if dots != 0 && current_color != Some(color_index) {
+                text += &foreground(self.palette_rgb[color_index]);
+                current_color = Some(color_index);
+            }
+
+            text.push(std::char::from_u32(0x2800 + dots).unwrap());
+        }
+
+        text
+    }
+
+    // Returns the escape sequences updating the terminal from the last rendered frame.
+    pub fn render(&mut self, emulator: &Emulator) -> String
+    {
+        let rows: Vec<String> = (0..self.row_count(emulator)).map(|row| {
+            let text = match self.mode {
+                TtyRenderMode::HalfBlocks => self.render_half_block_row(emulator, row),
+                TtyRenderMode::Braille => self.render_braille_row(emulator, row),
+            };
+
+            text + "\x1b[0m"
+        }).collect();
+
+        let mut output = String::new();
+
+        // Everything moves when the resolution changes
+        if rows.len() != self.previous_rows.len() {
+            output += "\x1b[2J";
+            self.previous_rows.clear();
+        }
+
+        for (row, text) in rows.iter().enumerate() {
+            if self.previous_rows.get(row) != Some(text) {
+                output += &format!("\x1b[{};1H{}", row + 1, text);
+            }
+        }
+
+        self.previous_rows = rows;
+
+        output
+    }
+}
+
+#[derive(Clone, Copy)]
+struct KeyPress
+{
+    time_ms: u32,
+    is_repeat: bool, // Came while the key was still held, so the terminal is auto-repeating it
+}
+
+// Key state out of a stream of key presses.
+#[derive(Default)]
+pub struct TtyKeyboard
+{
+    last_press: [Option<KeyPress>; 16], // By CHIP-8 key
+    last_rewind: Option<KeyPress>,
+    is_quit_requested: bool,
+}
+
+impl TtyKeyboard
+{
+    fn press(last_press: &mut Option<KeyPress>, time_ms: u32)
+    {
+        let is_repeat = Self::is_held(*last_press, time_ms);
+
+        *last_press = Some(KeyPress { time_ms, is_repeat });
+    }
+
+    pub fn feed(&mut self, byte: u8, time_ms: u32)
+    {
+        let character = byte.to_ascii_lowercase();
+
+        if let Some((key, _)) = KEY_CHARACTERS.iter().find(|(_, key_character)| *key_character == character) {
+            Self::press(&mut self.last_press[*key as usize], time_ms);
+        }
+
+        match byte {
+            BYTE_CTRL_C => self.is_quit_requested = true,
+            BYTE_BACKSPACE => Self::press(&mut self.last_rewind, time_ms),
+            _ => {},
+        }
+    }
+
+    // Escape also starts the sequences of the arrow and function keys, it only quits on its own.
+    pub fn feed_all(&mut self, bytes: &[u8], time_ms: u32)
+    {
+        if bytes == [BYTE_ESCAPE] {
+            self.is_quit_requested = true;
+        } else if bytes.first() != Some(&BYTE_ESCAPE) {
+            for byte in bytes {
+                self.feed(*byte, time_ms);
+            }
+        }
+    }
+
+    fn is_held(last_press: Option<KeyPress>, time_ms: u32) -> bool
+    {
+        last_press.is_some_and(|press| {
+            let timeout_ms = if press.is_repeat { KEY_REPEAT_RELEASE_TIMEOUT_MS } else { KEY_INITIAL_RELEASE_TIMEOUT_MS };
+
+            time_ms.wrapping_sub(press.time_ms) < timeout_ms
+        })
+    }
+
+    pub fn input(&self, time_ms: u32) -> HostInput
+    {
+        let key_state = self.last_press.iter().enumerate()
+            .filter(|(_, last_press)| Self::is_held(**last_press, time_ms))
+            .fold(0, |key_state, (key, _)| key_state | 1 << key);
+
+        HostInput {
+            key_state,
+            is_rewinding: Self::is_held(self.last_rewind, time_ms),
+            actions: Vec::new(),
+        }
+    }
+
+    pub fn is_quit_requested(&self) -> bool
+    {
+        self.is_quit_requested
+    }
+}
+
+// Draws in the terminal and reads raw keys from it.
+// Save states and the debugger need the SDL frontend.
+pub struct TtyHost
+{
+    renderer: TtyRenderer,
+    keyboard: TtyKeyboard,
+    input: Receiver<Vec<u8>>,
+    start_time: Instant,
+    next_frame_ms: u32,
+    saved_terminal_mode: Option<String>,
+    was_sound_playing: bool,
+    status_row: usize,
+}
+
+fn run_stty(arguments: &[&str]) -> Option<String>
+{
+    Command::new("stty").args(arguments).output().ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|text| text.trim().to_string())
+}
+
+impl TtyHost
+{
+    // Switches the terminal to raw mode until the host is dropped.
+    pub fn new(mode: TtyRenderMode, palette: &Palette) -> Result<TtyHost, String>
+    {
+        let saved_terminal_mode = run_stty(&["-g"]);
+
+        if saved_terminal_mode.is_none() || run_stty(&["raw", "-echo"]).is_none() {
+            return Err("unable to switch the terminal to raw mode".to_string());
+        }
+
+        // Reads block, they are handed over to the loop as they come
+        let (sender, receiver) = mpsc::channel();
+
+        thread::spawn(move || {
+            let mut buffer = [0; 64];
+
+            while let Ok(size) = io::stdin().read(&mut buffer) {
+                if size == 0 || sender.send(buffer[..size].to_vec()).is_err() {
+                    break;
+                }
+            }
+        });
+
+        // Hide the cursor
+        print!("\x1b[?25l\x1b[2J");
+
+        Ok(TtyHost {
+            renderer: TtyRenderer::new(mode, palette),
+            keyboard: TtyKeyboard::default(),
+            input: receiver,
+            start_time: Instant::now(),
+            next_frame_ms: 0,
+            saved_terminal_mode,
+            was_sound_playing: false,
+            status_row: 0,
+        })
+    }
+}
+
+impl Drop for TtyHost
+{
+    fn drop(&mut self)
+    {
+        print!("\x1b[0m\x1b[?25h\x1b[{};1H\r\n", self.status_row + 2);
+        io::stdout().flush().ok();
+
+        if let Some(mode) = &self.saved_terminal_mode {
+            run_stty(&[mode]);
+        }
+    }
+}
+
+impl Host for TtyHost
+{
+    fn poll_input(&mut self) -> HostInput
+    {
+        let time_ms = self.ticks_ms();
+
+        while let Ok(bytes) = self.input.try_recv() {
+            self.keyboard.feed_all(&bytes, time_ms);
+        }
+
+        self.keyboard.input(time_ms)
+    }
+
+    fn is_quit_requested(&self) -> bool
+    {
+        self.keyboard.is_quit_requested()
+    }
+
+    fn ticks_ms(&mut self) -> u32
+    {
+        self.start_time.elapsed().as_millis() as u32
+    }
+
+    // Terminals have no vsync, frames are paced at 60 Hz instead.
+    fn present_screen(&mut self, emulator: &Emulator) -> Result<(), String>
+    {
+        let output = self.renderer.render(emulator);
+
+        self.status_row = self.renderer.row_count(emulator);
+
+        let mut stdout = io::stdout();
+        stdout.write_all(output.as_bytes()).and_then(|_| stdout.flush()).map_err(|error| error.to_string())?;
+
+        let time_ms = self.ticks_ms();
+
+        self.next_frame_ms = self.next_frame_ms.max(time_ms.saturating_sub(FRAME_PERIOD_MS)) + FRAME_PERIOD_MS;
+
+        if self.next_frame_ms > time_ms {
+            thread::sleep(Duration::from_millis(u64::from(self.next_frame_ms - time_ms)));
+        }
+
+        Ok(())
+    }
+
+    // The terminal bell rings once per beep.
+    fn play_sound(&mut self, is_playing: bool)
+    {
+        if is_playing && !self.was_sound_playing {
+            print!("\x07");
+        }
+
+        self.was_sound_playing = is_playing;
+    }
+
+    fn show_status(&mut self, status: Option<&str>) -> Result<(), String>
+    {
+        print!("\x1b[{};1H\x1b[0m\x1b[2K{}", self.status_row + 1, status.unwrap_or(""));
+
+        io::stdout().flush().map_err(|error| error.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::{
+        config::{Color, EmuConfig},
+        cpu,
+    };
+
+    #[test]
+    fn tty() {
+        let palette = Palette {
+            primary: Color { r: 1.0, g: 1.0, b: 1.0 },
+            secondary: Color { r: 0.0, g: 0.0, b: 0.0 },
+            ..Palette::default()
+        };
+
+        let mut emulator = Emulator::with_config(&EmuConfig::default());
+        let mut renderer = TtyRenderer::new(TtyRenderMode::HalfBlocks, &palette);
+
+        // LD V0, 0 / LD F, V0 / DRW V0, V0, 5: the '0' glyph in the top left corner
+        emulator.load_program(vec![0x60, 0x00, 0xF0, 0x29, 0xD0, 0x05]).unwrap();
+
+        let output = renderer.render(&emulator);
+
+        // First frame clears and draws every row
+        assert!(output.starts_with("\x1b[2J\x1b[1;1H"));
+        assert_eq!(output.matches("\x1b[").count(), 1 + 16 * 4);
+        assert_eq!(renderer.render(&emulator), "");
+
+        emulator.execute_step(3 * cpu::INSTRUCTION_EXECUTION_PERIOD_MS).unwrap();
+
+        // Only the rows of the glyph are drawn again, the first one holds F0 over 90
+        let output = renderer.render(&emulator);
+        let white = "\x1b[38;2;255;255;255m";
+        let black = "\x1b[38;2;0;0;0m";
+
+        assert!(output.starts_with(&format!("\x1b[1;1H{}\x1b[48;2;255;255;255m▀{}\x1b[48;2;0;0;0m▀▀", white, white)));
+        assert_eq!(output.matches(";1H").count(), 3);
+        assert!(output.contains(&format!("\x1b[3;1H{}\x1b[48;2;0;0;0m▀▀▀▀{}", white, black)));
+
+        // SUBCASE: braille
+        {
+            let mut renderer = TtyRenderer::new(TtyRenderMode::Braille, &palette);
+            let output = renderer.render(&emulator);
+
+            // Top left 4x4 pixels: F0 90 90 90
+            assert!(output.contains(&format!("\x1b[1;1H\x1b[48;2;0;0;0m{}\u{284F}\u{28B9}\u{2800}", white)));
+            assert_eq!(output.matches(";1H").count(), 8);
+        }
+
+        // SUBCASE: keyboard
+        {
+            let mut keyboard = TtyKeyboard::default();
+
+            keyboard.feed_all(b"wX", 1000);
+
+            assert_eq!(keyboard.input(1000).key_state, 1 << 0x5 | 1 << 0x0);
+
+            // The key stays held across the initial repeat delay
+            assert_eq!(keyboard.input(1500).key_state, 1 << 0x5 | 1 << 0x0);
+
+            // Auto-repeat keeps it held, and releases show up sooner once it repeats
+            keyboard.feed_all(b"w", 1500);
+            keyboard.feed_all(b"w", 1530);
+
+            assert_eq!(keyboard.input(1600).key_state, 1 << 0x5 | 1 << 0x0);
+            assert_eq!(keyboard.input(1700).key_state, 1 << 0x0);
+            assert_eq!(keyboard.input(1750).key_state, 0);
+
+            // Arrow keys don't quit
+            keyboard.feed_all(b"\x1b[A", 1800);
+
+            assert!(!keyboard.is_quit_requested());
+            assert_eq!(keyboard.input(1800).key_state, 0);
+
+            keyboard.feed_all(&[BYTE_BACKSPACE], 1800);
+
+            assert!(keyboard.input(1900).is_rewinding);
+
+            keyboard.feed_all(&[BYTE_ESCAPE], 2000);
+
+            assert!(keyboard.is_quit_requested());
+        }
+    }
+}
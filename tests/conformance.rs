@@ -0,0 +1,263 @@
+// Runs the test ROMs under every quirks profile and compares their final screen to the expected one.
+// The bundled ROMs are the assembly sources of tests/conformance. They document the results of every profile
+// in '; expect <profile>: <results>' lines, one '1' or '0' per check, and the expected screen is drawn from those.
+// Binary suites such as Timendus' tests, BestCoder's opcode test or corax+ aren't bundled. They run when
+// CHIP8_CONFORMANCE_ROMS names a directory of '.ch8' files, against the goldens of its 'golden' subdirectory.
+// A missing golden fails the test, running with CHIP8_BLESS=1 records the current results instead. Recorded goldens
+// only catch changes, each one has to be checked against the documented pass screen of its suite.
+// Nothing here needs the window frontend, 'cargo test --no-default-features' runs it without libSDL2.
+use chip8emu::{
+    chip8::{
+        assembler,
+        EmuConfig,
+        Platform,
+        Quirks,
+        QuirksProfile,
+        QUIRKS_PROFILE_NAMES,
+    },
+    headless,
+    Emulator,
+};
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    env,
+    fs,
+    path::{Path, PathBuf},
+};
+
+// Long enough for the display wait quirk, which costs a frame per result drawn
+const FRAME_COUNT: u32 = 180;
+
+// Results are drawn on a grid of 8 by 6 pixel cells, 8 per row
+const CELL_WIDTH: usize = 8;
+const CELL_HEIGHT: usize = 6;
+const CELLS_PER_ROW: usize = 8;
+
+const SCREEN_WIDTH: usize = 64;
+const SCREEN_HEIGHT: usize = 32;
+
+// Font glyphs of the results, '0' for a failed check and '1' for a passed one
+const GLYPH_ZERO: [u8; 5] = [0xF0, 0x90, 0x90, 0x90, 0xF0];
+const GLYPH_ONE: [u8; 5] = [0x20, 0x60, 0x20, 0x20, 0x70];
+
+enum Expected
+{
+    Documented(BTreeMap<String, String>), // Results by profile name
+    Golden(PathBuf), // Directory of the recorded screens
+}
+
+struct TestRom
+{
+    name: String,
+    program: Result<Vec<u8>, String>,
+    expected: Expected,
+}
+
+fn profile_platform(profile: QuirksProfile) -> Platform
+{
+    match profile {
+        QuirksProfile::CosmacVip | QuirksProfile::Chip48 => Platform::Chip8,
+        QuirksProfile::SuperChip => Platform::SuperChip,
+        QuirksProfile::XoChip => Platform::XoChip,
+    }
+}
+
+fn files_with_extension(directory: &Path, extension: &str) -> Vec<PathBuf>
+{
+    let mut paths: Vec<PathBuf> = fs::read_dir(directory)
+        .unwrap_or_else(|error| panic!("unable to list '{}': {}", directory.display(), error))
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|path_extension| path_extension == extension))
+        .collect();
+
+    paths.sort();
+    paths
+}
+
+fn file_stem(path: &Path) -> String
+{
+    path.file_stem().unwrap().to_string_lossy().into_owned()
+}
+
+// '; expect <profile>: <results>' lines
+fn documented_results(source: &str) -> BTreeMap<String, String>
+{
+    source.lines()
+        .filter_map(|line| line.strip_prefix("; expect "))
+        .filter_map(|line| line.split_once(':'))
+        .map(|(profile_name, results)| (profile_name.trim().to_string(), results.trim().to_string()))
+        .collect()
+}
+
+fn load_roms() -> Vec<TestRom>
+{
+    let bundled_directory = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("conformance");
+
+    let mut roms: Vec<TestRom> = files_with_extension(&bundled_directory, "asm").iter()
+        .map(|path| {
+            let source = fs::read_to_string(path).unwrap_or_else(|error| panic!("unable to read '{}': {}", path.display(), error));
+
+            TestRom {
+                name: file_stem(path),
+                program: assembler::assemble(&source).map_err(|error| error.to_string()),
+                expected: Expected::Documented(documented_results(&source)),
+            }
+        })
+        .collect();
+
+    if let Some(directory) = env::var_os("CHIP8_CONFORMANCE_ROMS").map(PathBuf::from) {
+        roms.extend(files_with_extension(&directory, "ch8").iter().map(|path| TestRom {
+            name: file_stem(path),
+            program: fs::read(path).map_err(|error| error.to_string()),
+            expected: Expected::Golden(directory.join("golden")),
+        }));
+    }
+
+    roms
+}
+
+// What run_rom returns when every result is drawn as documented
+fn draw_results(results: &str) -> String
+{
+    let mut screen = vec![vec!['.'; SCREEN_WIDTH]; SCREEN_HEIGHT];
+
+    for (index, result) in results.chars().enumerate() {
+        let glyph = if result == '1' { GLYPH_ONE } else { GLYPH_ZERO };
+        let (left, top) = (index % CELLS_PER_ROW * CELL_WIDTH, index / CELLS_PER_ROW * CELL_HEIGHT);
+
+        for (y, row) in glyph.iter().enumerate() {
+            for x in 0..8 {
+                if row & (0x80 >> x) != 0 {
+                    screen[top + y][left + x] = '#';
+                }
+            }
+        }
+    }
+
+    let lines: Vec<String> = screen.iter().map(|line| line.iter().collect()).collect();
+
+    format!("status: ok\n{}\n", lines.join("\n"))
+}
+
+// Status line followed by the screen
+fn run_rom(program: &[u8], profile: QuirksProfile) -> String
+{
+    let config = EmuConfig {
+        platform: profile_platform(profile),
+        quirks: Quirks::from_profile(profile),
+        ..EmuConfig::default()
+    };
+
+    let mut emulator = Emulator::with_config(&config);
+
+    emulator.seed_rng(0);
+
+    if let Err(fault) = emulator.load_program(program.to_vec()) {
+        return format!("status: unable to load: {}\n", fault);
+    }
+
+    let report = headless::run(&mut emulator, &headless::HeadlessConfig {
+        limit: headless::RunLimit::Frames(FRAME_COUNT),
+        key_script: Vec::new(),
+    });
+
+    let status = report.fault.map_or("ok".to_string(), |error| error.to_string());
+    let mut result = format!("status: {}\n", status).into_bytes();
+
+    headless::write_screen(&emulator, &mut result).unwrap();
+
+    String::from_utf8(result).unwrap()
+}
+
+// Names the result cells that differ, which are the failing checks of the bundled ROMs.
+fn compare_results(expected: &str, actual: &str) -> Vec<String>
+{
+    let mut expected_lines = expected.lines();
+    let mut actual_lines = actual.lines();
+    let mut differences = Vec::new();
+
+    let (expected_status, actual_status) = (expected_lines.next(), actual_lines.next());
+
+    if expected_status != actual_status {
+        differences.push(format!("expected '{}', got '{}'", expected_status.unwrap_or(""), actual_status.unwrap_or("")));
+    }
+
+    let expected_screen: Vec<&str> = expected_lines.collect();
+    let actual_screen: Vec<&str> = actual_lines.collect();
+
+    if expected_screen.len() != actual_screen.len() || expected_screen.first().map(|line| line.len()) != actual_screen.first().map(|line| line.len()) {
+        differences.push("screen size differs".to_string());
+        return differences;
+    }
+
+    let mut cells = BTreeSet::new();
+
+    for (y, (expected_line, actual_line)) in expected_screen.iter().zip(&actual_screen).enumerate() {
+        for (x, (expected_pixel, actual_pixel)) in expected_line.chars().zip(actual_line.chars()).enumerate() {
+            if expected_pixel != actual_pixel {
+                cells.insert((y / CELL_HEIGHT, x / CELL_WIDTH));
+            }
+        }
+    }
+
+    differences.extend(cells.iter().map(|(row, column)| {
+        format!("cell at row {}, column {} differs (check {})", row, column, row * CELLS_PER_ROW + column + 1)
+    }));
+
+    differences
+}
+
+#[test]
+fn conformance() {
+    let is_blessing = env::var_os("CHIP8_BLESS").is_some();
+    let mut failures = Vec::new();
+
+    for rom in load_roms() {
+        let program = match &rom.program {
+            Ok(program) => program,
+            Err(error) => {
+                failures.push(format!("{}: {}", rom.name, error));
+                continue;
+            },
+        };
+
+        for profile_name in QUIRKS_PROFILE_NAMES.iter() {
+            let profile = QuirksProfile::from_name(profile_name).unwrap();
+            let result = run_rom(program, profile);
+
+            let expected = match &rom.expected {
+                Expected::Documented(results) => match results.get(*profile_name) {
+                    Some(results) => draw_results(results),
+                    None => {
+                        failures.push(format!("{} ({}): no '; expect {}: <results>' line", rom.name, profile_name, profile_name));
+                        continue;
+                    },
+                },
+                Expected::Golden(golden_directory) => {
+                    let golden_path = golden_directory.join(format!("{}.{}.txt", rom.name, profile_name));
+
+                    if is_blessing {
+                        fs::create_dir_all(golden_directory).unwrap();
+                        fs::write(&golden_path, &result).unwrap();
+                        continue;
+                    }
+
+                    match fs::read_to_string(&golden_path) {
+                        Ok(expected) => expected,
+                        Err(_) => {
+                            failures.push(format!("{} ({}): no golden at '{}', run with CHIP8_BLESS=1 to record it",
+                                rom.name, profile_name, golden_path.display()));
+                            continue;
+                        },
+                    }
+                },
+            };
+
+            failures.extend(compare_results(&expected, &result).iter()
+                .map(|difference| format!("{} ({}): {}", rom.name, profile_name, difference)));
+        }
+    }
+
+    assert!(failures.is_empty(), "conformance failures:\n{}", failures.join("\n"));
+}
@@ -0,0 +1,347 @@
+; Opcode checks that hold under every quirks profile, in the spirit of corax+.
+; Each check draws '1' when it passes and '0' when it fails, 8 checks per row:
+;  1 LD Vx, byte        2 ADD Vx, byte       3 ADD Vx, byte wraps  4 LD Vx, Vy
+;  5 OR                 6 AND                7 XOR                 8 ADD Vx, Vy
+;  9 ADD Vx, Vy carry  10 ADD Vx, Vy no carry 11 SUB               12 SUB borrow
+; 13 SUBN              14 SE Vx, Vy          15 SNE Vx, Vy         16 SE Vx, byte
+; 17 CALL and RET      18 JP                 19 LD [I] and LD Vx, [I]  20 LD B hundreds
+; 21 LD B tens         22 LD B units         23 ADD I, Vx          24 LD DT and LD Vx, DT
+; 25 LD F              26 DRW no collision   27 DRW collision      28 RND with mask 0
+; 29 SKP released      30 SKNP released      31 ADD Vx, Vy adding 0  32 SUB equal
+; 33 SUBN equal       34 JP to itself
+; Expected results, every check passes whatever the profile:
+; expect vip: 1111111111111111111111111111111111
+; expect chip48: 1111111111111111111111111111111111
+; expect schip: 1111111111111111111111111111111111
+; expect xochip: 1111111111111111111111111111111111
+
+    ; V8 and V9 are the position of the next result
+    LD V8, 0
+    LD V9, 0
+
+    ; 1
+    LD V0, 0x2A
+    LD VA, 0
+    SNE V0, 0x2A
+    LD VA, 1
+    CALL report
+
+    ; 2
+    ADD V0, 0x10
+    LD VA, 0
+    SNE V0, 0x3A
+    LD VA, 1
+    CALL report
+
+    ; 3
+    LD V0, 0xFF
+    ADD V0, 2
+    LD VA, 0
+    SNE V0, 1
+    LD VA, 1
+    CALL report
+
+    ; 4
+    LD V1, 7
+    LD V0, V1
+    LD VA, 0
+    SNE V0, 7
+    LD VA, 1
+    CALL report
+
+    ; 5
+    LD V0, 0x0F
+    LD V1, 0xF0
+    OR V0, V1
+    LD VA, 0
+    SNE V0, 0xFF
+    LD VA, 1
+    CALL report
+
+    ; 6
+    LD V0, 0x3C
+    LD V1, 0x0F
+    AND V0, V1
+    LD VA, 0
+    SNE V0, 0x0C
+    LD VA, 1
+    CALL report
+
+    ; 7
+    LD V0, 0x3C
+    XOR V0, V1
+    LD VA, 0
+    SNE V0, 0x33
+    LD VA, 1
+    CALL report
+
+    ; 8 and 9
+    LD V0, 0xF0
+    LD V1, 0x20
+    ADD V0, V1
+    LD VA, 0
+    SNE V0, 0x10
+    LD VA, 1
+    LD VB, VF
+    CALL report
+    LD VA, 0
+    SNE VB, 1
+    LD VA, 1
+    CALL report
+
+    ; 10
+    ADD V0, V1
+    LD VA, 0
+    SNE VF, 0
+    LD VA, 1
+    CALL report
+
+    ; 11
+    LD V0, 0x30
+    LD V1, 0x10
+    SUB V0, V1
+    LD VB, VF
+    LD VA, 0
+    SNE V0, 0x20
+    LD VA, 1
+    SNE VB, 0
+    LD VA, 0
+    CALL report
+
+    ; 12
+    LD V0, 0x10
+    LD V1, 0x30
+    SUB V0, V1
+    LD VB, VF
+    LD VA, 0
+    SNE V0, 0xE0
+    LD VA, 1
+    SNE VB, 1
+    LD VA, 0
+    CALL report
+
+    ; 13
+    LD V0, 0x10
+    SUBN V0, V1
+    LD VB, VF
+    LD VA, 0
+    SNE V0, 0x20
+    LD VA, 1
+    SNE VB, 0
+    LD VA, 0
+    CALL report
+
+    ; 14
+    LD V0, 5
+    LD V1, 5
+    LD VA, 1
+    SE V0, V1
+    LD VA, 0
+    CALL report
+
+    ; 15
+    LD V1, 6
+    LD VA, 1
+    SNE V0, V1
+    LD VA, 0
+    CALL report
+
+    ; 16
+    LD VA, 0
+    SE V0, 6
+    LD VA, 1
+    CALL report
+
+    ; 17
+    LD V0, 0
+    CALL set_v0
+    LD VA, 0
+    SNE V0, 0x77
+    LD VA, 1
+    CALL report
+
+    ; 18
+    LD VA, 1
+    JP jumped
+    LD VA, 0
+jumped:
+    CALL report
+
+    ; 19
+    LD I, scratch
+    LD V0, 0x5A
+    LD [I], V0
+    LD I, scratch
+    LD V0, 0
+    LD V0, [I]
+    LD VA, 0
+    SNE V0, 0x5A
+    LD VA, 1
+    CALL report
+
+    ; 20 to 22
+    LD V0, 234
+    LD I, scratch
+    LD B, V0
+    LD I, scratch
+    LD V2, [I]
+    LD V3, V1
+    LD V4, V2
+    LD VA, 0
+    SNE V0, 2
+    LD VA, 1
+    CALL report
+    LD VA, 0
+    SNE V3, 3
+    LD VA, 1
+    CALL report
+    LD VA, 0
+    SNE V4, 4
+    LD VA, 1
+    CALL report
+
+    ; 23
+    LD I, scratch
+    LD V0, 3
+    ADD I, V0
+    LD V0, 0x99
+    LD [I], V0
+    LD I, scratch + 3
+    LD V0, 0
+    LD V0, [I]
+    LD VA, 0
+    SNE V0, 0x99
+    LD VA, 1
+    CALL report
+
+    ; 24
+    LD V0, 10
+    LD DT, V0
+    LD V1, DT
+    LD VA, 1
+    SNE V1, 0
+    LD VA, 0
+    CALL report
+
+    ; 25
+    LD V0, 0xA
+    LD F, V0
+    LD V0, [I]
+    LD VA, 0
+    SNE V0, 0xF0
+    LD VA, 1
+    CALL report
+
+    ; 26 and 27, drawn twice at the bottom right, leaving nothing behind
+    LD V0, 56
+    LD V1, 30
+    LD I, line
+    DRW V0, V1, 1
+    LD VB, VF
+    DRW V0, V1, 1
+    LD VC, VF
+    LD VA, 0
+    SNE VB, 0
+    LD VA, 1
+    CALL report
+    LD VA, 0
+    SNE VC, 1
+    LD VA, 1
+    CALL report
+
+    ; 28
+    RND V0, 0
+    LD VA, 0
+    SNE V0, 0
+    LD VA, 1
+    CALL report
+
+    ; 29
+    LD V0, 5
+    LD VA, 0
+    SKP V0
+    LD VA, 1
+    CALL report
+
+    ; 30
+    LD VA, 1
+    SKNP V0
+    LD VA, 0
+    CALL report
+
+    ; 31, no carry out of adding 0
+    LD V0, 0x10
+    LD V1, 0
+    ADD V0, V1
+    LD VB, VF
+    LD VA, 0
+    SNE V0, 0x10
+    LD VA, 1
+    SNE VB, 1
+    LD VA, 0
+    CALL report
+
+    ; 32, no borrow out of equal values
+    LD V0, 0x20
+    LD V1, 0x20
+    SUB V0, V1
+    LD VB, VF
+    LD VA, 0
+    SNE V0, 0
+    LD VA, 1
+    SNE VB, 0
+    LD VA, 0
+    CALL report
+
+    ; 33
+    LD V0, 0x20
+    SUBN V0, V1
+    LD VB, VF
+    LD VA, 0
+    SNE V0, 0
+    LD VA, 1
+    SNE VB, 0
+    LD VA, 0
+    CALL report
+
+    ; 34, drawn as passed up front, a JP that moves past itself runs into the code below which turns it into a '0'
+    LD VC, V8
+    LD VD, V9
+    LD VA, 1
+    CALL report
+end:
+    JP end
+
+    LD F, VA
+    DRW VC, VD, 5
+    LD VA, 0
+    LD F, VA
+    DRW VC, VD, 5
+failed:
+    JP failed_loop
+failed_loop:
+    JP failed
+
+set_v0:
+    LD V0, 0x77
+    RET
+
+; Draws '1' if VA is 1 and '0' otherwise, then moves to the next position
+report:
+    LD F, VA
+    DRW V8, V9, 5
+    ADD V8, 8
+    SE V8, 64
+    RET
+    LD V8, 0
+    ADD V9, 6
+    RET
+
+line:
+    sprite ########
+
+scratch:
+    db 0, 0, 0, 0, 0, 0, 0, 0
+
+; CHIP-8 programs are made of whole instructions, their size has to be even
+    db 0
@@ -0,0 +1,119 @@
+; Quirk probes in the spirit of the Timendus quirks test, the result depends on the profile.
+; Each probe draws '1' when the quirk is active and '0' otherwise:
+;  1 SHR shifts Vy     2 LD [I] leaves I past the registers   3 LD [I] moves I by x only
+;  4 JP V0 uses Vx     5 logic resets VF                      6 DRW wraps instead of clipping
+;  7 DRW waits for the display
+; Expected results, as documented for each interpreter by the Timendus quirks test:
+; expect vip: 1100101
+; expect chip48: 0011000
+; expect schip: 0001000
+; expect xochip: 1100010
+
+    JP main
+
+; Kept at the start so that its address has 2 as the second nibble, JP V0 then reads V2 with the quirk
+jump_table:
+    LD VA, 0
+    JP jumped
+    LD VA, 1
+jumped:
+    CALL report
+    JP after_jump
+
+main:
+    ; V8 and V9 are the position of the next result
+    LD V8, 0
+    LD V9, 0
+
+    ; 1
+    LD V0, 0x01
+    LD V1, 0x04
+    SHR V0, V1
+    LD VA, 0
+    SNE V0, 0x02
+    LD VA, 1
+    CALL report
+
+    ; 2 and 3, the second store lands on the first byte only if I did not move
+    LD I, scratch
+    LD V0, 0xAA
+    LD V1, 0xBB
+    LD [I], V1
+    LD V0, 0xCC
+    LD [I], V0
+    LD I, scratch
+    LD V2, [I]
+    LD VB, V1
+    LD VA, 0
+    SNE V2, 0xCC
+    LD VA, 1
+    CALL report
+    LD VA, 0
+    SNE VB, 0xCC
+    LD VA, 1
+    CALL report
+
+    ; 4
+    LD V0, 0
+    LD V2, 4
+    JP V0, jump_table
+after_jump:
+
+    ; 5
+    LD VF, 5
+    LD V0, 1
+    OR V0, V0
+    LD VA, 0
+    SNE VF, 0
+    LD VA, 1
+    CALL report
+
+    ; 6, the part past the right edge collides with a sprite at the left edge if it wraps
+    LD V0, 62
+    LD V1, 28
+    LD V2, 0
+    LD I, line
+    DRW V0, V1, 1
+    DRW V2, V1, 1
+    LD VA, VF
+    DRW V2, V1, 1
+    DRW V0, V1, 1
+    CALL report
+
+    ; 7, counts the draws made within 10 frames, one per frame when waiting and about 16 otherwise
+    LD V0, 10
+    LD DT, V0
+    LD V5, 0
+    LD I, blank
+wait:
+    DRW V0, V1, 1
+    ADD V5, 1
+    LD V4, DT
+    SE V4, 0
+    JP wait
+    LD V0, 13
+    SUB V0, V5
+    LD VA, VF
+    CALL report
+
+end:
+    JP end
+
+; Draws '1' if VA is 1 and '0' otherwise, then moves to the next position
+report:
+    LD F, VA
+    DRW V8, V9, 5
+    ADD V8, 8
+    SE V8, 64
+    RET
+    LD V8, 0
+    ADD V9, 6
+    RET
+
+line:
+    sprite ########
+blank:
+    sprite ........
+
+scratch:
+    db 0, 0, 0, 0, 0, 0, 0, 0